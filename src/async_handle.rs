@@ -0,0 +1,338 @@
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::handle::{self, Callback};
+use crate::{
+    uv_async_init, uv_async_send, uv_async_t, uv_errno_t_UV_ECANCELED, uv_handle_t, uv_is_active,
+};
+use std::hint;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Set in Shared::state once the handle has started closing.
+const CLOSED: usize = 1 << (usize::BITS - 1);
+
+/// The state shared between an AsyncHandle and its senders.
+struct Shared<T> {
+    handle: *mut uv_async_t,
+
+    /// The high bit is CLOSED; the remaining bits count the senders that are in the middle of
+    /// calling uv_async_send. The handle can't be closed until that count drops to zero.
+    state: AtomicUsize,
+
+    queue: Queue<T>,
+}
+
+// The handle pointer is only used for uv_async_send (which is thread-safe) while the handle is
+// guaranteed to be open.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn send(&self, payload: Option<T>) -> Result<()> {
+        let prev = self.state.fetch_add(1, Ordering::Acquire);
+        if prev & CLOSED != 0 {
+            self.state.fetch_sub(1, Ordering::Release);
+            return Err(UvError::new("uv_async_send", uv_errno_t_UV_ECANCELED));
+        }
+
+        if let Some(payload) = payload {
+            self.queue.push(payload);
+        }
+        let ret = unsafe { uvret!(uv_async_send(self.handle)) };
+        self.state.fetch_sub(1, Ordering::Release);
+        ret?;
+        Ok(())
+    }
+
+    /// Mark the handle closed and wait for any in-flight senders to finish.
+    fn close(&self) {
+        self.state.fetch_or(CLOSED, Ordering::AcqRel);
+        while self.state.load(Ordering::Acquire) & !CLOSED != 0 {
+            hint::spin_loop();
+        }
+    }
+}
+
+struct Node<T> {
+    value: T,
+    next: *mut Node<T>,
+}
+
+/// A lock-free, multi-producer, single-consumer queue. Producers push onto a stack; the consumer
+/// takes the entire stack at once and reverses it to restore the order the values were pushed.
+struct Queue<T> {
+    head: AtomicPtr<Node<T>>,
+}
+
+impl<T> Queue<T> {
+    fn new() -> Queue<T> {
+        Queue {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value,
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn take_all(&self) -> Messages<T> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut reversed = ptr::null_mut();
+        while !node.is_null() {
+            unsafe {
+                let next = (*node).next;
+                (*node).next = reversed;
+                reversed = node;
+                node = next;
+            }
+        }
+        Messages { head: reversed }
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        self.take_all();
+    }
+}
+
+/// The payloads that were sent since the last time an AsyncHandle's callback was called, in the
+/// order they were sent. Any payloads that aren't consumed are dropped along with the iterator.
+pub struct Messages<T> {
+    head: *mut Node<T>,
+}
+
+impl<T> Iterator for Messages<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.head.is_null() {
+            return None;
+        }
+        let node = unsafe { Box::from_raw(self.head) };
+        self.head = node.next;
+        Some(node.value)
+    }
+}
+
+impl<T> Drop for Messages<T> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
+type AsyncCallback<T> = dyn FnMut(&AsyncHandle<T>, Messages<T>);
+
+struct AsyncState<T> {
+    shared: Arc<Shared<T>>,
+    cb: Callback<AsyncCallback<T>>,
+}
+
+/// A safe wrapper around uv_async_t, which allows other threads to wake up the loop.
+///
+/// The AsyncHandle itself belongs to the loop's thread; other threads wake it with an
+/// AsyncSender, which may be cloned and sent to any thread. Senders may either simply wake the
+/// loop or also send a payload, which is passed through a lock-free queue to the callback.
+///
+/// libuv coalesces wake ups: if several sends happen before the loop gets a chance to run the
+/// callback, the callback is only called once. The only guarantee is that the callback will be
+/// called at least once _after_ any given send. So, the callback receives every payload that has
+/// been sent since it was last called, and it may occasionally receive none at all (if an earlier
+/// call already received them).
+///
+/// The handle is closed when the AsyncHandle is dropped, never when a sender is dropped, so the
+/// handle is always closed from the loop's thread. Once the handle is closed, sends return an
+/// error (UV_ECANCELED). Keep in mind that an active AsyncHandle keeps the loop alive.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::async_handle::AsyncHandle;
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::uv_run_mode_UV_RUN_NOWAIT;
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// # use std::thread;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let calls = Rc::new(RefCell::new(Vec::new()));
+/// let log = calls.clone();
+/// let handle = AsyncHandle::new(&r#loop, move |_, messages| {
+///     log.borrow_mut().push(messages.collect::<Vec<u32>>());
+/// })
+/// .unwrap();
+///
+/// // every send completes before the loop runs, so they are all coalesced into a single call
+/// let threads: Vec<_> = (0..4)
+///     .map(|t| {
+///         let sender = handle.sender();
+///         thread::spawn(move || {
+///             for i in 0..25 {
+///                 sender.send(t * 25 + i).unwrap();
+///             }
+///         })
+///     })
+///     .collect();
+/// for thread in threads {
+///     thread.join().unwrap();
+/// }
+///
+/// r#loop.run(uv_run_mode_UV_RUN_NOWAIT);
+/// let calls = calls.borrow();
+/// assert_eq!(calls.len(), 1);
+/// let mut received = calls[0].clone();
+/// received.sort();
+/// assert_eq!(received, (0..100).collect::<Vec<_>>());
+/// ```
+///
+/// Dropping senders, even the last one, leaves the handle open; dropping the handle closes it:
+///
+/// ```
+/// # use libuv_sys2::async_handle::AsyncHandle;
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+/// # use std::mem;
+/// # use std::thread;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let handle: AsyncHandle = AsyncHandle::new(&r#loop, |_, _| {}).unwrap();
+/// let sender = handle.sender();
+/// thread::spawn(move || mem::drop(sender)).join().unwrap();
+/// assert!(handle.is_active());
+///
+/// let sender = handle.sender();
+/// mem::drop(handle);
+/// assert!(sender.wake().is_err());
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// ```
+pub struct AsyncHandle<T = ()> {
+    handle: *mut uv_async_t,
+    _payload: PhantomData<T>,
+}
+
+impl<T: Send + 'static> AsyncHandle<T> {
+    /// Create a new async handle. `cb` is called on the loop's thread after one or more senders
+    /// call wake() or send().
+    pub fn new<F>(r#loop: &Loop, cb: F) -> Result<AsyncHandle<T>>
+    where
+        F: FnMut(&AsyncHandle<T>, Messages<T>) + 'static,
+    {
+        let mut cb_slot: Callback<AsyncCallback<T>> = Callback::empty();
+        cb_slot.set(Box::new(cb));
+
+        // The shared state needs the handle pointer, but the handle is allocated by alloc(), so
+        // it's filled in afterward. No sender can exist before then.
+        let shared = Arc::new(Shared {
+            handle: ptr::null_mut(),
+            state: AtomicUsize::new(0),
+            queue: Queue::new(),
+        });
+        let state = AsyncState {
+            shared,
+            cb: cb_slot,
+        };
+        let handle = unsafe {
+            handle::alloc(r#loop, state, |r#loop, handle| {
+                uvret!(uv_async_init(r#loop, handle, Some(async_cb::<T>)))
+            })?
+        };
+        unsafe {
+            let state = handle::state::<_, AsyncState<T>>(handle);
+            let shared = Arc::get_mut(&mut (*state).shared).expect("no senders yet");
+            shared.handle = handle;
+        }
+        Ok(AsyncHandle {
+            handle,
+            _payload: PhantomData,
+        })
+    }
+
+    /// Create a new sender for this handle.
+    pub fn sender(&self) -> AsyncSender<T> {
+        let shared = unsafe {
+            (*handle::state::<_, AsyncState<T>>(self.handle))
+                .shared
+                .clone()
+        };
+        AsyncSender { shared }
+    }
+}
+
+impl<T> AsyncHandle<T> {
+    /// Returns true if the handle is active, which it is until it is dropped.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The raw uv_async_t.
+    pub fn as_ptr(&self) -> *mut uv_async_t {
+        self.handle
+    }
+}
+
+impl<T> Drop for AsyncHandle<T> {
+    fn drop(&mut self) {
+        unsafe {
+            (*handle::state::<_, AsyncState<T>>(self.handle))
+                .shared
+                .close();
+            handle::close::<_, AsyncState<T>>(self.handle);
+        }
+    }
+}
+
+unsafe extern "C" fn async_cb<T>(handle: *mut uv_async_t) {
+    // The AsyncHandle passed to the callback doesn't own the handle, so it must not be dropped.
+    let r#async = ManuallyDrop::new(AsyncHandle {
+        handle,
+        _payload: PhantomData,
+    });
+    let state = handle::state::<_, AsyncState<T>>(handle);
+    let shared = &(*state).shared;
+    let messages = shared.queue.take_all();
+    handle::invoke(ptr::addr_of_mut!((*state).cb), |cb| cb(&r#async, messages));
+}
+
+/// The sending half of an AsyncHandle. Senders may be freely cloned and sent to other threads.
+pub struct AsyncSender<T = ()> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> AsyncSender<T> {
+    /// Wake the loop, calling the AsyncHandle's callback without sending a payload. Returns an
+    /// error if the AsyncHandle has been dropped.
+    pub fn wake(&self) -> Result<()> {
+        self.shared.send(None)
+    }
+
+    /// Send a payload to the AsyncHandle's callback, waking the loop. Returns an error if the
+    /// AsyncHandle has been dropped, in which case the payload is dropped, too.
+    pub fn send(&self, payload: T) -> Result<()> {
+        self.shared.send(Some(payload))
+    }
+}
+
+impl<T> Clone for AsyncSender<T> {
+    fn clone(&self) -> AsyncSender<T> {
+        AsyncSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{uv_err_name, uv_errno_t_UV_EOF, uv_strerror};
+use std::error::Error;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::os::raw::c_int;
+
+/// An error returned by libuv
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UvError {
+    func: &'static str,
+    code: c_int,
+}
+
+impl UvError {
+    /// Create an error for a libuv error code, as returned by the libuv function `func`.
+    pub fn new(func: &'static str, code: c_int) -> UvError {
+        UvError { func, code }
+    }
+
+    /// The name of the libuv function that returned the error.
+    pub fn func(&self) -> &'static str {
+        self.func
+    }
+
+    /// The libuv error code. This can be compared to the `uv_errno_t_UV_*` constants.
+    pub fn code(&self) -> c_int {
+        self.code
+    }
+
+    /// Returns true if the error is UV_EOF, which libuv uses to signal the end of a stream.
+    pub fn is_eof(&self) -> bool {
+        self.code == uv_errno_t_UV_EOF
+    }
+
+    /// The name of the error, ie, "EAGAIN".
+    pub fn name(&self) -> String {
+        unsafe {
+            CStr::from_ptr(uv_err_name(self.code))
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
+
+impl fmt::Display for UvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        unsafe {
+            let err = CStr::from_ptr(uv_strerror(self.code)).to_string_lossy();
+            let name = CStr::from_ptr(uv_err_name(self.code)).to_string_lossy();
+            write!(f, "Error calling {}: {} ({})", self.func, err, name)
+        }
+    }
+}
+
+impl Error for UvError {}
+
+impl From<UvError> for io::Error {
+    fn from(err: UvError) -> io::Error {
+        // On unix, libuv error codes are just negated errno values.
+        if cfg!(unix) {
+            io::Error::from_raw_os_error(-err.code)
+        } else {
+            io::Error::other(err)
+        }
+    }
+}
+
+/// A result that may be a UvError
+pub type Result<T> = std::result::Result<T, UvError>;
+
+/// Turns the return code from a libuv function into a Result. Non-negative return codes are
+/// passed through as the Ok value, since some functions (uv_try_write, for example) use them to
+/// return a count.
+macro_rules! uvret {
+    ($func:ident$args:tt) => {
+        match $func$args {
+            code if code < 0 => Err($crate::error::UvError::new(stringify!($func), code as _)),
+            code => Ok(code),
+        }
+    };
+}
@@ -0,0 +1,160 @@
+use crate::error::Result;
+use crate::{
+    uv_close, uv_default_loop, uv_errno_t_UV_EBUSY, uv_handle_t, uv_is_closing, uv_loop_alive,
+    uv_loop_close, uv_loop_init, uv_loop_t, uv_now, uv_run, uv_run_mode,
+    uv_run_mode_UV_RUN_DEFAULT, uv_stop, uv_update_time, uv_walk,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::rc::Rc;
+
+/// A libuv event loop.
+///
+/// Loop is a cheap, reference-counted pointer to a uv_loop_t: cloning it does not create a new
+/// loop. Every handle created by the safe wrappers in this crate keeps a clone of its Loop, so the
+/// loop is guaranteed to outlive its handles. A loop created with Loop::new() is closed and
+/// deallocated when the last clone is dropped.
+///
+/// Keep in mind that dropping a handle only _starts_ closing it: libuv finishes closing handles
+/// (and the wrappers release their memory, including their clone of the Loop) the next time the
+/// loop runs.
+#[derive(Clone)]
+pub struct Loop {
+    inner: Rc<LoopInner>,
+}
+
+struct LoopInner {
+    ptr: *mut uv_loop_t,
+    owned: bool,
+}
+
+thread_local! {
+    /// The loops that are currently running on this thread. This is keyed by the uv_loop_t, rather
+    /// than kept in LoopInner, since default_loop() and from_raw() may wrap the same loop any
+    /// number of times.
+    static RUNNING: RefCell<HashSet<*mut uv_loop_t>> = RefCell::new(HashSet::new());
+}
+
+impl Loop {
+    /// Allocate and initialize a new event loop.
+    pub fn new() -> Result<Loop> {
+        unsafe {
+            let ptr = Box::into_raw(Box::new(mem::zeroed::<uv_loop_t>()));
+            if let Err(err) = uvret!(uv_loop_init(ptr)) {
+                mem::drop(Box::from_raw(ptr));
+                return Err(err);
+            }
+            Ok(Loop::wrap(ptr, true))
+        }
+    }
+
+    /// Returns the default loop.
+    ///
+    /// The default loop is a process-wide global in libuv and, like every other loop, may only be
+    /// used from a single thread.
+    pub fn default_loop() -> Loop {
+        unsafe { Loop::wrap(uv_default_loop(), false) }
+    }
+
+    /// Wrap a loop that was created outside of this crate. The Loop will not close the underlying
+    /// uv_loop_t when it is dropped.
+    ///
+    /// # Safety
+    /// `ptr` must point to an initialized uv_loop_t that will outlive the returned Loop and every
+    /// handle created with it.
+    pub unsafe fn from_raw(ptr: *mut uv_loop_t) -> Loop {
+        Loop::wrap(ptr, false)
+    }
+
+    fn wrap(ptr: *mut uv_loop_t, owned: bool) -> Loop {
+        Loop {
+            inner: Rc::new(LoopInner { ptr, owned }),
+        }
+    }
+
+    /// The raw uv_loop_t.
+    pub fn as_ptr(&self) -> *mut uv_loop_t {
+        self.inner.ptr
+    }
+
+    /// Run the event loop; see uv_run for a description of the modes. Returns true if there are
+    /// still active handles or requests, ie, if the loop should be run again.
+    ///
+    /// # Panics
+    /// libuv's loops are not reentrant, so this method will panic if it is called from a callback
+    /// that is running inside of this loop, even through a different Loop that wraps the same
+    /// uv_loop_t.
+    pub fn run(&self, mode: uv_run_mode) -> bool {
+        let ptr = self.inner.ptr;
+        assert!(
+            RUNNING.with(|running| running.borrow_mut().insert(ptr)),
+            "Loop::run called recursively"
+        );
+        let ret = unsafe { uv_run(ptr, mode) };
+        RUNNING.with(|running| running.borrow_mut().remove(&ptr));
+        ret != 0
+    }
+
+    /// Stop the event loop, causing run() to return as soon as possible.
+    pub fn stop(&self) {
+        unsafe { uv_stop(self.inner.ptr) }
+    }
+
+    /// Returns true if there are referenced active handles, active requests, or closing handles in
+    /// the loop.
+    pub fn alive(&self) -> bool {
+        unsafe { uv_loop_alive(self.inner.ptr) != 0 }
+    }
+
+    /// The cached "now" of the loop, in milliseconds. See uv_now.
+    pub fn now(&self) -> u64 {
+        unsafe { uv_now(self.inner.ptr) }
+    }
+
+    /// Update the loop's concept of "now". See uv_update_time.
+    pub fn update_time(&self) {
+        unsafe { uv_update_time(self.inner.ptr) }
+    }
+}
+
+impl PartialEq for Loop {
+    fn eq(&self, other: &Loop) -> bool {
+        self.inner.ptr == other.inner.ptr
+    }
+}
+
+impl Eq for Loop {}
+
+/// This callback is used by uv_walk to close any handles that are still open when an owned loop
+/// is dropped.
+unsafe extern "C" fn walk_and_close_cb(handle: *mut uv_handle_t, _arg: *mut c_void) {
+    if uv_is_closing(handle) == 0 {
+        uv_close(handle, None);
+    }
+}
+
+impl Drop for LoopInner {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+
+        unsafe {
+            // Any handles that are still open at this point were created outside of the safe
+            // wrappers (which hold a reference to the loop). Close them and give the loop a chance
+            // to finish. If the loop _still_ can't be closed, leak it rather than freeing memory
+            // that libuv is using.
+            if uv_loop_close(self.ptr) == uv_errno_t_UV_EBUSY {
+                uv_walk(self.ptr, Some(walk_and_close_cb), ptr::null_mut());
+                uv_run(self.ptr, uv_run_mode_UV_RUN_DEFAULT);
+                if uv_loop_close(self.ptr) != 0 {
+                    return;
+                }
+            }
+            mem::drop(Box::from_raw(self.ptr));
+        }
+    }
+}
@@ -0,0 +1,1462 @@
+//! Safe wrappers for libuv's file system operations.
+//!
+//! Every operation takes a callback that's called with a typed result once the operation has
+//! finished on libuv's thread pool. The uv_fs_t request behind each operation is allocated and
+//! cleaned up (with uv_fs_req_cleanup) automatically, and paths and buffers are kept alive for as
+//! long as libuv needs them.
+//!
+//! Every operation also has a _blocking counterpart, which runs the operation synchronously on
+//! the calling thread (libuv does this when it isn't given a callback) and returns its result.
+//! The blocking forms still need a Loop, but they don't run it, so they can be used before the
+//! loop has started, ie, to read configuration at startup.
+//!
+//! # Example
+//!
+//! ```
+//! # use libuv_sys2::error::Result;
+//! # use libuv_sys2::event_loop::Loop;
+//! # use libuv_sys2::fs::{self, OpenOptions};
+//! # use libuv_sys2::{uv_errno_t_UV_EEXIST, uv_run_mode_UV_RUN_DEFAULT};
+//! # use std::cell::RefCell;
+//! # use std::rc::Rc;
+//! #
+//! // start an operation and run the loop until its callback has been called
+//! fn wait<T, S>(r#loop: &Loop, start: S) -> Result<T>
+//! where
+//!     T: 'static,
+//!     S: FnOnce(Box<dyn FnOnce(Result<T>)>) -> Result<()>,
+//! {
+//!     let slot = Rc::new(RefCell::new(None));
+//!     let result = slot.clone();
+//!     start(Box::new(move |value| *result.borrow_mut() = Some(value)))?;
+//!     r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+//!     let value = slot.borrow_mut().take().expect("the callback was called");
+//!     value
+//! }
+//!
+//! let r#loop = Loop::new().unwrap();
+//! let dir = std::env::temp_dir().join(format!("libuv-sys2-fs-{}", std::process::id()));
+//! wait(&r#loop, |cb| fs::mkdir(&r#loop, &dir, 0o755, cb)).unwrap();
+//!
+//! let path = dir.join("hello.txt");
+//! let file = wait(&r#loop, |cb| {
+//!     OpenOptions::new()
+//!         .read(true)
+//!         .write(true)
+//!         .create_new(true)
+//!         .open(&r#loop, &path, cb)
+//! })
+//! .unwrap();
+//! assert_eq!(wait(&r#loop, |cb| file.write(b"hello, world", Some(0), cb)).unwrap(), 12);
+//! assert_eq!(wait(&r#loop, |cb| file.read(5, Some(7), cb)).unwrap(), b"world");
+//!
+//! let stat = wait(&r#loop, |cb| file.stat(cb)).unwrap();
+//! assert!(stat.is_file());
+//! assert_eq!(stat.size(), 12);
+//! wait(&r#loop, |cb| file.close(cb)).unwrap();
+//!
+//! // create_new refuses to open a file that already exists
+//! let err = wait(&r#loop, |cb| {
+//!     OpenOptions::new()
+//!         .write(true)
+//!         .create_new(true)
+//!         .open(&r#loop, &path, cb)
+//! })
+//! .err()
+//! .unwrap();
+//! assert_eq!(err.code(), uv_errno_t_UV_EEXIST);
+//!
+//! let renamed = dir.join("renamed.txt");
+//! wait(&r#loop, |cb| fs::rename(&r#loop, &path, &renamed, cb)).unwrap();
+//! assert!(wait(&r#loop, |cb| fs::stat(&r#loop, &path, cb)).is_err());
+//! let real = wait(&r#loop, |cb| fs::realpath(&r#loop, &renamed, cb)).unwrap();
+//! assert_eq!(real, renamed.canonicalize().unwrap());
+//!
+//! #[cfg(unix)]
+//! {
+//!     let link = dir.join("link");
+//!     wait(&r#loop, |cb| fs::symlink(&r#loop, "renamed.txt", &link, 0, cb)).unwrap();
+//!     assert!(wait(&r#loop, |cb| fs::lstat(&r#loop, &link, cb)).unwrap().is_symlink());
+//!     let target = wait(&r#loop, |cb| fs::readlink(&r#loop, &link, cb)).unwrap();
+//!     assert_eq!(target.to_str(), Some("renamed.txt"));
+//!     wait(&r#loop, |cb| fs::unlink(&r#loop, &link, cb)).unwrap();
+//! }
+//!
+//! wait(&r#loop, |cb| fs::unlink(&r#loop, &renamed, cb)).unwrap();
+//! wait(&r#loop, |cb| fs::rmdir(&r#loop, &dir, cb)).unwrap();
+//! ```
+//!
+//! The blocking forms return the same results as the asynchronous ones:
+//!
+//! ```
+//! # use libuv_sys2::error::Result;
+//! # use libuv_sys2::event_loop::Loop;
+//! # use libuv_sys2::fs::{self, File, Stat};
+//! # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+//! # use std::cell::RefCell;
+//! # use std::rc::Rc;
+//! #
+//! # fn wait<T, S>(r#loop: &Loop, start: S) -> Result<T>
+//! # where
+//! #     T: 'static,
+//! #     S: FnOnce(Box<dyn FnOnce(Result<T>)>) -> Result<()>,
+//! # {
+//! #     let slot = Rc::new(RefCell::new(None));
+//! #     let result = slot.clone();
+//! #     start(Box::new(move |value| *result.borrow_mut() = Some(value)))?;
+//! #     r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+//! #     let value = slot.borrow_mut().take().expect("the callback was called");
+//! #     value
+//! # }
+//! #
+//! fn same(a: &Stat, b: &Stat) -> bool {
+//!     (a.dev(), a.ino(), a.mode(), a.size()) == (b.dev(), b.ino(), b.mode(), b.size())
+//! }
+//!
+//! let r#loop = Loop::new().unwrap();
+//! let dir = std::env::temp_dir().join(format!("libuv-sys2-blocking-{}", std::process::id()));
+//! fs::mkdir_blocking(&r#loop, &dir, 0o755).unwrap();
+//! let path = dir.join("config.txt");
+//! let file = File::create_blocking(&r#loop, &path).unwrap();
+//! assert_eq!(file.write_blocking(b"answer = 42\n", None).unwrap(), 12);
+//! file.close_blocking().unwrap();
+//!
+//! let file = File::open_blocking(&r#loop, &path).unwrap();
+//! let data = file.read_blocking(64, Some(0)).unwrap();
+//! assert_eq!(data, wait(&r#loop, |cb| file.read(64, Some(0), cb)).unwrap());
+//! assert!(same(&file.stat_blocking().unwrap(), &wait(&r#loop, |cb| file.stat(cb)).unwrap()));
+//!
+//! let stat = fs::stat_blocking(&r#loop, &path).unwrap();
+//! assert!(same(&stat, &wait(&r#loop, |cb| fs::stat(&r#loop, &path, cb)).unwrap()));
+//! let real = fs::realpath_blocking(&r#loop, &path).unwrap();
+//! assert_eq!(real, wait(&r#loop, |cb| fs::realpath(&r#loop, &path, cb)).unwrap());
+//!
+//! #[cfg(unix)]
+//! {
+//!     let link = dir.join("link");
+//!     fs::symlink_blocking(&r#loop, "config.txt", &link, 0).unwrap();
+//!     let target = fs::readlink_blocking(&r#loop, &link).unwrap();
+//!     assert_eq!(target, wait(&r#loop, |cb| fs::readlink(&r#loop, &link, cb)).unwrap());
+//!     let stat = fs::lstat_blocking(&r#loop, &link).unwrap();
+//!     assert!(same(&stat, &wait(&r#loop, |cb| fs::lstat(&r#loop, &link, cb)).unwrap()));
+//!     fs::unlink_blocking(&r#loop, &link).unwrap();
+//! }
+//!
+//! // errors are the same too
+//! let missing = dir.join("missing");
+//! let err = fs::stat_blocking(&r#loop, &missing).unwrap_err();
+//! assert_eq!(err, wait(&r#loop, |cb| fs::stat(&r#loop, &missing, cb)).unwrap_err());
+//!
+//! drop(file);
+//! fs::unlink_blocking(&r#loop, &path).unwrap();
+//! fs::rmdir_blocking(&r#loop, &dir).unwrap();
+//! ```
+
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::{
+    uv_buf_t, uv_errno_t_UV_EINVAL, uv_file, uv_fs_cb, uv_fs_chmod, uv_fs_chown, uv_fs_close,
+    uv_fs_fdatasync, uv_fs_fstat, uv_fs_fsync, uv_fs_ftruncate, uv_fs_get_ptr, uv_fs_get_result,
+    uv_fs_get_statbuf, uv_fs_link, uv_fs_lstat, uv_fs_mkdir, uv_fs_open, uv_fs_read,
+    uv_fs_readlink, uv_fs_realpath, uv_fs_rename, uv_fs_req_cleanup, uv_fs_rmdir, uv_fs_stat,
+    uv_fs_statfs, uv_fs_symlink, uv_fs_t, uv_fs_unlink, uv_fs_utime, uv_fs_write, uv_gid_t,
+    uv_loop_t, uv_stat_t, uv_statfs_t, uv_timespec_t, uv_uid_t, UV_FS_O_APPEND, UV_FS_O_CREAT,
+    UV_FS_O_EXCL, UV_FS_O_RDONLY, UV_FS_O_RDWR, UV_FS_O_TRUNC, UV_FS_O_WRONLY,
+};
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub mod copy;
+pub mod dir;
+pub mod temp;
+
+/// Convert a path for libuv, which wants NUL-terminated UTF-8 on Windows.
+fn cpath(func: &'static str, path: &Path) -> Result<CString> {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    };
+    #[cfg(windows)]
+    let bytes = match path.to_str() {
+        Some(path) => path.as_bytes().to_vec(),
+        None => return Err(UvError::new(func, uv_errno_t_UV_EINVAL)),
+    };
+    CString::new(bytes).map_err(|_| UvError::new(func, uv_errno_t_UV_EINVAL))
+}
+
+/// Convert a path returned by libuv.
+unsafe fn from_cpath(path: *const c_char) -> PathBuf {
+    let bytes = CStr::from_ptr(path).to_bytes();
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(OsStr::from_bytes(bytes))
+    }
+    #[cfg(windows)]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// libuv's offset for "the current position".
+fn raw_offset(offset: Option<u64>) -> i64 {
+    offset.map_or(-1, |offset| offset.min(i64::MAX as u64) as i64)
+}
+
+/// A time as the fractional seconds since the epoch that uv_fs_utime wants.
+fn seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(before) => -before.duration().as_secs_f64(),
+    }
+}
+
+/// How a request is run: asynchronously, with Async, or synchronously, with Blocking.
+trait Mode<T> {
+    /// What running the request returns if it was started (and, for Blocking, finished)
+    /// successfully.
+    type Output;
+
+    /// Run a request. `call` makes the uv_fs_* call with the request and callback it's given.
+    /// Once libuv has finished, `result` turns the request into a typed value, if the request
+    /// succeeded, and the request is cleaned up. Anything that libuv needs to stay alive until
+    /// then, such as a buffer, should be owned by `result`.
+    unsafe fn run<C, R>(
+        self,
+        r#loop: &Loop,
+        func: &'static str,
+        call: C,
+        result: R,
+    ) -> Result<Self::Output>
+    where
+        C: FnOnce(*mut uv_loop_t, *mut uv_fs_t, uv_fs_cb) -> c_int,
+        R: FnOnce(*mut uv_fs_t) -> T + 'static;
+}
+
+/// Run a request on libuv's thread pool, and call a callback with its result.
+struct Async<F>(F);
+
+/// A uv_fs_t, along with the closure that finishes it.
+#[repr(C)]
+struct FsRequest<F> {
+    /// This must be the first field so that the uv_fs_t pointer that libuv passes to the
+    /// callback can be cast back to the FsRequest.
+    req: uv_fs_t,
+    done: Option<F>,
+}
+
+impl<T, F> Mode<T> for Async<F>
+where
+    F: FnOnce(Result<T>) + 'static,
+{
+    type Output = ();
+
+    unsafe fn run<C, R>(self, r#loop: &Loop, func: &'static str, call: C, result: R) -> Result<()>
+    where
+        C: FnOnce(*mut uv_loop_t, *mut uv_fs_t, uv_fs_cb) -> c_int,
+        R: FnOnce(*mut uv_fs_t) -> T + 'static,
+    {
+        let Async(cb) = self;
+        let keep_alive = r#loop.clone();
+        let done = move |req: *mut uv_fs_t| {
+            let _loop = keep_alive;
+            let ret = uv_fs_get_result(req);
+            let value = if ret < 0 {
+                // Release whatever `result` owns before calling the callback, which may want to
+                // start another operation that needs it.
+                mem::drop(result);
+                Err(UvError::new(func, ret as c_int))
+            } else {
+                Ok(result(req))
+            };
+            uv_fs_req_cleanup(req);
+            cb(value);
+        };
+        let req = Box::into_raw(Box::new(FsRequest {
+            req: mem::zeroed(),
+            done: Some(done),
+        }));
+        let ret = call(r#loop.as_ptr(), req as *mut uv_fs_t, callback(req));
+        if ret < 0 {
+            uv_fs_req_cleanup(req as *mut uv_fs_t);
+            mem::drop(Box::from_raw(req));
+            return Err(UvError::new(func, ret));
+        }
+        Ok(())
+    }
+}
+
+/// The uv_fs_cb for a request, which is generic over the request's closure.
+fn callback<F>(_req: *mut FsRequest<F>) -> uv_fs_cb
+where
+    F: FnOnce(*mut uv_fs_t),
+{
+    Some(fs_cb::<F>)
+}
+
+unsafe extern "C" fn fs_cb<F>(req: *mut uv_fs_t)
+where
+    F: FnOnce(*mut uv_fs_t),
+{
+    // The request can't be moved out of its box until libuv is done with it: uv_fs_t may point
+    // into itself.
+    let request = req as *mut FsRequest<F>;
+    let done = &mut (*request).done;
+    if let Some(done) = done.take() {
+        done(req);
+    }
+    mem::drop(Box::from_raw(request));
+}
+
+/// Run a request synchronously, on the calling thread, which libuv does when it's not given a
+/// callback. The loop isn't run.
+struct Blocking;
+
+impl<T> Mode<T> for Blocking {
+    type Output = T;
+
+    unsafe fn run<C, R>(self, r#loop: &Loop, func: &'static str, call: C, result: R) -> Result<T>
+    where
+        C: FnOnce(*mut uv_loop_t, *mut uv_fs_t, uv_fs_cb) -> c_int,
+        R: FnOnce(*mut uv_fs_t) -> T + 'static,
+    {
+        let mut req: uv_fs_t = mem::zeroed();
+        let ret = call(r#loop.as_ptr(), &mut req, None);
+        let value = if ret < 0 {
+            Err(UvError::new(func, ret))
+        } else {
+            Ok(result(&mut req))
+        };
+        // Anything that libuv allocated for the result, such as the path returned by
+        // uv_fs_readlink, has been copied by now, and is freed here, just like it is for
+        // asynchronous requests.
+        uv_fs_req_cleanup(&mut req);
+        value
+    }
+}
+
+fn unit(_req: *mut uv_fs_t) {}
+
+unsafe fn count(req: *mut uv_fs_t) -> usize {
+    uv_fs_get_result(req) as usize
+}
+
+unsafe fn stat_result(req: *mut uv_fs_t) -> Stat {
+    Stat::from_raw(*uv_fs_get_statbuf(req))
+}
+
+/// The uv_statfs_t that uv_fs_statfs returns, which libuv frees in uv_fs_req_cleanup.
+unsafe fn statfs_result(req: *mut uv_fs_t) -> StatFs {
+    StatFs::from_raw(*(uv_fs_get_ptr(req) as *const uv_statfs_t))
+}
+
+/// The path that uv_fs_readlink and uv_fs_realpath return. libuv frees it in
+/// uv_fs_req_cleanup, so it's copied.
+unsafe fn path_result(req: *mut uv_fs_t) -> PathBuf {
+    from_cpath(uv_fs_get_ptr(req) as *const c_char)
+}
+
+/// The mode bits of st_mode that hold the file type, and the types. libuv uses the same values
+/// on every platform.
+const S_IFMT: u64 = 0o170000;
+const S_IFIFO: u64 = 0o010000;
+const S_IFCHR: u64 = 0o020000;
+const S_IFDIR: u64 = 0o040000;
+const S_IFBLK: u64 = 0o060000;
+const S_IFREG: u64 = 0o100000;
+const S_IFLNK: u64 = 0o120000;
+const S_IFSOCK: u64 = 0o140000;
+
+/// The type of a file, from its st_mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+    Unknown,
+}
+
+impl FileType {
+    /// Decode the file type bits of `mode`.
+    pub fn from_mode(mode: u64) -> FileType {
+        match mode & S_IFMT {
+            S_IFREG => FileType::File,
+            S_IFDIR => FileType::Dir,
+            S_IFLNK => FileType::Symlink,
+            S_IFIFO => FileType::Fifo,
+            S_IFSOCK => FileType::Socket,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            _ => FileType::Unknown,
+        }
+    }
+}
+
+/// Convert a uv_timespec_t, which is relative to the epoch.
+// tv_sec and tv_nsec are c_longs, which are only 32 bits on Windows.
+#[allow(clippy::unnecessary_cast)]
+fn system_time(time: uv_timespec_t) -> SystemTime {
+    let (sec, nsec) = (time.tv_sec as i64, time.tv_nsec as u32);
+    let time = if sec >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(sec as u64, nsec))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::from_secs(sec.unsigned_abs()))
+            .and_then(|time| time.checked_add(Duration::from_nanos(nsec as u64)))
+    };
+    time.unwrap_or(UNIX_EPOCH)
+}
+
+/// Information about a file, as returned by stat(), lstat() and File::stat().
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::fs::temp::TempDir;
+/// # use libuv_sys2::fs::{self, File, FileType};
+/// # use std::time::{Duration, SystemTime, UNIX_EPOCH};
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let dir = TempDir::create_blocking(&r#loop).unwrap();
+/// let path = dir.path().join("file");
+/// File::create_blocking(&r#loop, &path).unwrap().write_blocking(b"1234", None).unwrap();
+/// fs::chmod_blocking(&r#loop, &path, 0o640).unwrap();
+/// let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+/// fs::utime_blocking(&r#loop, &path, SystemTime::now(), modified).unwrap();
+///
+/// let stat = fs::stat_blocking(&r#loop, &path).unwrap();
+/// assert_eq!(stat.file_type(), FileType::File);
+/// assert_eq!(stat.size(), 4);
+/// assert_eq!(stat.modified(), modified);
+/// assert!(stat.accessed() > modified);
+/// #[cfg(unix)]
+/// assert_eq!(stat.permissions(), 0o640);
+/// assert_eq!(fs::stat_blocking(&r#loop, dir.path()).unwrap().file_type(), FileType::Dir);
+///
+/// // not every platform knows when a file was created, but it can't be after its metadata
+/// // was last changed
+/// if let Some(created) = stat.created() {
+///     assert!(created <= stat.changed());
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Stat {
+    raw: uv_stat_t,
+}
+
+impl Stat {
+    /// Wrap a raw uv_stat_t.
+    pub fn from_raw(raw: uv_stat_t) -> Stat {
+        Stat { raw }
+    }
+
+    /// The raw uv_stat_t.
+    pub fn as_raw(&self) -> &uv_stat_t {
+        &self.raw
+    }
+
+    /// The size of the file, in bytes.
+    pub fn size(&self) -> u64 {
+        self.raw.st_size
+    }
+
+    /// The file's type and permissions, as in st_mode.
+    pub fn mode(&self) -> u64 {
+        self.raw.st_mode
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::from_mode(self.raw.st_mode)
+    }
+
+    /// The file's permission bits, including the setuid, setgid and sticky bits. On Windows,
+    /// libuv only reports whether the file is read-only.
+    pub fn permissions(&self) -> u32 {
+        (self.raw.st_mode & 0o7777) as u32
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == FileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == FileType::Dir
+    }
+
+    /// Returns true if the file is a symbolic link. Only lstat() reports symbolic links; stat()
+    /// follows them.
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == FileType::Symlink
+    }
+
+    /// The number of hard links to the file.
+    pub fn nlink(&self) -> u64 {
+        self.raw.st_nlink
+    }
+
+    pub fn uid(&self) -> u64 {
+        self.raw.st_uid
+    }
+
+    pub fn gid(&self) -> u64 {
+        self.raw.st_gid
+    }
+
+    /// The id of the device that the file is on.
+    pub fn dev(&self) -> u64 {
+        self.raw.st_dev
+    }
+
+    /// The id of the device that the file is, if it's a device.
+    pub fn rdev(&self) -> u64 {
+        self.raw.st_rdev
+    }
+
+    /// The file's inode number.
+    pub fn ino(&self) -> u64 {
+        self.raw.st_ino
+    }
+
+    /// The preferred block size for I/O on the file.
+    pub fn block_size(&self) -> u64 {
+        self.raw.st_blksize
+    }
+
+    /// The number of 512-byte blocks allocated to the file.
+    pub fn blocks(&self) -> u64 {
+        self.raw.st_blocks
+    }
+
+    /// The time that the file was last read.
+    pub fn accessed(&self) -> SystemTime {
+        system_time(self.raw.st_atim)
+    }
+
+    /// The time that the file's contents were last changed.
+    pub fn modified(&self) -> SystemTime {
+        system_time(self.raw.st_mtim)
+    }
+
+    /// The time that the file's metadata was last changed.
+    pub fn changed(&self) -> SystemTime {
+        system_time(self.raw.st_ctim)
+    }
+
+    /// The time that the file was created, if the platform and file system report it.
+    ///
+    /// On Linux, libuv only knows the creation time when statx() is available; otherwise it
+    /// reports the time of the last metadata change instead. A creation time that's the same as
+    /// changed() is therefore treated as unknown there, which may also hide a genuine creation
+    /// time if the file's metadata hasn't changed since it was created.
+    pub fn created(&self) -> Option<SystemTime> {
+        let birthtime = self.raw.st_birthtim;
+        if birthtime.tv_sec == 0 && birthtime.tv_nsec == 0 {
+            return None;
+        }
+        if cfg!(any(target_os = "linux", target_os = "android")) {
+            let ctime = self.raw.st_ctim;
+            if birthtime.tv_sec == ctime.tv_sec && birthtime.tv_nsec == ctime.tv_nsec {
+                return None;
+            }
+        }
+        Some(system_time(birthtime))
+    }
+}
+
+/// Information about a file system, as returned by statfs().
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::fs;
+/// # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let dir = std::env::temp_dir();
+/// let stat = fs::statfs_blocking(&r#loop, &dir).unwrap();
+/// assert!(stat.block_size() > 0);
+/// assert!(stat.available() <= stat.free() && stat.free() <= stat.blocks());
+///
+/// let blocks = Rc::new(Cell::new(0));
+/// let total = blocks.clone();
+/// fs::statfs(&r#loop, &dir, move |stat| total.set(stat.unwrap().blocks())).unwrap();
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// assert_eq!(blocks.get(), stat.blocks());
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct StatFs {
+    raw: uv_statfs_t,
+}
+
+impl StatFs {
+    /// Wrap a raw uv_statfs_t.
+    pub fn from_raw(raw: uv_statfs_t) -> StatFs {
+        StatFs { raw }
+    }
+
+    /// The raw uv_statfs_t.
+    pub fn as_raw(&self) -> &uv_statfs_t {
+        &self.raw
+    }
+
+    /// The type of the file system, as a platform-specific magic number.
+    pub fn fs_type(&self) -> u64 {
+        self.raw.f_type
+    }
+
+    /// The size of the file system's blocks, in bytes.
+    pub fn block_size(&self) -> u64 {
+        self.raw.f_bsize
+    }
+
+    /// The total number of blocks.
+    pub fn blocks(&self) -> u64 {
+        self.raw.f_blocks
+    }
+
+    /// The number of free blocks.
+    pub fn free(&self) -> u64 {
+        self.raw.f_bfree
+    }
+
+    /// The number of free blocks that unprivileged users may use.
+    pub fn available(&self) -> u64 {
+        self.raw.f_bavail
+    }
+
+    /// The space that unprivileged users may use, in bytes.
+    pub fn available_bytes(&self) -> u64 {
+        self.raw.f_bavail.saturating_mul(self.raw.f_bsize)
+    }
+
+    /// The total number of file nodes (inodes).
+    pub fn files(&self) -> u64 {
+        self.raw.f_files
+    }
+
+    /// The number of free file nodes.
+    pub fn free_files(&self) -> u64 {
+        self.raw.f_ffree
+    }
+}
+
+/// Options for opening a File, like std::fs::OpenOptions.
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    mode: u32,
+    custom_flags: c_int,
+}
+
+impl Default for OpenOptions {
+    fn default() -> OpenOptions {
+        OpenOptions::new()
+    }
+}
+
+impl OpenOptions {
+    /// Options with everything turned off. At least one of read, write or append must be turned
+    /// on before opening a file.
+    pub fn new() -> OpenOptions {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            mode: 0o666,
+            custom_flags: 0,
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut OpenOptions {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut OpenOptions {
+        self.write = write;
+        self
+    }
+
+    /// Open the file for appending: every write goes to the end of the file. This implies
+    /// write.
+    pub fn append(&mut self, append: bool) -> &mut OpenOptions {
+        self.append = append;
+        self
+    }
+
+    /// Truncate the file to zero length when it's opened. Requires write.
+    pub fn truncate(&mut self, truncate: bool) -> &mut OpenOptions {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Create the file if it doesn't exist. Requires write or append.
+    pub fn create(&mut self, create: bool) -> &mut OpenOptions {
+        self.create = create;
+        self
+    }
+
+    /// Create the file, failing with UV_EEXIST if it already exists. Requires write or append.
+    pub fn create_new(&mut self, create_new: bool) -> &mut OpenOptions {
+        self.create_new = create_new;
+        self
+    }
+
+    /// The permissions that a new file is created with, before the umask is applied. Defaults to
+    /// 0o666.
+    pub fn mode(&mut self, mode: u32) -> &mut OpenOptions {
+        self.mode = mode;
+        self
+    }
+
+    /// Extra flags to pass to uv_fs_open, ie, UV_FS_O_NOFOLLOW.
+    pub fn custom_flags(&mut self, flags: c_int) -> &mut OpenOptions {
+        self.custom_flags = flags;
+        self
+    }
+
+    /// The flags for uv_fs_open. Combinations that std::fs::OpenOptions would reject are
+    /// rejected with UV_EINVAL.
+    fn flags(&self) -> Result<c_int> {
+        let invalid = Err(UvError::new("uv_fs_open", uv_errno_t_UV_EINVAL));
+        let writing = self.write || self.append;
+        let access = match (self.read, writing) {
+            (true, false) => UV_FS_O_RDONLY,
+            (false, true) => UV_FS_O_WRONLY,
+            (true, true) => UV_FS_O_RDWR,
+            (false, false) => return invalid,
+        };
+        if (self.truncate || self.create || self.create_new) && !writing {
+            return invalid;
+        }
+        if self.truncate && self.append && !self.write {
+            return invalid;
+        }
+
+        let mut flags = access;
+        if self.append {
+            flags |= UV_FS_O_APPEND;
+        }
+        if self.truncate {
+            flags |= UV_FS_O_TRUNC;
+        }
+        if self.create_new {
+            flags |= UV_FS_O_CREAT | UV_FS_O_EXCL;
+        } else if self.create {
+            flags |= UV_FS_O_CREAT;
+        }
+        Ok(flags as c_int | self.custom_flags)
+    }
+
+    /// Open the file at `path`, and call `cb` with it.
+    pub fn open<P, F>(&self, r#loop: &Loop, path: P, cb: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(Result<File>) + 'static,
+    {
+        self.open_with(r#loop, path.as_ref(), Async(cb))
+    }
+
+    /// Open the file at `path`, blocking until it's open.
+    pub fn open_blocking<P: AsRef<Path>>(&self, r#loop: &Loop, path: P) -> Result<File> {
+        self.open_with(r#loop, path.as_ref(), Blocking)
+    }
+
+    fn open_with<M: Mode<File>>(&self, r#loop: &Loop, path: &Path, mode: M) -> Result<M::Output> {
+        let path = cpath("uv_fs_open", path)?;
+        let (flags, perms) = (self.flags()?, self.mode as c_int);
+        let file_loop = r#loop.clone();
+        unsafe {
+            mode.run(
+                r#loop,
+                "uv_fs_open",
+                |r#loop, req, cb| uv_fs_open(r#loop, req, path.as_ptr(), flags, perms, cb),
+                move |req| File::from_raw(&file_loop, uv_fs_get_result(req) as uv_file),
+            )
+        }
+    }
+}
+
+struct FileInner {
+    fd: Cell<uv_file>,
+    r#loop: Loop,
+}
+
+impl Drop for FileInner {
+    fn drop(&mut self) {
+        let fd = self.fd.get();
+        if fd >= 0 {
+            unsafe {
+                let mut req: uv_fs_t = mem::zeroed();
+                uv_fs_close(self.r#loop.as_ptr(), &mut req, fd, None);
+                uv_fs_req_cleanup(&mut req);
+            }
+        }
+    }
+}
+
+/// An open file.
+///
+/// Every operation on a file has an asynchronous form, which calls a callback, and a _blocking
+/// form, which runs the operation on the calling thread and returns its result.
+///
+/// The file is closed when it's dropped, once any asynchronous operations that are still in
+/// progress have finished. Closing it that way blocks while the file is closed; use close() to
+/// avoid that.
+pub struct File {
+    inner: Rc<FileInner>,
+}
+
+impl File {
+    /// Open a file for reading.
+    pub fn open<P, F>(r#loop: &Loop, path: P, cb: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(Result<File>) + 'static,
+    {
+        OpenOptions::new().read(true).open(r#loop, path, cb)
+    }
+
+    pub fn open_blocking<P: AsRef<Path>>(r#loop: &Loop, path: P) -> Result<File> {
+        OpenOptions::new().read(true).open_blocking(r#loop, path)
+    }
+
+    /// Open a file for writing, creating it if it doesn't exist, and truncating it if it does.
+    pub fn create<P, F>(r#loop: &Loop, path: P, cb: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(Result<File>) + 'static,
+    {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(r#loop, path, cb)
+    }
+
+    pub fn create_blocking<P: AsRef<Path>>(r#loop: &Loop, path: P) -> Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open_blocking(r#loop, path)
+    }
+
+    /// Wrap a file descriptor, which the File takes ownership of. Asynchronous operations on
+    /// the file will run on `loop`.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor that nothing else will close.
+    pub unsafe fn from_raw(r#loop: &Loop, fd: uv_file) -> File {
+        File {
+            inner: Rc::new(FileInner {
+                fd: Cell::new(fd),
+                r#loop: r#loop.clone(),
+            }),
+        }
+    }
+
+    /// The file descriptor.
+    pub fn as_raw(&self) -> uv_file {
+        self.inner.fd.get()
+    }
+
+    /// Give up ownership of the file descriptor, which will no longer be closed when the File
+    /// is dropped.
+    pub fn into_raw(self) -> uv_file {
+        self.inner.fd.replace(-1)
+    }
+
+    /// The loop that the file's operations run on.
+    pub fn get_loop(&self) -> Loop {
+        self.inner.r#loop.clone()
+    }
+
+    /// Run an operation on the file. `result` keeps the file open until the operation has
+    /// finished.
+    unsafe fn run<T, M, C, R>(
+        &self,
+        mode: M,
+        func: &'static str,
+        call: C,
+        result: R,
+    ) -> Result<M::Output>
+    where
+        M: Mode<T>,
+        C: FnOnce(*mut uv_loop_t, *mut uv_fs_t, uv_fs_cb) -> c_int,
+        R: FnOnce(*mut uv_fs_t) -> T + 'static,
+    {
+        let inner = self.inner.clone();
+        let result = move |req| {
+            let _file = inner;
+            result(req)
+        };
+        mode.run(&self.inner.r#loop, func, call, result)
+    }
+
+    /// Read up to `len` bytes at `offset`, or at the current position if `offset` is None. `cb`
+    /// is called with the bytes that were read, which are empty at the end of the file.
+    pub fn read<F>(&self, len: usize, offset: Option<u64>, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<Vec<u8>>) + 'static,
+    {
+        self.read_with(len, offset, Async(cb))
+    }
+
+    pub fn read_blocking(&self, len: usize, offset: Option<u64>) -> Result<Vec<u8>> {
+        self.read_with(len, offset, Blocking)
+    }
+
+    fn read_with<M>(&self, len: usize, offset: Option<u64>, mode: M) -> Result<M::Output>
+    where
+        M: Mode<Vec<u8>>,
+    {
+        let fd = self.as_raw();
+        let mut buf = vec![0; len];
+        let raw = uv_buf_t {
+            base: buf.as_mut_ptr() as *mut c_char,
+            len: len as _,
+        };
+        unsafe {
+            self.run(
+                mode,
+                "uv_fs_read",
+                |r#loop, req, cb| uv_fs_read(r#loop, req, fd, &raw, 1, raw_offset(offset), cb),
+                move |req| {
+                    buf.truncate(count(req));
+                    buf
+                },
+            )
+        }
+    }
+
+    /// Write `data` at `offset`, or at the current position if `offset` is None. `cb` is called
+    /// with the number of bytes that were written.
+    ///
+    /// `data` may be anything that owns its bytes, including an array, which is moved out of the
+    /// caller's stack frame:
+    ///
+    /// ```
+    /// # use libuv_sys2::event_loop::Loop;
+    /// # use libuv_sys2::fs::{self, File};
+    /// # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+    /// # use std::cell::Cell;
+    /// # use std::rc::Rc;
+    /// #
+    /// let r#loop = Loop::new().unwrap();
+    /// let path = std::env::temp_dir().join(format!("libuv-sys2-write-{}", std::process::id()));
+    /// let file = File::create_blocking(&r#loop, &path).unwrap();
+    ///
+    /// fn start(file: &File, written: Rc<Cell<usize>>) {
+    ///     let data = [0x5a_u8; 4096];
+    ///     file.write(data, Some(0), move |n| written.set(n.unwrap())).unwrap();
+    /// }
+    /// let written = Rc::new(Cell::new(0));
+    /// start(&file, written.clone());
+    /// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+    /// assert_eq!(written.get(), 4096);
+    /// assert!(file.read_blocking(8192, Some(0)).unwrap() == [0x5a; 4096]);
+    ///
+    /// drop(file);
+    /// fs::unlink_blocking(&r#loop, &path).unwrap();
+    /// ```
+    pub fn write<B, F>(&self, data: B, offset: Option<u64>, cb: F) -> Result<()>
+    where
+        B: AsRef<[u8]> + 'static,
+        F: FnOnce(Result<usize>) + 'static,
+    {
+        // The data is boxed first so that the buffer stays put when it's moved into the request,
+        // even if `B` keeps its bytes inline, like an array does.
+        let data = Box::new(data);
+        let bytes = (*data).as_ref();
+        let raw = uv_buf_t {
+            base: bytes.as_ptr() as *mut c_char,
+            len: bytes.len() as _,
+        };
+        unsafe { self.write_with(raw, data, offset, Async(cb)) }
+    }
+
+    pub fn write_blocking(&self, data: &[u8], offset: Option<u64>) -> Result<usize> {
+        let raw = uv_buf_t {
+            base: data.as_ptr() as *mut c_char,
+            len: data.len() as _,
+        };
+        unsafe { self.write_with(raw, (), offset, Blocking) }
+    }
+
+    /// Write `raw`, which must stay valid for as long as `data` does.
+    unsafe fn write_with<M, D>(
+        &self,
+        raw: uv_buf_t,
+        data: D,
+        offset: Option<u64>,
+        mode: M,
+    ) -> Result<M::Output>
+    where
+        M: Mode<usize>,
+        D: 'static,
+    {
+        let fd = self.as_raw();
+        self.run(
+            mode,
+            "uv_fs_write",
+            |r#loop, req, cb| uv_fs_write(r#loop, req, fd, &raw, 1, raw_offset(offset), cb),
+            move |req| {
+                let _data = data;
+                count(req)
+            },
+        )
+    }
+
+    /// Get information about the file (uv_fs_fstat).
+    pub fn stat<F>(&self, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<Stat>) + 'static,
+    {
+        self.stat_with(Async(cb))
+    }
+
+    pub fn stat_blocking(&self) -> Result<Stat> {
+        self.stat_with(Blocking)
+    }
+
+    fn stat_with<M: Mode<Stat>>(&self, mode: M) -> Result<M::Output> {
+        let fd = self.as_raw();
+        unsafe {
+            self.run(
+                mode,
+                "uv_fs_fstat",
+                |r#loop, req, cb| uv_fs_fstat(r#loop, req, fd, cb),
+                |req| stat_result(req),
+            )
+        }
+    }
+
+    /// Flush the file's data and metadata to disk (uv_fs_fsync).
+    pub fn sync<F>(&self, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<()>) + 'static,
+    {
+        self.sync_with(Async(cb))
+    }
+
+    pub fn sync_blocking(&self) -> Result<()> {
+        self.sync_with(Blocking)
+    }
+
+    fn sync_with<M: Mode<()>>(&self, mode: M) -> Result<M::Output> {
+        let fd = self.as_raw();
+        unsafe {
+            self.run(
+                mode,
+                "uv_fs_fsync",
+                |r#loop, req, cb| uv_fs_fsync(r#loop, req, fd, cb),
+                unit,
+            )
+        }
+    }
+
+    /// Flush the file's data, and only as much metadata as is needed to read it back, to disk
+    /// (uv_fs_fdatasync).
+    pub fn datasync<F>(&self, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<()>) + 'static,
+    {
+        self.datasync_with(Async(cb))
+    }
+
+    pub fn datasync_blocking(&self) -> Result<()> {
+        self.datasync_with(Blocking)
+    }
+
+    fn datasync_with<M: Mode<()>>(&self, mode: M) -> Result<M::Output> {
+        let fd = self.as_raw();
+        unsafe {
+            self.run(
+                mode,
+                "uv_fs_fdatasync",
+                |r#loop, req, cb| uv_fs_fdatasync(r#loop, req, fd, cb),
+                unit,
+            )
+        }
+    }
+
+    /// Truncate or extend the file to `len` bytes (uv_fs_ftruncate).
+    pub fn truncate<F>(&self, len: u64, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<()>) + 'static,
+    {
+        self.truncate_with(len, Async(cb))
+    }
+
+    pub fn truncate_blocking(&self, len: u64) -> Result<()> {
+        self.truncate_with(len, Blocking)
+    }
+
+    fn truncate_with<M: Mode<()>>(&self, len: u64, mode: M) -> Result<M::Output> {
+        let fd = self.as_raw();
+        let len = len.min(i64::MAX as u64) as i64;
+        unsafe {
+            self.run(
+                mode,
+                "uv_fs_ftruncate",
+                |r#loop, req, cb| uv_fs_ftruncate(r#loop, req, fd, len, cb),
+                unit,
+            )
+        }
+    }
+
+    /// Close the file without blocking. Asynchronous operations that are still in progress may
+    /// fail.
+    pub fn close<F>(self, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<()>) + 'static,
+    {
+        self.close_with(Async(cb))
+    }
+
+    /// Close the file, returning any error, which dropping the File would ignore.
+    pub fn close_blocking(self) -> Result<()> {
+        self.close_with(Blocking)
+    }
+
+    fn close_with<M: Mode<()>>(self, mode: M) -> Result<M::Output> {
+        let r#loop = self.get_loop();
+        let fd = self.into_raw();
+        unsafe {
+            mode.run(
+                &r#loop,
+                "uv_fs_close",
+                |r#loop, req, cb| uv_fs_close(r#loop, req, fd, cb),
+                unit,
+            )
+        }
+    }
+}
+
+/// Define an operation on one path, in both its asynchronous and blocking forms.
+macro_rules! path_op {
+    (
+        $(#[$attr:meta])*
+        $name:ident, $blocking:ident, $with:ident, $func:ident, $t:ty, $result:expr
+    ) => {
+        $(#[$attr])*
+        pub fn $name<P, F>(r#loop: &Loop, path: P, cb: F) -> Result<()>
+        where
+            P: AsRef<Path>,
+            F: FnOnce(Result<$t>) + 'static,
+        {
+            $with(r#loop, path.as_ref(), Async(cb))
+        }
+
+        pub fn $blocking<P: AsRef<Path>>(r#loop: &Loop, path: P) -> Result<$t> {
+            $with(r#loop, path.as_ref(), Blocking)
+        }
+
+        fn $with<M: Mode<$t>>(r#loop: &Loop, path: &Path, mode: M) -> Result<M::Output> {
+            let path = cpath(stringify!($func), path)?;
+            unsafe {
+                mode.run(
+                    r#loop,
+                    stringify!($func),
+                    |r#loop, req, cb| $func(r#loop, req, path.as_ptr(), cb),
+                    |req| $result(req),
+                )
+            }
+        }
+    };
+}
+
+path_op!(
+    /// Get information about a file, following symbolic links.
+    stat, stat_blocking, stat_with, uv_fs_stat, Stat, stat_result
+);
+
+path_op!(
+    /// Get information about a file, without following symbolic links.
+    lstat, lstat_blocking, lstat_with, uv_fs_lstat, Stat, stat_result
+);
+
+path_op!(
+    /// Get information about the file system that a file is on.
+    statfs, statfs_blocking, statfs_with, uv_fs_statfs, StatFs, statfs_result
+);
+
+path_op!(
+    /// Remove a file.
+    unlink, unlink_blocking, unlink_with, uv_fs_unlink, (), unit
+);
+
+path_op!(
+    /// Remove an empty directory.
+    rmdir, rmdir_blocking, rmdir_with, uv_fs_rmdir, (), unit
+);
+
+path_op!(
+    /// Read the target of a symbolic link.
+    readlink, readlink_blocking, readlink_with, uv_fs_readlink, PathBuf, path_result
+);
+
+path_op!(
+    /// Get the canonical, absolute form of a path, with all symbolic links resolved.
+    realpath, realpath_blocking, realpath_with, uv_fs_realpath, PathBuf, path_result
+);
+
+/// Create a directory with the permissions `mode` (before the umask is applied). On Windows,
+/// `mode` is ignored.
+pub fn mkdir<P, F>(r#loop: &Loop, path: P, mode: u32, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    mkdir_with(r#loop, path.as_ref(), mode, Async(cb))
+}
+
+pub fn mkdir_blocking<P: AsRef<Path>>(r#loop: &Loop, path: P, mode: u32) -> Result<()> {
+    mkdir_with(r#loop, path.as_ref(), mode, Blocking)
+}
+
+fn mkdir_with<M: Mode<()>>(r#loop: &Loop, path: &Path, perms: u32, mode: M) -> Result<M::Output> {
+    let path = cpath("uv_fs_mkdir", path)?;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_mkdir",
+            |r#loop, req, cb| uv_fs_mkdir(r#loop, req, path.as_ptr(), perms as c_int, cb),
+            unit,
+        )
+    }
+}
+
+/// Rename `from` to `to`, replacing `to` if it exists.
+pub fn rename<P, Q, F>(r#loop: &Loop, from: P, to: Q, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    rename_with(r#loop, from.as_ref(), to.as_ref(), Async(cb))
+}
+
+pub fn rename_blocking<P, Q>(r#loop: &Loop, from: P, to: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    rename_with(r#loop, from.as_ref(), to.as_ref(), Blocking)
+}
+
+fn rename_with<M: Mode<()>>(r#loop: &Loop, from: &Path, to: &Path, mode: M) -> Result<M::Output> {
+    let from = cpath("uv_fs_rename", from)?;
+    let to = cpath("uv_fs_rename", to)?;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_rename",
+            |r#loop, req, cb| uv_fs_rename(r#loop, req, from.as_ptr(), to.as_ptr(), cb),
+            unit,
+        )
+    }
+}
+
+/// Create a hard link, `link`, to `original`.
+pub fn link<P, Q, F>(r#loop: &Loop, original: P, link: Q, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    link_with(r#loop, original.as_ref(), link.as_ref(), Async(cb))
+}
+
+pub fn link_blocking<P, Q>(r#loop: &Loop, original: P, link: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    link_with(r#loop, original.as_ref(), link.as_ref(), Blocking)
+}
+
+fn link_with<M: Mode<()>>(
+    r#loop: &Loop,
+    original: &Path,
+    link: &Path,
+    mode: M,
+) -> Result<M::Output> {
+    let original = cpath("uv_fs_link", original)?;
+    let link = cpath("uv_fs_link", link)?;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_link",
+            |r#loop, req, cb| uv_fs_link(r#loop, req, original.as_ptr(), link.as_ptr(), cb),
+            unit,
+        )
+    }
+}
+
+/// Create a symbolic link, `link`, that points to `original`. On Windows, `flags` may be
+/// UV_FS_SYMLINK_DIR, for a link to a directory, or UV_FS_SYMLINK_JUNCTION, to create a
+/// junction instead; elsewhere, it's ignored.
+pub fn symlink<P, Q, F>(r#loop: &Loop, original: P, link: Q, flags: u32, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    symlink_with(r#loop, original.as_ref(), link.as_ref(), flags, Async(cb))
+}
+
+pub fn symlink_blocking<P, Q>(r#loop: &Loop, original: P, link: Q, flags: u32) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    symlink_with(r#loop, original.as_ref(), link.as_ref(), flags, Blocking)
+}
+
+fn symlink_with<M: Mode<()>>(
+    r#loop: &Loop,
+    original: &Path,
+    link: &Path,
+    flags: u32,
+    mode: M,
+) -> Result<M::Output> {
+    let original = cpath("uv_fs_symlink", original)?;
+    let link = cpath("uv_fs_symlink", link)?;
+    let flags = flags as c_int;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_symlink",
+            |r#loop, req, cb| {
+                uv_fs_symlink(r#loop, req, original.as_ptr(), link.as_ptr(), flags, cb)
+            },
+            unit,
+        )
+    }
+}
+
+/// Change a file's permissions.
+pub fn chmod<P, F>(r#loop: &Loop, path: P, mode: u32, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    chmod_with(r#loop, path.as_ref(), mode, Async(cb))
+}
+
+pub fn chmod_blocking<P: AsRef<Path>>(r#loop: &Loop, path: P, mode: u32) -> Result<()> {
+    chmod_with(r#loop, path.as_ref(), mode, Blocking)
+}
+
+fn chmod_with<M: Mode<()>>(r#loop: &Loop, path: &Path, perms: u32, mode: M) -> Result<M::Output> {
+    let path = cpath("uv_fs_chmod", path)?;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_chmod",
+            |r#loop, req, cb| uv_fs_chmod(r#loop, req, path.as_ptr(), perms as c_int, cb),
+            unit,
+        )
+    }
+}
+
+/// Change a file's owner and group. This is a no-op on Windows.
+pub fn chown<P, F>(r#loop: &Loop, path: P, uid: uv_uid_t, gid: uv_gid_t, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    chown_with(r#loop, path.as_ref(), uid, gid, Async(cb))
+}
+
+pub fn chown_blocking<P>(r#loop: &Loop, path: P, uid: uv_uid_t, gid: uv_gid_t) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    chown_with(r#loop, path.as_ref(), uid, gid, Blocking)
+}
+
+fn chown_with<M: Mode<()>>(
+    r#loop: &Loop,
+    path: &Path,
+    uid: uv_uid_t,
+    gid: uv_gid_t,
+    mode: M,
+) -> Result<M::Output> {
+    let path = cpath("uv_fs_chown", path)?;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_chown",
+            |r#loop, req, cb| uv_fs_chown(r#loop, req, path.as_ptr(), uid, gid, cb),
+            unit,
+        )
+    }
+}
+
+/// Change a file's access and modification times.
+pub fn utime<P, F>(
+    r#loop: &Loop,
+    path: P,
+    atime: SystemTime,
+    mtime: SystemTime,
+    cb: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    utime_with(r#loop, path.as_ref(), atime, mtime, Async(cb))
+}
+
+pub fn utime_blocking<P>(r#loop: &Loop, path: P, atime: SystemTime, mtime: SystemTime) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    utime_with(r#loop, path.as_ref(), atime, mtime, Blocking)
+}
+
+fn utime_with<M: Mode<()>>(
+    r#loop: &Loop,
+    path: &Path,
+    atime: SystemTime,
+    mtime: SystemTime,
+    mode: M,
+) -> Result<M::Output> {
+    let path = cpath("uv_fs_utime", path)?;
+    let (atime, mtime) = (seconds(atime), seconds(mtime));
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_utime",
+            |r#loop, req, cb| uv_fs_utime(r#loop, req, path.as_ptr(), atime, mtime, cb),
+            unit,
+        )
+    }
+}
@@ -0,0 +1,395 @@
+//! Copying files, and sending them over TCP connections.
+//!
+//! # Example
+//!
+//! ```
+//! # use libuv_sys2::event_loop::Loop;
+//! # use libuv_sys2::fs::copy::{self, copy_file_blocking};
+//! # use libuv_sys2::fs::temp::TempDir;
+//! # use libuv_sys2::fs::File;
+//! # use libuv_sys2::stream::allocator::ReusableAllocator;
+//! # use libuv_sys2::stream::Stream;
+//! # use libuv_sys2::tcp::Tcp;
+//! # use libuv_sys2::{
+//! #     uv_errno_t_UV_EEXIST, uv_run_mode_UV_RUN_DEFAULT, UV_FS_COPYFILE_EXCL,
+//! #     UV_FS_COPYFILE_FICLONE,
+//! # };
+//! # use std::cell::{Cell, RefCell};
+//! # use std::rc::Rc;
+//! #
+//! let r#loop = Loop::new().unwrap();
+//! let dir = TempDir::create_blocking(&r#loop).unwrap();
+//! let data: Vec<u8> = (0..1_000_000u32).map(|i| i as u8).collect();
+//! let src = dir.path().join("src");
+//! File::create_blocking(&r#loop, &src).unwrap().write_blocking(&data, None).unwrap();
+//!
+//! // clone the file if the file system can, and copy it otherwise
+//! let dst = dir.path().join("dst");
+//! copy_file_blocking(&r#loop, &src, &dst, UV_FS_COPYFILE_FICLONE).unwrap();
+//! let copied = File::open_blocking(&r#loop, &dst).unwrap().read_blocking(2_000_000, None);
+//! assert!(copied.unwrap() == data);
+//! let err = copy_file_blocking(&r#loop, &src, &dst, UV_FS_COPYFILE_EXCL).unwrap_err();
+//! assert_eq!(err.code(), uv_errno_t_UV_EEXIST);
+//!
+//! // send the middle of the file over a loopback connection
+//! let server = Tcp::new(&r#loop).unwrap();
+//! server.bind(&"127.0.0.1:0".parse().unwrap(), 0).unwrap();
+//! let received = Rc::new(RefCell::new(Vec::new()));
+//! let clients = Rc::new(RefCell::new(Vec::new()));
+//! let (buffer, accepted) = (received.clone(), clients.clone());
+//! server
+//!     .listen(1, move |_, client| {
+//!         let client = client.unwrap();
+//!         let buffer = buffer.clone();
+//!         client
+//!             .read_start(ReusableAllocator::default(), move |client, data| match data {
+//!                 Ok(data) => buffer.borrow_mut().extend_from_slice(data),
+//!                 Err(_) => client.read_stop().unwrap(),
+//!             })
+//!             .unwrap();
+//!         accepted.borrow_mut().push(client);
+//!     })
+//!     .unwrap();
+//!
+//! let file = File::open_blocking(&r#loop, &src).unwrap();
+//! let client = Tcp::new(&r#loop).unwrap();
+//! let progress = Rc::new(Cell::new(0));
+//! let sent = Rc::new(Cell::new(None));
+//! let (reported, total) = (progress.clone(), sent.clone());
+//! client
+//!     .connect(&server.sockname().unwrap(), move |client, result| {
+//!         result.unwrap();
+//!         let on_progress = move |sent| {
+//!             assert!(sent > reported.get());
+//!             reported.set(sent);
+//!         };
+//!         let on_sent = move |result: libuv_sys2::error::Result<u64>| {
+//!             total.set(Some(result.unwrap()));
+//!         };
+//!         copy::send_file(client, &file, 1000, 900_000, on_progress, on_sent).unwrap();
+//!     })
+//!     .unwrap();
+//!
+//! while sent.get().is_none() || received.borrow().len() < 900_000 {
+//!     r#loop.run(libuv_sys2::uv_run_mode_UV_RUN_ONCE);
+//! }
+//! assert_eq!(sent.get(), Some(900_000));
+//! assert_eq!(progress.get(), 900_000);
+//! assert!(*received.borrow() == data[1000..901_000]);
+//!
+//! // the callback comes from the loop, even if there's nothing to send
+//! let file = File::open_blocking(&r#loop, &src).unwrap();
+//! let empty = Rc::new(Cell::new(None));
+//! let result = empty.clone();
+//! let on_sent = move |sent: libuv_sys2::error::Result<u64>| result.set(Some(sent.unwrap()));
+//! copy::send_file(&client, &file, 0, 0, |_| {}, on_sent).unwrap();
+//! assert_eq!(empty.get(), None);
+//! while empty.get().is_none() {
+//!     r#loop.run(libuv_sys2::uv_run_mode_UV_RUN_ONCE);
+//! }
+//! assert_eq!(empty.get(), Some(0));
+//!
+//! drop((client, server));
+//! clients.borrow_mut().clear();
+//! r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+//! ```
+
+use super::{count, cpath, unit, Async, Blocking, File, Mode};
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::stream::{self, sealed::StreamHandle, Stream};
+use crate::tcp::Tcp;
+use crate::{uv_errno_t_UV_ECANCELED, uv_fs_copyfile, uv_handle_t, uv_is_closing, uv_stream_t};
+use std::cell::RefCell;
+use std::mem::ManuallyDrop;
+use std::os::raw::c_int;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+
+/// The most that send_file() passes to a single uv_fs_sendfile call, so that progress is
+/// reported regularly.
+pub const SENDFILE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// The size of the chunks that send_file() reads and writes itself when it can't use
+/// uv_fs_sendfile.
+pub const WRITE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Copy the file at `src` to `dst`, replacing `dst` if it exists. `flags` may include:
+///
+/// * UV_FS_COPYFILE_EXCL: fail with UV_EEXIST if `dst` exists.
+/// * UV_FS_COPYFILE_FICLONE: make a copy-on-write clone (a reflink), if the file system
+///   supports it, and fall back to copying the data if it doesn't.
+/// * UV_FS_COPYFILE_FICLONE_FORCE: make a copy-on-write clone, or fail without falling back.
+pub fn copy_file<P, Q, F>(r#loop: &Loop, src: P, dst: Q, flags: u32, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnOnce(Result<()>) + 'static,
+{
+    copy_file_with(r#loop, src.as_ref(), dst.as_ref(), flags, Async(cb))
+}
+
+pub fn copy_file_blocking<P, Q>(r#loop: &Loop, src: P, dst: Q, flags: u32) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    copy_file_with(r#loop, src.as_ref(), dst.as_ref(), flags, Blocking)
+}
+
+fn copy_file_with<M: Mode<()>>(
+    r#loop: &Loop,
+    src: &Path,
+    dst: &Path,
+    flags: u32,
+    mode: M,
+) -> Result<M::Output> {
+    let src = cpath("uv_fs_copyfile", src)?;
+    let dst = cpath("uv_fs_copyfile", dst)?;
+    let flags = flags as c_int;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_copyfile",
+            |r#loop, req, cb| uv_fs_copyfile(r#loop, req, src.as_ptr(), dst.as_ptr(), flags, cb),
+            unit,
+        )
+    }
+}
+
+/// Send `len` bytes of `file`, starting at `offset`, to `out`.
+///
+/// The data is sent with uv_fs_sendfile, which copies it inside the kernel, one chunk at a time.
+/// Whenever the socket's buffer is full, or writes that were queued on `out` are still pending,
+/// a chunk is read and written through `out` instead, which keeps the data in order. (On
+/// Windows, every chunk is sent that way.) Other writes shouldn't be queued on `out` until the
+/// send has finished.
+///
+/// `progress` is called with the total number of bytes sent so far after each chunk, and `cb` is
+/// called with the total once the send has finished, which is less than `len` if the file ended
+/// first. `cb` is always called from the loop, even if there's nothing to send. If `out` is
+/// closed before then, the send fails with UV_ECANCELED once the chunk in flight has finished.
+pub fn send_file<P, F>(
+    out: &Tcp,
+    file: &File,
+    offset: u64,
+    len: u64,
+    progress: P,
+    cb: F,
+) -> Result<()>
+where
+    P: FnMut(u64) + 'static,
+    F: FnOnce(Result<u64>) + 'static,
+{
+    let stream = out.as_stream_ptr();
+    let send = Rc::new(RefCell::new(Sending {
+        stream,
+        alive: unsafe { stream::alive(stream) },
+        file: File {
+            inner: file.inner.clone(),
+        },
+        offset,
+        remaining: len,
+        sent: 0,
+        progress,
+        cb: Some(cb),
+    }));
+
+    // A zero-length read stands in for the send, so that `cb` isn't called from in here.
+    if len == 0 {
+        let done = send.clone();
+        return file.read(0, Some(offset), move |result| {
+            finish(&done, result.map(|_| ()));
+        });
+    }
+    next_chunk(&send)
+}
+
+struct Sending<P, F> {
+    stream: *mut uv_stream_t,
+    alive: Weak<()>,
+    file: File,
+    offset: u64,
+    remaining: u64,
+    sent: u64,
+    progress: P,
+    cb: Option<F>,
+}
+
+type SharedSend<P, F> = Rc<RefCell<Sending<P, F>>>;
+
+impl<P, F> Sending<P, F> {
+    /// The stream that the file is being sent to, unless it's been closed.
+    fn out(&self) -> Result<ManuallyDrop<Tcp>> {
+        let closing = match self.alive.upgrade() {
+            Some(_) => unsafe { uv_is_closing(self.stream as *const uv_handle_t) != 0 },
+            None => true,
+        };
+        if closing {
+            return Err(UvError::new("uv_fs_sendfile", uv_errno_t_UV_ECANCELED));
+        }
+        Ok(ManuallyDrop::new(unsafe {
+            Tcp::from_stream_ptr(self.stream)
+        }))
+    }
+}
+
+/// Start sending the next chunk, or finish if there's nothing left to send.
+fn next_chunk<P, F>(send: &SharedSend<P, F>) -> Result<()>
+where
+    P: FnMut(u64) + 'static,
+    F: FnOnce(Result<u64>) + 'static,
+{
+    let out = {
+        let sending = send.borrow();
+        if sending.remaining == 0 {
+            drop(sending);
+            finish(send, Ok(()));
+            return Ok(());
+        }
+        sending.out()?
+    };
+
+    #[cfg(unix)]
+    {
+        use crate::uv_stream_get_write_queue_size;
+
+        if unsafe { uv_stream_get_write_queue_size(out.as_stream_ptr()) } == 0 {
+            return sendfile_chunk(send, &out);
+        }
+    }
+    #[cfg(windows)]
+    let _ = out;
+    write_chunk(send)
+}
+
+/// Continue with the next chunk, finishing with the error if it can't be started.
+fn resume<P, F>(send: &SharedSend<P, F>)
+where
+    P: FnMut(u64) + 'static,
+    F: FnOnce(Result<u64>) + 'static,
+{
+    if let Err(err) = next_chunk(send) {
+        finish(send, Err(err));
+    }
+}
+
+/// Send a chunk with uv_fs_sendfile.
+#[cfg(unix)]
+fn sendfile_chunk<P, F>(send: &SharedSend<P, F>, out: &Tcp) -> Result<()>
+where
+    P: FnMut(u64) + 'static,
+    F: FnOnce(Result<u64>) + 'static,
+{
+    use crate::{
+        uv_errno_t_UV_EAGAIN, uv_errno_t_UV_EIO, uv_fileno, uv_fs_sendfile, uv_os_fd_t,
+        uv_translate_sys_error,
+    };
+    use std::os::unix::io::{AsRawFd, BorrowedFd};
+
+    let mut out_fd: uv_os_fd_t = -1;
+    unsafe { uvret!(uv_fileno(out.as_ptr() as *const uv_handle_t, &mut out_fd)) }?;
+
+    // The chunk is sent on the thread pool, where closing `out` can't stop it. A duplicate of the
+    // socket, which the request owns, keeps the descriptor from being reused in the meantime.
+    let dup = unsafe { BorrowedFd::borrow_raw(out_fd) }
+        .try_clone_to_owned()
+        .map_err(|err| {
+            let code = err
+                .raw_os_error()
+                .map_or(uv_errno_t_UV_EIO, |errno| unsafe {
+                    uv_translate_sys_error(errno)
+                });
+            UvError::new("dup", code)
+        })?;
+    let out_fd = dup.as_raw_fd();
+    let sending = send.borrow();
+    let in_fd = sending.file.as_raw();
+    let offset = sending.offset.min(i64::MAX as u64) as i64;
+    let len = sending.remaining.min(SENDFILE_CHUNK_SIZE as u64) as usize;
+    let next = send.clone();
+    let done = move |result: Result<usize>| match result {
+        Ok(0) => finish(&next, Ok(())),
+        Ok(sent) => {
+            advance(&next, sent);
+            resume(&next);
+        }
+        // The socket's buffer is full: write a chunk through the stream, which waits for room.
+        Err(err) if err.code() == uv_errno_t_UV_EAGAIN => {
+            if let Err(err) = write_chunk(&next) {
+                finish(&next, Err(err));
+            }
+        }
+        Err(err) => finish(&next, Err(err)),
+    };
+    unsafe {
+        sending.file.run(
+            Async(done),
+            "uv_fs_sendfile",
+            |r#loop, req, cb| uv_fs_sendfile(r#loop, req, out_fd, in_fd, offset, len, cb),
+            move |req| {
+                let _out = dup;
+                count(req)
+            },
+        )
+    }
+}
+
+/// Send a chunk by reading it from the file and writing it to the stream.
+fn write_chunk<P, F>(send: &SharedSend<P, F>) -> Result<()>
+where
+    P: FnMut(u64) + 'static,
+    F: FnOnce(Result<u64>) + 'static,
+{
+    let sending = send.borrow();
+    let len = sending.remaining.min(WRITE_CHUNK_SIZE as u64) as usize;
+    let next = send.clone();
+    sending.file.read(len, Some(sending.offset), move |result| {
+        let data = match result {
+            Ok(data) if data.is_empty() => return finish(&next, Ok(())),
+            Ok(data) => data,
+            Err(err) => return finish(&next, Err(err)),
+        };
+        let len = data.len();
+        let write = next.clone();
+        let written = next.borrow().out().and_then(|out| {
+            out.write(data, move |_, result| match result {
+                Ok(()) => {
+                    advance(&write, len);
+                    resume(&write);
+                }
+                Err(err) => finish(&write, Err(err)),
+            })
+        });
+        if let Err(err) = written {
+            finish(&next, Err(err));
+        }
+    })
+}
+
+/// Record that `sent` more bytes have been sent, and report the progress.
+fn advance<P, F>(send: &SharedSend<P, F>, sent: usize)
+where
+    P: FnMut(u64),
+{
+    let sending = &mut *send.borrow_mut();
+    let sent = (sent as u64).min(sending.remaining);
+    sending.offset += sent;
+    sending.remaining -= sent;
+    sending.sent += sent;
+    (sending.progress)(sending.sent);
+}
+
+/// Call the completion callback, once.
+fn finish<P, F>(send: &SharedSend<P, F>, result: Result<()>)
+where
+    F: FnOnce(Result<u64>),
+{
+    let (cb, sent) = {
+        let sending = &mut *send.borrow_mut();
+        (sending.cb.take(), sending.sent)
+    };
+    if let Some(cb) = cb {
+        cb(result.map(|()| sent));
+    }
+}
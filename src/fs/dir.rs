@@ -0,0 +1,533 @@
+//! Reading directories.
+//!
+//! Dir streams a directory's entries in batches with uv_fs_opendir, uv_fs_readdir and
+//! uv_fs_closedir, so that only one batch is in memory at a time. scandir() lists a whole
+//! directory at once with uv_fs_scandir. walk() visits every entry in a directory tree, using
+//! either of them.
+//!
+//! # Example
+//!
+//! ```
+//! # use libuv_sys2::event_loop::Loop;
+//! # use libuv_sys2::fs::dir::{self, Dir, Walker};
+//! # use libuv_sys2::{
+//! #     uv_dirent_type_t_UV_DIRENT_DIR, uv_dirent_type_t_UV_DIRENT_FILE, uv_errno_t_UV_ENOENT,
+//! #     uv_run_mode_UV_RUN_DEFAULT,
+//! # };
+//! # use std::cell::{Cell, RefCell};
+//! # use std::ffi::OsString;
+//! # use std::rc::Rc;
+//! #
+//! let r#loop = Loop::new().unwrap();
+//! let root = std::env::temp_dir().join(format!("libuv-sys2-dir-{}", std::process::id()));
+//! std::fs::create_dir_all(root.join("a/b")).unwrap();
+//! std::fs::write(root.join("a/b/c.txt"), "c").unwrap();
+//! std::fs::write(root.join("d.txt"), "d").unwrap();
+//!
+//! // read one entry at a time
+//! let dir = Dir::open_blocking(&r#loop, &root).unwrap();
+//! dir.set_batch_size(1);
+//! let mut entries = Vec::new();
+//! loop {
+//!     let batch = dir.read_blocking().unwrap();
+//!     if batch.is_empty() {
+//!         break;
+//!     }
+//!     assert_eq!(batch.len(), 1);
+//!     entries.extend(batch);
+//! }
+//! dir.close_blocking().unwrap();
+//! entries.sort();
+//! assert_eq!(
+//!     entries,
+//!     vec![
+//!         (OsString::from("a"), uv_dirent_type_t_UV_DIRENT_DIR),
+//!         (OsString::from("d.txt"), uv_dirent_type_t_UV_DIRENT_FILE),
+//!     ]
+//! );
+//!
+//! // scandir lists the same entries in one go
+//! let mut scanned = dir::scandir_blocking(&r#loop, &root).unwrap();
+//! scanned.sort();
+//! assert_eq!(scanned, entries);
+//!
+//! // walk the whole tree
+//! let paths = Rc::new(RefCell::new(Vec::new()));
+//! let done = Rc::new(Cell::new(false));
+//! let (walked, finished) = (paths.clone(), done.clone());
+//! dir::walk(
+//!     &r#loop,
+//!     &root,
+//!     move |path, kind| walked.borrow_mut().push((path.to_owned(), kind.unwrap())),
+//!     move || finished.set(true),
+//! )
+//! .unwrap();
+//! r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+//! assert!(done.get());
+//!
+//! let mut paths = paths.take();
+//! paths.sort();
+//! assert_eq!(
+//!     paths,
+//!     vec![
+//!         (root.join("a"), uv_dirent_type_t_UV_DIRENT_DIR),
+//!         (root.join("a/b"), uv_dirent_type_t_UV_DIRENT_DIR),
+//!         (root.join("a/b/c.txt"), uv_dirent_type_t_UV_DIRENT_FILE),
+//!         (root.join("d.txt"), uv_dirent_type_t_UV_DIRENT_FILE),
+//!     ]
+//! );
+//! std::fs::remove_dir_all(&root).unwrap();
+//!
+//! // a root that can't be listed is reported to the callback, like any other directory
+//! for &scandir in &[false, true] {
+//!     let errors = Rc::new(RefCell::new(Vec::new()));
+//!     let done = Rc::new(Cell::new(false));
+//!     let (reported, finished) = (errors.clone(), done.clone());
+//!     Walker::new()
+//!         .scandir(scandir)
+//!         .walk(
+//!             &r#loop,
+//!             &root,
+//!             move |path, kind| reported.borrow_mut().push((path.to_owned(), kind.unwrap_err())),
+//!             move || finished.set(true),
+//!         )
+//!         .unwrap();
+//!     r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+//!     assert!(done.get());
+//!     let errors = errors.take();
+//!     assert_eq!(errors.len(), 1);
+//!     assert_eq!(errors[0].0, root);
+//!     assert_eq!(errors[0].1.code(), uv_errno_t_UV_ENOENT);
+//! }
+//! ```
+
+use super::{cpath, from_cpath, Async, Blocking, Mode};
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::{
+    uv_dir_t, uv_dirent_t, uv_dirent_type_t, uv_dirent_type_t_UV_DIRENT_DIR,
+    uv_dirent_type_t_UV_DIRENT_UNKNOWN, uv_errno_t_UV_EALREADY, uv_errno_t_UV_EBUSY,
+    uv_fs_closedir, uv_fs_get_ptr, uv_fs_get_result, uv_fs_opendir, uv_fs_readdir,
+    uv_fs_req_cleanup, uv_fs_scandir, uv_fs_scandir_next, uv_fs_t,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::rc::Rc;
+
+/// The number of entries that a Dir reads at a time, unless it's told otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// A directory entry's name, and its type. The type is UV_DIRENT_UNKNOWN if the file system
+/// doesn't report it.
+pub type DirEntry = (OsString, uv_dirent_type_t);
+
+unsafe fn entry(dirent: &uv_dirent_t) -> DirEntry {
+    (from_cpath(dirent.name).into_os_string(), dirent.type_)
+}
+
+struct DirInner {
+    dir: Cell<*mut uv_dir_t>,
+    r#loop: Loop,
+    batch_size: Cell<usize>,
+    reading: Cell<bool>,
+}
+
+impl Drop for DirInner {
+    fn drop(&mut self) {
+        let dir = self.dir.get();
+        if !dir.is_null() {
+            unsafe {
+                let mut req: uv_fs_t = mem::zeroed();
+                uv_fs_closedir(self.r#loop.as_ptr(), &mut req, dir, None);
+                uv_fs_req_cleanup(&mut req);
+            }
+        }
+    }
+}
+
+/// The array of entries that uv_fs_readdir fills in. It's attached to the uv_dir_t for as long as
+/// the read is in progress, and libuv frees the entries' names when the request is cleaned up,
+/// so the Batch must outlive both.
+struct Batch {
+    dir: Rc<DirInner>,
+    entries: Vec<uv_dirent_t>,
+}
+
+impl Batch {
+    unsafe fn new(dir: Rc<DirInner>) -> Batch {
+        let empty = uv_dirent_t {
+            name: ptr::null(),
+            type_: uv_dirent_type_t_UV_DIRENT_UNKNOWN,
+        };
+        let mut entries = vec![empty; dir.batch_size.get()];
+        let raw = dir.dir.get();
+        (*raw).dirents = entries.as_mut_ptr();
+        (*raw).nentries = entries.len();
+        dir.reading.set(true);
+        Batch { dir, entries }
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        let raw = self.dir.dir.get();
+        unsafe {
+            (*raw).dirents = ptr::null_mut();
+            (*raw).nentries = 0;
+        }
+        self.dir.reading.set(false);
+    }
+}
+
+/// An open directory, which is read a batch of entries at a time.
+///
+/// The directory is closed when it's dropped, once a read that's still in progress has
+/// finished. Closing it that way blocks while the directory is closed; use close() to avoid that.
+pub struct Dir {
+    inner: Rc<DirInner>,
+}
+
+impl Dir {
+    /// Open the directory at `path`, and call `cb` with it.
+    pub fn open<P, F>(r#loop: &Loop, path: P, cb: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(Result<Dir>) + 'static,
+    {
+        Dir::open_with(r#loop, path.as_ref(), Async(cb))
+    }
+
+    pub fn open_blocking<P: AsRef<Path>>(r#loop: &Loop, path: P) -> Result<Dir> {
+        Dir::open_with(r#loop, path.as_ref(), Blocking)
+    }
+
+    fn open_with<M: Mode<Dir>>(r#loop: &Loop, path: &Path, mode: M) -> Result<M::Output> {
+        let path = cpath("uv_fs_opendir", path)?;
+        let dir_loop = r#loop.clone();
+        unsafe {
+            mode.run(
+                r#loop,
+                "uv_fs_opendir",
+                |r#loop, req, cb| uv_fs_opendir(r#loop, req, path.as_ptr(), cb),
+                move |req| Dir {
+                    inner: Rc::new(DirInner {
+                        dir: Cell::new(uv_fs_get_ptr(req) as *mut uv_dir_t),
+                        r#loop: dir_loop,
+                        batch_size: Cell::new(DEFAULT_BATCH_SIZE),
+                        reading: Cell::new(false),
+                    }),
+                },
+            )
+        }
+    }
+
+    /// The most entries that a read returns.
+    pub fn batch_size(&self) -> usize {
+        self.inner.batch_size.get()
+    }
+
+    /// Set the most entries that a read returns. This takes effect from the next read.
+    pub fn set_batch_size(&self, size: usize) {
+        self.inner.batch_size.set(size.max(1));
+    }
+
+    /// Read the next batch of entries, which is empty once every entry has been read. The
+    /// entries are in no particular order, and don't include "." and "..".
+    ///
+    /// Only one read can be in progress at a time; starting another fails with UV_EALREADY.
+    pub fn read<F>(&self, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<Vec<DirEntry>>) + 'static,
+    {
+        self.read_with(Async(cb))
+    }
+
+    pub fn read_blocking(&self) -> Result<Vec<DirEntry>> {
+        self.read_with(Blocking)
+    }
+
+    fn read_with<M: Mode<Vec<DirEntry>>>(&self, mode: M) -> Result<M::Output> {
+        if self.inner.reading.get() {
+            return Err(UvError::new("uv_fs_readdir", uv_errno_t_UV_EALREADY));
+        }
+        let dir = self.inner.dir.get();
+        unsafe {
+            let batch = Batch::new(self.inner.clone());
+            mode.run(
+                &self.inner.r#loop,
+                "uv_fs_readdir",
+                |r#loop, req, cb| uv_fs_readdir(r#loop, req, dir, cb),
+                move |req| {
+                    let read = uv_fs_get_result(req) as usize;
+                    let entries = batch.entries[..read].iter().map(|e| entry(e)).collect();
+                    // Free the names now, while the array that they're in is still alive.
+                    // Cleaning up the request again afterwards does nothing.
+                    uv_fs_req_cleanup(req);
+                    entries
+                },
+            )
+        }
+    }
+
+    /// Close the directory without blocking. Fails with UV_EBUSY if a read is in progress, in
+    /// which case the directory is closed once the read has finished.
+    pub fn close<F>(self, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<()>) + 'static,
+    {
+        self.close_with(Async(cb))
+    }
+
+    pub fn close_blocking(self) -> Result<()> {
+        self.close_with(Blocking)
+    }
+
+    fn close_with<M: Mode<()>>(self, mode: M) -> Result<M::Output> {
+        if self.inner.reading.get() {
+            return Err(UvError::new("uv_fs_closedir", uv_errno_t_UV_EBUSY));
+        }
+        let dir = self.inner.dir.replace(ptr::null_mut());
+        unsafe {
+            mode.run(
+                &self.inner.r#loop,
+                "uv_fs_closedir",
+                |r#loop, req, cb| uv_fs_closedir(r#loop, req, dir, cb),
+                |_| (),
+            )
+        }
+    }
+}
+
+/// List every entry in a directory at once, sorted by name, except for "." and "..".
+pub fn scandir<P, F>(r#loop: &Loop, path: P, cb: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(Result<Vec<DirEntry>>) + 'static,
+{
+    scandir_with(r#loop, path.as_ref(), Async(cb))
+}
+
+pub fn scandir_blocking<P: AsRef<Path>>(r#loop: &Loop, path: P) -> Result<Vec<DirEntry>> {
+    scandir_with(r#loop, path.as_ref(), Blocking)
+}
+
+fn scandir_with<M>(r#loop: &Loop, path: &Path, mode: M) -> Result<M::Output>
+where
+    M: Mode<Vec<DirEntry>>,
+{
+    let path = cpath("uv_fs_scandir", path)?;
+    unsafe {
+        mode.run(
+            r#loop,
+            "uv_fs_scandir",
+            |r#loop, req, cb| uv_fs_scandir(r#loop, req, path.as_ptr(), 0, cb),
+            |req| {
+                // Each name is freed by the next call to uv_fs_scandir_next, so it's copied
+                // straight away.
+                let mut entries = Vec::new();
+                let mut dirent: uv_dirent_t = mem::zeroed();
+                while uv_fs_scandir_next(req, &mut dirent) == 0 {
+                    entries.push(entry(&dirent));
+                }
+                entries
+            },
+        )
+    }
+}
+
+/// Options for walking a directory tree.
+#[derive(Clone, Debug)]
+pub struct Walker {
+    batch_size: usize,
+    scandir: bool,
+}
+
+impl Default for Walker {
+    fn default() -> Walker {
+        Walker::new()
+    }
+}
+
+impl Walker {
+    /// Walk with Dir, reading DEFAULT_BATCH_SIZE entries at a time.
+    pub fn new() -> Walker {
+        Walker {
+            batch_size: DEFAULT_BATCH_SIZE,
+            scandir: false,
+        }
+    }
+
+    /// Read each directory `size` entries at a time.
+    pub fn batch_size(&mut self, size: usize) -> &mut Walker {
+        self.batch_size = size;
+        self
+    }
+
+    /// List each directory at once with scandir() rather than streaming it with a Dir, which
+    /// takes fewer requests but holds a whole directory's entries in memory.
+    pub fn scandir(&mut self, scandir: bool) -> &mut Walker {
+        self.scandir = scandir;
+        self
+    }
+
+    /// Visit every entry under `root`, breadth first. `cb` is called with the path and type of
+    /// each entry, or with the path of a directory and the error that stopped it from being
+    /// read. `done` is called once the whole tree has been walked.
+    ///
+    /// Symbolic links aren't followed, and nor are entries whose type is UV_DIRENT_UNKNOWN. If
+    /// `root` itself can't be listed, ie, because it doesn't exist, `cb` is called with `root` and
+    /// the error, followed by `done`, like any other directory. An error is only returned if the
+    /// walk can't be started at all, ie, because `root` contains a NUL byte, in which case neither
+    /// callback is called.
+    pub fn walk<P, F, D>(&self, r#loop: &Loop, root: P, cb: F, done: D) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&Path, Result<uv_dirent_type_t>) + 'static,
+        D: FnOnce() + 'static,
+    {
+        let walk = Rc::new(RefCell::new(Walk {
+            r#loop: r#loop.clone(),
+            options: self.clone(),
+            pending: VecDeque::new(),
+            cb,
+            done: Some(done),
+        }));
+        list(&walk, root.as_ref().to_owned())
+    }
+}
+
+/// Walk the tree under `root` with the default options. See Walker::walk().
+pub fn walk<P, F, D>(r#loop: &Loop, root: P, cb: F, done: D) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Path, Result<uv_dirent_type_t>) + 'static,
+    D: FnOnce() + 'static,
+{
+    Walker::new().walk(r#loop, root, cb, done)
+}
+
+struct Walk<F, D> {
+    r#loop: Loop,
+    options: Walker,
+    /// Directories that have been found but not listed yet.
+    pending: VecDeque<PathBuf>,
+    cb: F,
+    done: Option<D>,
+}
+
+type SharedWalk<F, D> = Rc<RefCell<Walk<F, D>>>;
+
+/// Start listing `dir`.
+fn list<F, D>(walk: &SharedWalk<F, D>, dir: PathBuf) -> Result<()>
+where
+    F: FnMut(&Path, Result<uv_dirent_type_t>) + 'static,
+    D: FnOnce() + 'static,
+{
+    let (r#loop, options) = {
+        let walk = walk.borrow();
+        (walk.r#loop.clone(), walk.options.clone())
+    };
+    let walk = walk.clone();
+    let path = dir.clone();
+    if options.scandir {
+        scandir(&r#loop, path, move |result| {
+            match result {
+                Ok(entries) => visit(&walk, &dir, entries),
+                Err(err) => report(&walk, &dir, err),
+            }
+            next(&walk);
+        })
+    } else {
+        Dir::open(&r#loop, path, move |result| match result {
+            Ok(handle) => {
+                handle.set_batch_size(options.batch_size);
+                read(walk, dir, handle);
+            }
+            Err(err) => {
+                report(&walk, &dir, err);
+                next(&walk);
+            }
+        })
+    }
+}
+
+/// Read the next batch of entries from `handle`, which is `dir`.
+fn read<F, D>(walk: SharedWalk<F, D>, dir: PathBuf, handle: Dir)
+where
+    F: FnMut(&Path, Result<uv_dirent_type_t>) + 'static,
+    D: FnOnce() + 'static,
+{
+    let reader = Dir {
+        inner: handle.inner.clone(),
+    };
+    let on_error = (walk.clone(), dir.clone());
+    let started = reader.read(move |result| match result {
+        Ok(entries) if !entries.is_empty() => {
+            visit(&walk, &dir, entries);
+            read(walk, dir, handle);
+        }
+        Ok(_) => {
+            let _ = handle.close(|_| ());
+            next(&walk);
+        }
+        Err(err) => {
+            report(&walk, &dir, err);
+            next(&walk);
+        }
+    });
+    if let Err(err) = started {
+        let (walk, dir) = on_error;
+        report(&walk, &dir, err);
+        next(&walk);
+    }
+}
+
+fn visit<F, D>(walk: &SharedWalk<F, D>, dir: &Path, entries: Vec<DirEntry>)
+where
+    F: FnMut(&Path, Result<uv_dirent_type_t>),
+{
+    let walk = &mut *walk.borrow_mut();
+    for (name, kind) in entries {
+        let path = dir.join(name);
+        (walk.cb)(&path, Ok(kind));
+        if kind == uv_dirent_type_t_UV_DIRENT_DIR {
+            walk.pending.push_back(path);
+        }
+    }
+}
+
+fn report<F, D>(walk: &SharedWalk<F, D>, dir: &Path, err: UvError)
+where
+    F: FnMut(&Path, Result<uv_dirent_type_t>),
+{
+    let walk = &mut *walk.borrow_mut();
+    (walk.cb)(dir, Err(err));
+}
+
+/// List the next pending directory, or finish the walk if there aren't any.
+fn next<F, D>(walk: &SharedWalk<F, D>)
+where
+    F: FnMut(&Path, Result<uv_dirent_type_t>) + 'static,
+    D: FnOnce() + 'static,
+{
+    loop {
+        let dir = walk.borrow_mut().pending.pop_front();
+        match dir {
+            Some(dir) => match list(walk, dir.clone()) {
+                Ok(()) => return,
+                Err(err) => report(walk, &dir, err),
+            },
+            None => {
+                let done = walk.borrow_mut().done.take();
+                if let Some(done) = done {
+                    done();
+                }
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Temporary files and directories, which are removed when they're dropped.
+//!
+//! # Example
+//!
+//! ```
+//! # use libuv_sys2::event_loop::Loop;
+//! # use libuv_sys2::fs::temp::{TempDir, TempFile};
+//! # use libuv_sys2::fs::{self, File};
+//! # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+//! # use std::cell::RefCell;
+//! # use std::rc::Rc;
+//! #
+//! let r#loop = Loop::new().unwrap();
+//! let dir = TempDir::create_blocking(&r#loop).unwrap();
+//! fs::mkdir_blocking(&r#loop, dir.path().join("nested"), 0o755).unwrap();
+//! let file = File::create_blocking(&r#loop, dir.path().join("nested/file.txt")).unwrap();
+//! file.write_blocking(b"scratch", None).unwrap();
+//! drop(file);
+//!
+//! // dropping the directory removes everything in it
+//! let path = dir.path().to_owned();
+//! drop(dir);
+//! assert!(fs::stat_blocking(&r#loop, &path).is_err());
+//!
+//! // unless it's persisted
+//! let kept = Rc::new(RefCell::new(None));
+//! let slot = kept.clone();
+//! TempDir::create(&r#loop, move |dir| *slot.borrow_mut() = Some(dir.unwrap())).unwrap();
+//! r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+//! let path = kept.take().unwrap().persist();
+//! assert!(fs::stat_blocking(&r#loop, &path).unwrap().is_dir());
+//!
+//! // temporary files can go anywhere
+//! let file = TempFile::create_in_blocking(&r#loop, &path, "log-").unwrap();
+//! assert!(file.path().file_name().unwrap().to_str().unwrap().starts_with("log-"));
+//! assert_eq!(file.file().write_blocking(b"hello", None).unwrap(), 5);
+//! let file_path = file.path().to_owned();
+//! drop(file);
+//! assert!(fs::stat_blocking(&r#loop, &file_path).is_err());
+//! fs::rmdir_blocking(&r#loop, &path).unwrap();
+//!
+//! // anything that's already gone doesn't matter
+//! let dir = TempDir::create_blocking(&r#loop).unwrap();
+//! std::fs::remove_dir_all(dir.path()).unwrap();
+//! dir.close().unwrap();
+//! ```
+
+use super::dir::scandir_blocking;
+use super::{
+    cpath, from_cpath, lstat_blocking, rmdir_blocking, unlink_blocking, Async, Blocking, File, Mode,
+};
+use crate::error::Result;
+use crate::event_loop::Loop;
+use crate::{
+    uv_dirent_type_t, uv_dirent_type_t_UV_DIRENT_DIR, uv_dirent_type_t_UV_DIRENT_UNKNOWN,
+    uv_errno_t_UV_ENOENT, uv_file, uv_fs_get_path, uv_fs_get_result, uv_fs_mkdtemp, uv_fs_mkstemp,
+};
+use std::env;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// The prefix of the names of temporary files and directories, unless they're given one.
+pub const DEFAULT_PREFIX: &str = ".tmp";
+
+/// The template for uv_fs_mkdtemp and uv_fs_mkstemp, which replace the trailing Xs.
+fn template(dir: &Path, prefix: &str) -> PathBuf {
+    dir.join(format!("{}XXXXXX", prefix))
+}
+
+/// Remove the directory `path` and everything in it, without following symbolic links. An entry
+/// that can't be removed doesn't stop the others from being removed; the first error is returned
+/// once everything else has been tried. Entries that have already gone don't count as errors.
+fn remove_all(r#loop: &Loop, path: &Path) -> Result<()> {
+    let mut result = Ok(());
+    match scandir_blocking(r#loop, path) {
+        Ok(entries) => {
+            for (name, kind) in entries {
+                keep_first(&mut result, remove_entry(r#loop, &path.join(name), kind));
+            }
+        }
+        Err(err) => keep_first(&mut result, Err(err)),
+    }
+    keep_first(&mut result, rmdir_blocking(r#loop, path));
+    result
+}
+
+/// Remove a directory entry of type `kind`, which is looked up if it's unknown.
+fn remove_entry(r#loop: &Loop, entry: &Path, mut kind: uv_dirent_type_t) -> Result<()> {
+    if kind == uv_dirent_type_t_UV_DIRENT_UNKNOWN && lstat_blocking(r#loop, entry)?.is_dir() {
+        kind = uv_dirent_type_t_UV_DIRENT_DIR;
+    }
+    if kind == uv_dirent_type_t_UV_DIRENT_DIR {
+        remove_all(r#loop, entry)
+    } else {
+        unlink_blocking(r#loop, entry)
+    }
+}
+
+/// Record the error from `next` in `result`, unless there's already one there, or it's UV_ENOENT.
+fn keep_first(result: &mut Result<()>, next: Result<()>) {
+    if let Err(err) = next {
+        if result.is_ok() && err.code() != uv_errno_t_UV_ENOENT {
+            *result = Err(err);
+        }
+    }
+}
+
+/// A directory with a unique name, which is removed, along with everything in it, when it's
+/// dropped.
+pub struct TempDir {
+    path: Option<PathBuf>,
+    r#loop: Loop,
+}
+
+impl TempDir {
+    /// Create a directory in the system's temporary directory.
+    pub fn create<F>(r#loop: &Loop, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<TempDir>) + 'static,
+    {
+        TempDir::create_in(r#loop, env::temp_dir(), DEFAULT_PREFIX, cb)
+    }
+
+    pub fn create_blocking(r#loop: &Loop) -> Result<TempDir> {
+        TempDir::create_in_blocking(r#loop, env::temp_dir(), DEFAULT_PREFIX)
+    }
+
+    /// Create a directory in `dir`, with a name that starts with `prefix`.
+    pub fn create_in<P, F>(r#loop: &Loop, dir: P, prefix: &str, cb: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(Result<TempDir>) + 'static,
+    {
+        TempDir::create_with(r#loop, dir.as_ref(), prefix, Async(cb))
+    }
+
+    pub fn create_in_blocking<P>(r#loop: &Loop, dir: P, prefix: &str) -> Result<TempDir>
+    where
+        P: AsRef<Path>,
+    {
+        TempDir::create_with(r#loop, dir.as_ref(), prefix, Blocking)
+    }
+
+    fn create_with<M>(r#loop: &Loop, dir: &Path, prefix: &str, mode: M) -> Result<M::Output>
+    where
+        M: Mode<TempDir>,
+    {
+        let template = cpath("uv_fs_mkdtemp", &template(dir, prefix))?;
+        let dir_loop = r#loop.clone();
+        unsafe {
+            mode.run(
+                r#loop,
+                "uv_fs_mkdtemp",
+                |r#loop, req, cb| uv_fs_mkdtemp(r#loop, req, template.as_ptr(), cb),
+                // The generated path belongs to the request, which frees it when it's cleaned up.
+                move |req| TempDir {
+                    path: Some(from_cpath(uv_fs_get_path(req))),
+                    r#loop: dir_loop,
+                },
+            )
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path
+            .as_ref()
+            .expect("the directory hasn't been persisted")
+    }
+
+    /// Keep the directory, rather than removing it when the TempDir is dropped.
+    pub fn persist(mut self) -> PathBuf {
+        self.path
+            .take()
+            .expect("the directory hasn't been persisted")
+    }
+
+    /// Remove the directory and everything in it, returning any error, which dropping the
+    /// TempDir would ignore.
+    pub fn close(mut self) -> Result<()> {
+        let path = self
+            .path
+            .take()
+            .expect("the directory hasn't been persisted");
+        remove_all(&self.r#loop, &path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = remove_all(&self.r#loop, &path);
+        }
+    }
+}
+
+/// An open file with a unique name, which is closed and removed when it's dropped.
+pub struct TempFile {
+    file: Option<File>,
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Create a file in the system's temporary directory.
+    pub fn create<F>(r#loop: &Loop, cb: F) -> Result<()>
+    where
+        F: FnOnce(Result<TempFile>) + 'static,
+    {
+        TempFile::create_in(r#loop, env::temp_dir(), DEFAULT_PREFIX, cb)
+    }
+
+    pub fn create_blocking(r#loop: &Loop) -> Result<TempFile> {
+        TempFile::create_in_blocking(r#loop, env::temp_dir(), DEFAULT_PREFIX)
+    }
+
+    /// Create a file in `dir`, with a name that starts with `prefix`. The file is open for
+    /// reading and writing.
+    pub fn create_in<P, F>(r#loop: &Loop, dir: P, prefix: &str, cb: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(Result<TempFile>) + 'static,
+    {
+        TempFile::create_with(r#loop, dir.as_ref(), prefix, Async(cb))
+    }
+
+    pub fn create_in_blocking<P>(r#loop: &Loop, dir: P, prefix: &str) -> Result<TempFile>
+    where
+        P: AsRef<Path>,
+    {
+        TempFile::create_with(r#loop, dir.as_ref(), prefix, Blocking)
+    }
+
+    fn create_with<M>(r#loop: &Loop, dir: &Path, prefix: &str, mode: M) -> Result<M::Output>
+    where
+        M: Mode<TempFile>,
+    {
+        let template = cpath("uv_fs_mkstemp", &template(dir, prefix))?;
+        let file_loop = r#loop.clone();
+        unsafe {
+            mode.run(
+                r#loop,
+                "uv_fs_mkstemp",
+                |r#loop, req, cb| uv_fs_mkstemp(r#loop, req, template.as_ptr(), cb),
+                move |req| TempFile {
+                    file: Some(File::from_raw(&file_loop, uv_fs_get_result(req) as uv_file)),
+                    path: from_cpath(uv_fs_get_path(req)),
+                },
+            )
+        }
+    }
+
+    pub fn file(&self) -> &File {
+        self.file.as_ref().expect("the file hasn't been persisted")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keep the file, rather than removing it when the TempFile is dropped.
+    pub fn persist(mut self) -> (File, PathBuf) {
+        let file = self.file.take().expect("the file hasn't been persisted");
+        (file, mem::take(&mut self.path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // The file is closed first, since Windows won't remove open files.
+        if let Some(file) = self.file.take() {
+            let r#loop = file.get_loop();
+            drop(file);
+            let _ = unlink_blocking(&r#loop, &self.path);
+        }
+    }
+}
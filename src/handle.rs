@@ -0,0 +1,390 @@
+use crate::error::Result;
+use crate::event_loop::Loop;
+use crate::{
+    uv_close, uv_fileno, uv_handle_get_data, uv_handle_get_type, uv_handle_set_data, uv_handle_t,
+    uv_handle_type, uv_handle_type_UV_ASYNC, uv_handle_type_UV_CHECK, uv_handle_type_UV_FILE,
+    uv_handle_type_UV_FS_EVENT, uv_handle_type_UV_FS_POLL, uv_handle_type_UV_HANDLE,
+    uv_handle_type_UV_IDLE, uv_handle_type_UV_NAMED_PIPE, uv_handle_type_UV_POLL,
+    uv_handle_type_UV_PREPARE, uv_handle_type_UV_PROCESS, uv_handle_type_UV_SIGNAL,
+    uv_handle_type_UV_STREAM, uv_handle_type_UV_TCP, uv_handle_type_UV_TIMER,
+    uv_handle_type_UV_TTY, uv_handle_type_UV_UDP, uv_handle_type_UV_UNKNOWN_HANDLE,
+    uv_handle_type_name, uv_has_ref, uv_is_active, uv_is_closing, uv_loop_t, uv_os_fd_t, uv_walk,
+};
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::vec;
+
+/// The type of a handle, as returned by uv_handle_get_type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HandleType {
+    Async,
+    Check,
+    FsEvent,
+    FsPoll,
+    Handle,
+    Idle,
+    Pipe,
+    Poll,
+    Prepare,
+    Process,
+    Stream,
+    Tcp,
+    Timer,
+    Tty,
+    Udp,
+    Signal,
+    File,
+    Unknown(uv_handle_type),
+}
+
+impl HandleType {
+    /// Convert a raw uv_handle_type into a HandleType.
+    pub fn from_raw(raw: uv_handle_type) -> HandleType {
+        match raw {
+            uv_handle_type_UV_ASYNC => HandleType::Async,
+            uv_handle_type_UV_CHECK => HandleType::Check,
+            uv_handle_type_UV_FS_EVENT => HandleType::FsEvent,
+            uv_handle_type_UV_FS_POLL => HandleType::FsPoll,
+            uv_handle_type_UV_HANDLE => HandleType::Handle,
+            uv_handle_type_UV_IDLE => HandleType::Idle,
+            uv_handle_type_UV_NAMED_PIPE => HandleType::Pipe,
+            uv_handle_type_UV_POLL => HandleType::Poll,
+            uv_handle_type_UV_PREPARE => HandleType::Prepare,
+            uv_handle_type_UV_PROCESS => HandleType::Process,
+            uv_handle_type_UV_STREAM => HandleType::Stream,
+            uv_handle_type_UV_TCP => HandleType::Tcp,
+            uv_handle_type_UV_TIMER => HandleType::Timer,
+            uv_handle_type_UV_TTY => HandleType::Tty,
+            uv_handle_type_UV_UDP => HandleType::Udp,
+            uv_handle_type_UV_SIGNAL => HandleType::Signal,
+            uv_handle_type_UV_FILE => HandleType::File,
+            _ => HandleType::Unknown(raw),
+        }
+    }
+
+    /// Convert a HandleType back into a raw uv_handle_type.
+    pub fn to_raw(self) -> uv_handle_type {
+        match self {
+            HandleType::Async => uv_handle_type_UV_ASYNC,
+            HandleType::Check => uv_handle_type_UV_CHECK,
+            HandleType::FsEvent => uv_handle_type_UV_FS_EVENT,
+            HandleType::FsPoll => uv_handle_type_UV_FS_POLL,
+            HandleType::Handle => uv_handle_type_UV_HANDLE,
+            HandleType::Idle => uv_handle_type_UV_IDLE,
+            HandleType::Pipe => uv_handle_type_UV_NAMED_PIPE,
+            HandleType::Poll => uv_handle_type_UV_POLL,
+            HandleType::Prepare => uv_handle_type_UV_PREPARE,
+            HandleType::Process => uv_handle_type_UV_PROCESS,
+            HandleType::Stream => uv_handle_type_UV_STREAM,
+            HandleType::Tcp => uv_handle_type_UV_TCP,
+            HandleType::Timer => uv_handle_type_UV_TIMER,
+            HandleType::Tty => uv_handle_type_UV_TTY,
+            HandleType::Udp => uv_handle_type_UV_UDP,
+            HandleType::Signal => uv_handle_type_UV_SIGNAL,
+            HandleType::File => uv_handle_type_UV_FILE,
+            HandleType::Unknown(raw) => raw,
+        }
+    }
+
+    /// The name libuv uses for this handle type, ie, "tcp" or "timer". This is the same name that
+    /// uv_print_all_handles uses.
+    pub fn name(self) -> &'static str {
+        if let HandleType::Unknown(raw) = self {
+            if raw == uv_handle_type_UV_UNKNOWN_HANDLE {
+                return "<unknown>";
+            }
+        }
+
+        // uv_handle_type_name returns pointers to static strings, or NULL for unknown types
+        unsafe {
+            let name = uv_handle_type_name(self.to_raw());
+            if name.is_null() {
+                "<unknown>"
+            } else {
+                CStr::from_ptr(name).to_str().unwrap_or("<unknown>")
+            }
+        }
+    }
+}
+
+impl fmt::Display for HandleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A snapshot of the state of a handle, as yielded by handles().
+#[derive(Clone, Debug)]
+pub struct HandleInfo {
+    /// The raw handle. The handle may be closed and deallocated after the snapshot was taken, so
+    /// be careful dereferencing it.
+    pub ptr: *mut uv_handle_t,
+
+    /// The type of handle.
+    pub handle_type: HandleType,
+
+    /// Whether the handle is active (uv_is_active).
+    pub active: bool,
+
+    /// Whether the handle is referenced (uv_has_ref).
+    pub referenced: bool,
+
+    /// Whether the handle is closing or closed (uv_is_closing).
+    pub closing: bool,
+
+    /// The platform dependent file descriptor of the handle (uv_fileno), if it has one.
+    pub fd: Option<uv_os_fd_t>,
+}
+
+impl HandleInfo {
+    /// Take a snapshot of a handle.
+    ///
+    /// # Safety
+    /// `handle` must point to a valid, initialized handle.
+    pub unsafe fn from_raw(handle: *mut uv_handle_t) -> HandleInfo {
+        let mut fd: uv_os_fd_t = 0;
+        let fd = match uv_fileno(handle, &mut fd) {
+            0 => Some(fd),
+            _ => None,
+        };
+        HandleInfo {
+            ptr: handle,
+            handle_type: HandleType::from_raw(uv_handle_get_type(handle)),
+            active: uv_is_active(handle) != 0,
+            referenced: uv_has_ref(handle) != 0,
+            closing: uv_is_closing(handle) != 0,
+            fd,
+        }
+    }
+}
+
+/// Formats the handle the same way uv_print_all_handles does, ie, `[RA-] timer   0x5581d2f0`.
+/// The third flag, which libuv uses to mark internal handles, is always `-`: uv_walk never visits
+/// internal handles.
+impl fmt::Display for HandleInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}{}-] {:<8} {:p}",
+            if self.referenced { 'R' } else { '-' },
+            if self.active { 'A' } else { '-' },
+            self.handle_type.name(),
+            self.ptr
+        )
+    }
+}
+
+/// An iterator over the handles in a loop; see handles().
+#[derive(Debug)]
+pub struct Handles {
+    inner: vec::IntoIter<HandleInfo>,
+}
+
+impl Iterator for Handles {
+    type Item = HandleInfo;
+
+    fn next(&mut self) -> Option<HandleInfo> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Handles {}
+
+/// This callback is used by uv_walk to collect a snapshot of every handle in the loop.
+unsafe extern "C" fn walk_cb(handle: *mut uv_handle_t, arg: *mut c_void) {
+    let infos = &mut *(arg as *mut Vec<HandleInfo>);
+    infos.push(HandleInfo::from_raw(handle));
+}
+
+/// Returns an iterator over the handles in a loop, in the same order uv_walk visits them.
+///
+/// The state of every handle is captured when this function is called, so it is safe to close
+/// handles (or create new ones) while iterating.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate libuv_sys2;
+/// #
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::handle::{handles, HandleType};
+/// # use libuv_sys2::{uv_close, uv_idle_init, uv_idle_t, uv_run_mode_UV_RUN_DEFAULT};
+/// # use std::mem;
+/// #
+/// # fn main() {
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let mut idle: Box<uv_idle_t> = Box::new(unsafe { mem::zeroed() });
+/// unsafe { uv_idle_init(r#loop.as_ptr(), &mut *idle) };
+///
+/// let infos: Vec<_> = handles(&r#loop).collect();
+/// assert_eq!(infos.len(), 1);
+/// assert_eq!(infos[0].handle_type, HandleType::Idle);
+/// assert!(infos[0].referenced);
+/// assert!(!infos[0].active);
+/// assert!(!infos[0].closing);
+/// assert_eq!(infos[0].fd, None);
+/// assert!(infos[0].to_string().starts_with("[R--] idle     0x"));
+///
+/// unsafe { uv_close(uv_handle!(&mut *idle), None) };
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// #
+/// # }
+/// ```
+pub fn handles(r#loop: &Loop) -> Handles {
+    let mut infos: Vec<HandleInfo> = Vec::new();
+    unsafe {
+        uv_walk(
+            r#loop.as_ptr(),
+            Some(walk_cb),
+            &mut infos as *mut Vec<HandleInfo> as *mut c_void,
+        );
+    }
+    Handles {
+        inner: infos.into_iter(),
+    }
+}
+
+/// Write a report of every handle in the loop, formatted the same as uv_print_all_handles.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::handle::print_all_handles;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let mut report = Vec::new();
+/// print_all_handles(&r#loop, &mut report).unwrap();
+/// assert_eq!(String::from_utf8(report).unwrap(), "");
+/// ```
+pub fn print_all_handles<W: io::Write>(r#loop: &Loop, stream: &mut W) -> io::Result<()> {
+    for info in handles(r#loop) {
+        writeln!(stream, "{}", info)?;
+    }
+    Ok(())
+}
+
+/// Write a report of the active handles in the loop, formatted the same as
+/// uv_print_active_handles.
+pub fn print_active_handles<W: io::Write>(r#loop: &Loop, stream: &mut W) -> io::Result<()> {
+    for info in handles(r#loop).filter(|info| info.active) {
+        writeln!(stream, "{}", info)?;
+    }
+    Ok(())
+}
+
+/// Returns the same report as print_all_handles() as a String.
+pub fn all_handles_report(r#loop: &Loop) -> String {
+    handles(r#loop).map(|info| format!("{}\n", info)).collect()
+}
+
+/// Returns the same report as print_active_handles() as a String.
+pub fn active_handles_report(r#loop: &Loop) -> String {
+    handles(r#loop)
+        .filter(|info| info.active)
+        .map(|info| format!("{}\n", info))
+        .collect()
+}
+
+/// The data that the safe wrappers attach to their handles' data pointers: a clone of the loop,
+/// which guarantees the loop outlives the handle, and wrapper-specific state.
+///
+/// This is repr(C), so that the state of a handle whose state is a repr(C) struct can also be
+/// accessed as the struct's first field, with the same alignment. Tty does this with its
+/// StreamState.
+#[repr(C)]
+pub(crate) struct HandleData<S> {
+    r#loop: Loop,
+    state: S,
+}
+
+/// Allocate a zeroed handle on the heap, initialize it with `init`, and attach `state` to it. The
+/// handle must eventually be passed to close() to deallocate it.
+pub(crate) unsafe fn alloc<H, S, F>(r#loop: &Loop, state: S, init: F) -> Result<*mut H>
+where
+    F: FnOnce(*mut uv_loop_t, *mut H) -> Result<c_int>,
+{
+    let handle: *mut H = Box::into_raw(Box::new(mem::zeroed()));
+
+    // If initialization fails, libuv hasn't registered the handle with the loop, so it's safe to
+    // deallocate it right away.
+    if let Err(err) = init(r#loop.as_ptr(), handle) {
+        mem::drop(Box::from_raw(handle));
+        return Err(err);
+    }
+
+    let data = Box::into_raw(Box::new(HandleData {
+        r#loop: r#loop.clone(),
+        state,
+    }));
+    uv_handle_set_data(handle as *mut uv_handle_t, data as *mut c_void);
+    Ok(handle)
+}
+
+/// Retrieve a pointer to the state attached to a handle by alloc(). This returns a raw pointer,
+/// rather than a reference, because the state is frequently accessed re-entrantly: a callback
+/// stored in the state may call methods on the handle that access the state, too.
+pub(crate) unsafe fn state<H, S>(handle: *const H) -> *mut S {
+    let data = uv_handle_get_data(handle as *const uv_handle_t) as *mut HandleData<S>;
+    ptr::addr_of_mut!((*data).state)
+}
+
+/// Retrieve the loop that a handle created by alloc() belongs to.
+pub(crate) unsafe fn get_loop<H, S>(handle: *const H) -> Loop {
+    let data = uv_handle_get_data(handle as *const uv_handle_t) as *const HandleData<S>;
+    (*data).r#loop.clone()
+}
+
+/// Start closing a handle created by alloc(). The handle and its state will be deallocated once
+/// libuv has finished closing it.
+pub(crate) unsafe fn close<H, S>(handle: *mut H) {
+    if uv_is_closing(handle as *const uv_handle_t) == 0 {
+        uv_close(handle as *mut uv_handle_t, Some(close_cb::<H, S>));
+    }
+}
+
+/// The close callback for handles created by alloc(). This may also be passed to functions that
+/// close a handle themselves, such as uv_tcp_close_reset.
+pub(crate) unsafe extern "C" fn close_cb<H, S>(handle: *mut uv_handle_t) {
+    let data = uv_handle_get_data(handle) as *mut HandleData<S>;
+    mem::drop(Box::from_raw(data));
+    mem::drop(Box::from_raw(handle as *mut H));
+}
+
+/// A user callback stored in a handle's state.
+pub(crate) struct Callback<F: ?Sized>(Option<Box<F>>);
+
+impl<F: ?Sized> Callback<F> {
+    pub(crate) fn empty() -> Callback<F> {
+        Callback(None)
+    }
+
+    pub(crate) fn set(&mut self, cb: Box<F>) {
+        self.0 = Some(cb);
+    }
+}
+
+/// Invoke the callback in `slot`, if there is one. The callback is moved out of its slot while it
+/// runs so that it can safely replace itself (by restarting its handle, for example). If it did
+/// not, it is put back afterward.
+pub(crate) unsafe fn invoke<F, R, C>(slot: *mut Callback<F>, call: C) -> Option<R>
+where
+    F: ?Sized,
+    C: FnOnce(&mut F) -> R,
+{
+    let mut cb = (*slot).0.take()?;
+    let ret = call(&mut *cb);
+    if (*slot).0.is_none() {
+        (*slot).0 = Some(cb);
+    }
+    Some(ret)
+}
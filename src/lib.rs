@@ -42,3 +42,21 @@ macro_rules! uv_handle {
         $a as _
     };
 }
+
+#[macro_use]
+pub mod error;
+pub mod async_handle;
+pub mod event_loop;
+pub mod fs;
+pub mod handle;
+pub mod net;
+pub mod pipe;
+pub mod poll;
+pub mod process;
+pub mod signal;
+pub mod stream;
+pub mod tcp;
+pub mod timer;
+pub mod tty;
+pub mod udp;
+pub mod watcher;
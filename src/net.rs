@@ -0,0 +1,257 @@
+//! Conversions between std::net's socket addresses and the sockaddr types that libuv uses.
+
+use crate::error::Result;
+use crate::{
+    sockaddr, sockaddr_in, sockaddr_in6, sockaddr_storage, uv_if_indextoiid, uv_if_indextoname,
+    uv_ip4_addr, uv_ip6_addr, uv_ip_name, AF_INET, AF_INET6, AF_UNSPEC, UV_IF_NAMESIZE,
+};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ptr;
+
+/// A socket address in the form that libuv expects, backed by a sockaddr_storage, so it can hold
+/// an address of any family.
+///
+/// # Example
+///
+/// Addresses survive the round trip through a sockaddr:
+///
+/// ```
+/// # use libuv_sys2::net::SockAddr;
+/// # use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+/// #
+/// // a small xorshift generator, so that the test is repeatable
+/// let mut state = 0x2545f4914f6cdd1du64;
+/// let mut next = move || {
+///     state ^= state << 13;
+///     state ^= state >> 7;
+///     state ^= state << 17;
+///     state
+/// };
+///
+/// for _ in 0..1000 {
+///     let port = next() as u16;
+///     let ip = match next() % 3 {
+///         0 => IpAddr::V4(Ipv4Addr::from(next() as u32)),
+///         1 => IpAddr::V6(Ipv6Addr::from((next() as u128) << 64 | next() as u128)),
+///
+///         // IPv4-mapped IPv6 addresses have their own textual form
+///         _ => IpAddr::V6(Ipv4Addr::from(next() as u32).to_ipv6_mapped()),
+///     };
+///     let addr = SocketAddr::new(ip, port);
+///     let raw = SockAddr::new(&addr).unwrap();
+///     assert_eq!(raw.to_socket_addr().unwrap(), addr);
+///     assert_eq!(unsafe { SockAddr::from_raw(raw.as_ptr()) }, raw);
+/// }
+///
+/// let unspecified = SockAddr::unspecified();
+/// assert!(unspecified.is_unspecified());
+/// assert!(unspecified.to_socket_addr().is_err());
+/// ```
+///
+/// IPv6 scope IDs are interface indexes, which are passed to libuv by name:
+///
+/// ```
+/// # use libuv_sys2::net::SockAddr;
+/// # use std::net::SocketAddr;
+/// #
+/// # #[cfg(target_os = "linux")]
+/// # fn main() {
+/// // on Linux, the loopback interface is always index 1
+/// let addr: SocketAddr = "[fe80::1%1]:8080".parse().unwrap();
+/// let raw = SockAddr::new(&addr).unwrap();
+/// assert_eq!(raw.to_socket_addr().unwrap(), addr);
+///
+/// // an interface that doesn't exist can't be converted
+/// let addr: SocketAddr = "[fe80::1%4000000000]:8080".parse().unwrap();
+/// assert!(SockAddr::new(&addr).is_err());
+/// # }
+/// #
+/// # #[cfg(not(target_os = "linux"))]
+/// # fn main() {}
+/// ```
+#[derive(Clone, Copy)]
+pub struct SockAddr {
+    storage: sockaddr_storage,
+}
+
+impl SockAddr {
+    /// Convert a SocketAddr. This fails if the address has an IPv6 scope ID that doesn't match an
+    /// interface on this machine.
+    pub fn new(addr: &SocketAddr) -> Result<SockAddr> {
+        let mut raw = SockAddr::unspecified();
+        unsafe {
+            match addr {
+                SocketAddr::V4(v4) => {
+                    let ip = CString::new(v4.ip().to_string()).expect("IPs don't contain NULs");
+                    uvret!(uv_ip4_addr(
+                        ip.as_ptr(),
+                        v4.port() as c_int,
+                        raw.as_mut_ptr() as *mut sockaddr_in
+                    ))?;
+                }
+                SocketAddr::V6(v6) => {
+                    // libuv parses a scope ID from the "%zone" suffix: an interface name on unix,
+                    // or the index on Windows. uv_if_indextoiid returns the right one.
+                    let mut ip = v6.ip().to_string();
+                    if v6.scope_id() != 0 {
+                        ip.push('%');
+                        ip.push_str(&interface_id(v6.scope_id())?);
+                    }
+                    let ip = CString::new(ip).expect("IPs don't contain NULs");
+                    let raw6 = raw.as_mut_ptr() as *mut sockaddr_in6;
+                    uvret!(uv_ip6_addr(ip.as_ptr(), v6.port() as c_int, raw6))?;
+                    (*raw6).sin6_flowinfo = v6.flowinfo();
+                }
+            }
+        }
+        Ok(raw)
+    }
+
+    /// An address with the family AF_UNSPEC. This is useful as an out parameter for functions
+    /// that fill in an address, such as uv_tcp_getsockname.
+    pub fn unspecified() -> SockAddr {
+        // A zeroed sockaddr_storage has the family AF_UNSPEC.
+        SockAddr {
+            storage: unsafe { mem::zeroed() },
+        }
+    }
+
+    /// Copy a raw sockaddr. Only as many bytes as the address family requires are copied.
+    ///
+    /// # Safety
+    /// `addr` must point to a valid sockaddr, sockaddr_in or sockaddr_in6, as indicated by its
+    /// family.
+    pub unsafe fn from_raw(addr: *const sockaddr) -> SockAddr {
+        let len = match (*addr).sa_family as u32 {
+            AF_INET => mem::size_of::<sockaddr_in>(),
+            AF_INET6 => mem::size_of::<sockaddr_in6>(),
+            _ => mem::size_of::<sockaddr>(),
+        };
+        let mut raw = SockAddr::unspecified();
+        ptr::copy_nonoverlapping(addr as *const u8, raw.as_mut_ptr() as *mut u8, len);
+        raw
+    }
+
+    /// The address family: AF_INET, AF_INET6 or AF_UNSPEC.
+    pub fn family(&self) -> u32 {
+        self.storage.ss_family as u32
+    }
+
+    /// Returns true if the address family is AF_UNSPEC.
+    pub fn is_unspecified(&self) -> bool {
+        self.family() == AF_UNSPEC
+    }
+
+    /// Convert the address to a SocketAddr. Fails with UV_EAFNOSUPPORT if the family isn't
+    /// AF_INET or AF_INET6.
+    pub fn to_socket_addr(&self) -> Result<SocketAddr> {
+        let mut name: [c_char; 64] = [0; 64];
+        unsafe {
+            uvret!(uv_ip_name(self.as_ptr(), name.as_mut_ptr(), name.len()))?;
+            let ip: IpAddr = CStr::from_ptr(name.as_ptr())
+                .to_str()
+                .ok()
+                .and_then(|ip| ip.parse().ok())
+                .expect("libuv formats valid IP addresses");
+
+            // uv_ip_name has already rejected any other families
+            match ip {
+                IpAddr::V4(_) => {
+                    let raw = self.as_ptr() as *const sockaddr_in;
+                    Ok(SocketAddr::new(ip, u16::from_be((*raw).sin_port)))
+                }
+                IpAddr::V6(ip) => {
+                    let raw = self.as_ptr() as *const sockaddr_in6;
+                    Ok(SocketAddr::V6(SocketAddrV6::new(
+                        ip,
+                        u16::from_be((*raw).sin6_port),
+                        (*raw).sin6_flowinfo,
+                        scope_id(&*raw),
+                    )))
+                }
+            }
+        }
+    }
+
+    /// The address as a raw sockaddr.
+    pub fn as_ptr(&self) -> *const sockaddr {
+        &self.storage as *const sockaddr_storage as *const sockaddr
+    }
+
+    /// The address as a mutable raw sockaddr, which may be passed to functions that fill in an
+    /// address, along with capacity().
+    pub fn as_mut_ptr(&mut self) -> *mut sockaddr {
+        &mut self.storage as *mut sockaddr_storage as *mut sockaddr
+    }
+
+    /// The size of the underlying sockaddr_storage.
+    pub fn capacity(&self) -> c_int {
+        mem::size_of::<sockaddr_storage>() as c_int
+    }
+}
+
+#[cfg(not(windows))]
+fn scope_id(addr: &sockaddr_in6) -> u32 {
+    addr.sin6_scope_id
+}
+
+#[cfg(windows)]
+fn scope_id(addr: &sockaddr_in6) -> u32 {
+    unsafe { addr.__bindgen_anon_1.sin6_scope_id }
+}
+
+/// The name of an interface, given its index.
+pub fn interface_name(index: u32) -> Result<String> {
+    let mut buffer: [c_char; UV_IF_NAMESIZE as usize] = [0; UV_IF_NAMESIZE as usize];
+    let mut size = buffer.len();
+    unsafe {
+        uvret!(uv_if_indextoname(
+            index as c_uint,
+            buffer.as_mut_ptr(),
+            &mut size
+        ))?;
+        Ok(CStr::from_ptr(buffer.as_ptr())
+            .to_string_lossy()
+            .into_owned())
+    }
+}
+
+/// The identifier of an interface, given its index, in the form that is used for the scope of an
+/// IPv6 address: the interface name on unix, or the index itself on Windows.
+pub fn interface_id(index: u32) -> Result<String> {
+    let mut buffer: [c_char; UV_IF_NAMESIZE as usize] = [0; UV_IF_NAMESIZE as usize];
+    let mut size = buffer.len();
+    unsafe {
+        uvret!(uv_if_indextoiid(
+            index as c_uint,
+            buffer.as_mut_ptr(),
+            &mut size
+        ))?;
+        Ok(CStr::from_ptr(buffer.as_ptr())
+            .to_string_lossy()
+            .into_owned())
+    }
+}
+
+impl PartialEq for SockAddr {
+    fn eq(&self, other: &SockAddr) -> bool {
+        match (self.to_socket_addr(), other.to_socket_addr()) {
+            (Ok(a), Ok(b)) => a == b,
+            (Err(_), Err(_)) => self.family() == other.family(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for SockAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_socket_addr() {
+            Ok(addr) => write!(f, "SockAddr({})", addr),
+            Err(_) => write!(f, "SockAddr(family {})", self.family()),
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::handle;
+use crate::stream::{self, sealed, ConnectRequest, Stream, StreamState};
+use crate::{
+    uv_accept, uv_errno_t_UV_ENAMETOOLONG, uv_errno_t_UV_ENOBUFS, uv_file, uv_fs_close,
+    uv_fs_req_cleanup, uv_fs_t, uv_handle_t, uv_is_active, uv_loop_t, uv_os_sock_t, uv_pipe,
+    uv_pipe_bind2, uv_pipe_chmod, uv_pipe_connect2, uv_pipe_getpeername, uv_pipe_getsockname,
+    uv_pipe_init, uv_pipe_open, uv_pipe_t, uv_poll_event, uv_socketpair,
+    uv_stdio_flags_UV_NONBLOCK_PIPE, uv_stream_t, SOCK_STREAM, UV_PIPE_NO_TRUNCATE,
+};
+use std::mem::{self, ManuallyDrop};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::path::Path;
+
+pub mod ipc;
+
+/// The size of sockaddr_un.sun_path, which limits the length of a pipe's name.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SUN_PATH_LEN: usize = 108;
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const SUN_PATH_LEN: usize = 104;
+
+/// Check that `name` fits in sun_path. A path needs room for a NUL terminator; an abstract name,
+/// which starts with a NUL, does not.
+#[cfg(unix)]
+fn check_name(func: &'static str, name: &[u8]) -> Result<()> {
+    let max = match name.first() {
+        Some(0) => SUN_PATH_LEN,
+        _ => SUN_PATH_LEN - 1,
+    };
+    if name.len() > max {
+        return Err(UvError::new(func, uv_errno_t_UV_ENAMETOOLONG));
+    }
+    Ok(())
+}
+
+/// Named pipes on Windows don't have sun_path's limit.
+#[cfg(windows)]
+fn check_name(_func: &'static str, _name: &[u8]) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> &[u8] {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes()
+}
+
+#[cfg(windows)]
+fn path_bytes(path: &Path) -> &[u8] {
+    path.to_str()
+        .expect("pipe names must be valid unicode")
+        .as_bytes()
+}
+
+/// Close a file descriptor that was never handed to a handle.
+unsafe fn close_file(r#loop: *mut uv_loop_t, file: uv_file) {
+    let mut req: uv_fs_t = mem::zeroed();
+    uv_fs_close(r#loop, &mut req, file, None);
+    uv_fs_req_cleanup(&mut req);
+}
+
+/// A safe wrapper around uv_pipe_t: a unix domain socket on unix, or a named pipe on Windows.
+///
+/// Reading and writing are provided by the Stream trait. Names are byte strings rather than C
+/// strings; on Linux, a name that starts with a NUL byte is in the abstract namespace, which
+/// doesn't appear in the filesystem. Names that don't fit in sockaddr_un's sun_path are rejected
+/// with UV_ENAMETOOLONG instead of being truncated.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::pipe::Pipe;
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::stream::Stream;
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+/// # use std::cell::RefCell;
+/// # use std::process;
+/// # use std::rc::Rc;
+/// #
+/// # #[cfg(unix)]
+/// # fn main() {
+/// let r#loop = Loop::new().unwrap();
+/// let path = std::env::temp_dir().join(format!("libuv-sys2-pipe-{}.sock", process::id()));
+///
+/// let server = Pipe::new(&r#loop, false).unwrap();
+/// server.bind_path(&path).unwrap();
+/// assert_eq!(server.sockname().unwrap(), path.to_str().unwrap().as_bytes());
+/// let clients = Rc::new(RefCell::new(Vec::new()));
+/// let accepted = clients.clone();
+/// server
+///     .listen(16, move |_, client| {
+///         let client = client.unwrap();
+///         client.write(b"hello".to_vec(), |_, _| {}).unwrap();
+///         accepted.borrow_mut().push(client);
+///     })
+///     .unwrap();
+///
+/// let client = Pipe::new(&r#loop, false).unwrap();
+/// let received = Rc::new(RefCell::new(Vec::new()));
+/// let log = received.clone();
+/// client
+///     .connect_path(&path, move |client, result| {
+///         result.unwrap();
+///         client
+///             .read_start(ReusableAllocator::default(), move |_, data| {
+///                 log.borrow_mut().extend_from_slice(data.unwrap());
+///             })
+///             .unwrap();
+///     })
+///     .unwrap();
+///
+/// for _ in 0..100 {
+///     if received.borrow().len() == 5 {
+///         break;
+///     }
+///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// }
+/// assert_eq!(*received.borrow(), b"hello");
+///
+/// // names that don't fit in sun_path are an error, rather than being truncated
+/// let long = Pipe::new(&r#loop, false).unwrap();
+/// let err = long.bind(&[b'x'; 200]).unwrap_err();
+/// assert_eq!(err.name(), "ENAMETOOLONG");
+///
+/// drop((client, server, long));
+/// clients.borrow_mut().clear();
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// std::fs::remove_file(&path).unwrap();
+/// # }
+/// #
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+///
+/// On Linux, abstract names work the same way, without leaving a file behind:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::pipe::Pipe;
+/// # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+/// # use std::process;
+/// #
+/// # #[cfg(target_os = "linux")]
+/// # fn main() {
+/// let r#loop = Loop::new().unwrap();
+/// let name = format!("\0libuv-sys2-abstract-{}", process::id());
+/// let server = Pipe::new(&r#loop, false).unwrap();
+/// server.bind(name.as_bytes()).unwrap();
+/// assert_eq!(server.sockname().unwrap(), name.as_bytes());
+/// drop(server);
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// # }
+/// #
+/// # #[cfg(not(target_os = "linux"))]
+/// # fn main() {}
+/// ```
+///
+/// Connected pairs, for talking to yourself or a child process:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::pipe::Pipe;
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::stream::Stream;
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let (reader, writer) = Pipe::anonymous(&r#loop).unwrap();
+/// let (left, right) = Pipe::socketpair(&r#loop, false).unwrap();
+///
+/// let received = Rc::new(RefCell::new(Vec::new()));
+/// for pipe in [&reader, &right] {
+///     let log = received.clone();
+///     pipe.read_start(ReusableAllocator::default(), move |_, data| {
+///         log.borrow_mut().push(data.unwrap().to_vec());
+///     })
+///     .unwrap();
+/// }
+/// writer.write(b"pipe".to_vec(), |_, _| {}).unwrap();
+/// left.write(b"socketpair".to_vec(), |_, _| {}).unwrap();
+///
+/// for _ in 0..100 {
+///     if received.borrow().len() == 2 {
+///         break;
+///     }
+///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// }
+/// let mut received = received.borrow().clone();
+/// received.sort();
+/// assert_eq!(received, [b"pipe".to_vec(), b"socketpair".to_vec()]);
+///
+/// drop((reader, writer, left, right));
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// ```
+pub struct Pipe {
+    handle: *mut uv_pipe_t,
+}
+
+impl Pipe {
+    /// Create a new pipe handle. If `ipc` is true, the pipe can be used to pass handles between
+    /// processes.
+    pub fn new(r#loop: &Loop, ipc: bool) -> Result<Pipe> {
+        let handle = unsafe {
+            handle::alloc(r#loop, StreamState::new(), |r#loop, handle| {
+                uvret!(uv_pipe_init(r#loop, handle, ipc as c_int))
+            })?
+        };
+        Ok(Pipe { handle })
+    }
+
+    /// Create a new pipe handle for an existing file descriptor or HANDLE, such as one end of
+    /// a pipe created by some other means. The pipe takes ownership of `file`: it is closed
+    /// when the pipe is dropped, or right away if it can't be opened.
+    ///
+    /// # Safety
+    ///
+    /// `file` must be open, and the caller gives it up: nothing else may use or close it
+    /// afterwards.
+    pub unsafe fn open(r#loop: &Loop, file: uv_file, ipc: bool) -> Result<Pipe> {
+        let pipe = match Pipe::new(r#loop, ipc) {
+            Ok(pipe) => pipe,
+            Err(err) => {
+                close_file(r#loop.as_ptr(), file);
+                return Err(err);
+            }
+        };
+        if let Err(err) = uvret!(uv_pipe_open(pipe.handle, file)) {
+            close_file(r#loop.as_ptr(), file);
+            return Err(err);
+        }
+        Ok(pipe)
+    }
+
+    /// Create an anonymous pipe, returning the reading end and the writing end.
+    pub fn anonymous(r#loop: &Loop) -> Result<(Pipe, Pipe)> {
+        let mut fds: [uv_file; 2] = [-1; 2];
+        let flags = uv_stdio_flags_UV_NONBLOCK_PIPE as c_int;
+        unsafe {
+            uvret!(uv_pipe(fds.as_mut_ptr(), flags, flags))?;
+            Pipe::open_pair(r#loop, fds, false)
+        }
+    }
+
+    /// Create a pair of connected sockets. Unlike anonymous(), both ends are readable and writable.
+    pub fn socketpair(r#loop: &Loop, ipc: bool) -> Result<(Pipe, Pipe)> {
+        let mut socks: [uv_os_sock_t; 2] = [0 as uv_os_sock_t; 2];
+        let flags = uv_stdio_flags_UV_NONBLOCK_PIPE as c_int;
+        unsafe {
+            uvret!(uv_socketpair(
+                SOCK_STREAM as c_int,
+                0,
+                socks.as_mut_ptr(),
+                flags,
+                flags
+            ))?;
+            Pipe::open_pair(r#loop, [socks[0] as uv_file, socks[1] as uv_file], ipc)
+        }
+    }
+
+    /// Open both ends of a pipe that was just created, which nothing else owns.
+    unsafe fn open_pair(r#loop: &Loop, files: [uv_file; 2], ipc: bool) -> Result<(Pipe, Pipe)> {
+        let first = match Pipe::open(r#loop, files[0], ipc) {
+            Ok(pipe) => pipe,
+            Err(err) => {
+                close_file(r#loop.as_ptr(), files[1]);
+                return Err(err);
+            }
+        };
+        let second = Pipe::open(r#loop, files[1], ipc)?;
+        Ok((first, second))
+    }
+
+    /// Bind the pipe to a name, which may be a path or, on Linux, an abstract name that starts
+    /// with a NUL byte.
+    pub fn bind(&self, name: &[u8]) -> Result<()> {
+        check_name("uv_pipe_bind2", name)?;
+        unsafe {
+            uvret!(uv_pipe_bind2(
+                self.handle,
+                name.as_ptr() as *const c_char,
+                name.len(),
+                UV_PIPE_NO_TRUNCATE as c_uint
+            ))
+        }?;
+        Ok(())
+    }
+
+    /// Bind the pipe to a path.
+    pub fn bind_path(&self, path: &Path) -> Result<()> {
+        self.bind(path_bytes(path))
+    }
+
+    /// Start listening for connections. Each incoming connection is accepted, and `cb` is called
+    /// with the new Pipe handle. Dropping the new handle closes the connection.
+    pub fn listen<F>(&self, backlog: i32, mut cb: F) -> Result<()>
+    where
+        F: FnMut(&Pipe, Result<Pipe>) + 'static,
+    {
+        let handle = self.handle;
+        unsafe {
+            stream::listen(
+                self.as_stream_ptr(),
+                backlog as c_int,
+                Box::new(move |status| {
+                    // The Pipe passed to the callback doesn't own the handle, so it must not be
+                    // dropped.
+                    let server = ManuallyDrop::new(Pipe { handle });
+                    let client = match status {
+                        0 => server.accept(),
+                        code => Err(UvError::new("uv_listen", code)),
+                    };
+                    cb(&server, client)
+                }),
+            )
+        }
+    }
+
+    /// Accept an incoming connection on a listening pipe. The new pipe is an IPC pipe if the
+    /// listening pipe is.
+    fn accept(&self) -> Result<Pipe> {
+        let client = Pipe::new(&self.get_loop(), self.is_ipc())?;
+        unsafe { uvret!(uv_accept(self.as_stream_ptr(), client.as_stream_ptr())) }?;
+        Ok(client)
+    }
+
+    /// Connect to the pipe called `name` (see bind()). `cb` is called once the connection has
+    /// been established or has failed.
+    pub fn connect<F>(&self, name: &[u8], cb: F) -> Result<()>
+    where
+        F: FnOnce(&Pipe, Result<()>) + 'static,
+    {
+        check_name("uv_pipe_connect2", name)?;
+        let handle = self.handle;
+        unsafe {
+            ConnectRequest::submit(self.as_stream_ptr(), cb, |req, connect_cb| {
+                uvret!(uv_pipe_connect2(
+                    req,
+                    handle,
+                    name.as_ptr() as *const c_char,
+                    name.len(),
+                    UV_PIPE_NO_TRUNCATE as c_uint,
+                    connect_cb
+                ))
+            })
+        }
+    }
+
+    /// Connect to the pipe at `path`.
+    pub fn connect_path<F>(&self, path: &Path, cb: F) -> Result<()>
+    where
+        F: FnOnce(&Pipe, Result<()>) + 'static,
+    {
+        self.connect(path_bytes(path), cb)
+    }
+
+    /// Make the pipe accessible to other users. `flags` is uv_poll_event_UV_READABLE,
+    /// uv_poll_event_UV_WRITABLE, or both.
+    pub fn chmod(&self, flags: uv_poll_event) -> Result<()> {
+        unsafe { uvret!(uv_pipe_chmod(self.handle, flags as c_int)) }?;
+        Ok(())
+    }
+
+    /// The name that the pipe is bound to.
+    pub fn sockname(&self) -> Result<Vec<u8>> {
+        self.name(|handle, buffer, size| unsafe {
+            uvret!(uv_pipe_getsockname(handle, buffer, size))
+        })
+    }
+
+    /// The name of the pipe that this pipe is connected to.
+    pub fn peername(&self) -> Result<Vec<u8>> {
+        self.name(|handle, buffer, size| unsafe {
+            uvret!(uv_pipe_getpeername(handle, buffer, size))
+        })
+    }
+
+    /// Call uv_pipe_getsockname or uv_pipe_getpeername, growing the buffer if it's too small.
+    fn name<F>(&self, get: F) -> Result<Vec<u8>>
+    where
+        F: Fn(*const uv_pipe_t, *mut c_char, *mut usize) -> Result<c_int>,
+    {
+        let mut buffer = vec![0u8; 256];
+        loop {
+            let mut size = buffer.len();
+            match get(self.handle, buffer.as_mut_ptr() as *mut c_char, &mut size) {
+                Ok(_) => {
+                    buffer.truncate(size);
+                    return Ok(buffer);
+                }
+
+                // On UV_ENOBUFS, libuv sets size to what it needs, including a NUL terminator.
+                Err(err) if err.code() == uv_errno_t_UV_ENOBUFS && size > buffer.len() => {
+                    buffer.resize(size, 0);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns true if the pipe is listening, reading, or connecting.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the handle belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, StreamState>(self.handle) }
+    }
+
+    /// The raw uv_pipe_t.
+    pub fn as_ptr(&self) -> *mut uv_pipe_t {
+        self.handle
+    }
+}
+
+impl sealed::StreamHandle for Pipe {
+    fn stream_ptr(&self) -> *mut uv_stream_t {
+        self.handle as *mut uv_stream_t
+    }
+
+    unsafe fn from_stream_ptr(ptr: *mut uv_stream_t) -> Pipe {
+        Pipe {
+            handle: ptr as *mut uv_pipe_t,
+        }
+    }
+}
+
+impl Stream for Pipe {}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe { handle::close::<_, StreamState>(self.handle) }
+    }
+}
@@ -0,0 +1,310 @@
+//! Passing handles between processes over IPC pipes.
+
+use super::Pipe;
+use crate::error::{Result, UvError};
+use crate::stream::{Stream, WriteRequest};
+use crate::tcp::Tcp;
+use crate::udp::Udp;
+use crate::{
+    uv_accept, uv_errno_t_UV_EINVAL, uv_errno_t_UV_ENOTSUP, uv_handle_type,
+    uv_handle_type_UV_NAMED_PIPE, uv_handle_type_UV_TCP, uv_handle_type_UV_UDP,
+    uv_pipe_pending_count, uv_pipe_pending_type, uv_stream_t,
+};
+
+pub(crate) mod sealed {
+    use crate::uv_stream_t;
+
+    pub trait SendHandle {
+        /// The handle, cast to the uv_stream_t that uv_write2 expects. UDP handles aren't
+        /// streams, but libuv only uses the handle's file descriptor.
+        fn send_ptr(&self) -> *mut uv_stream_t;
+    }
+}
+
+/// A handle that can be sent over an IPC pipe: a Tcp, Pipe or Udp. Windows only supports
+/// sending Tcp handles.
+pub trait SendHandle: sealed::SendHandle {}
+
+impl sealed::SendHandle for Tcp {
+    fn send_ptr(&self) -> *mut uv_stream_t {
+        self.as_ptr() as *mut uv_stream_t
+    }
+}
+
+impl SendHandle for Tcp {}
+
+impl sealed::SendHandle for Pipe {
+    fn send_ptr(&self) -> *mut uv_stream_t {
+        self.as_ptr() as *mut uv_stream_t
+    }
+}
+
+impl SendHandle for Pipe {}
+
+impl sealed::SendHandle for Udp {
+    fn send_ptr(&self) -> *mut uv_stream_t {
+        self.as_ptr() as *mut uv_stream_t
+    }
+}
+
+impl SendHandle for Udp {}
+
+/// A handle that was received over an IPC pipe, wrapped in the type that matches what was sent.
+///
+/// # Example
+///
+/// Passing a connected TCP socket across an IPC socket pair:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::pipe::ipc::ReceivedHandle;
+/// # use libuv_sys2::pipe::Pipe;
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::stream::Stream;
+/// # use libuv_sys2::tcp::Tcp;
+/// # use libuv_sys2::{uv_handle_type_UV_UNKNOWN_HANDLE, uv_run_mode_UV_RUN_DEFAULT};
+/// # use libuv_sys2::uv_run_mode_UV_RUN_ONCE;
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let (parent, worker) = Pipe::socketpair(&r#loop, true).unwrap();
+///
+/// // the "parent" accepts connections and hands them to the "worker"
+/// let server = Tcp::new(&r#loop).unwrap();
+/// server.bind(&"127.0.0.1:0".parse().unwrap(), 0).unwrap();
+/// server
+///     .listen(16, move |_, client| {
+///         let client = client.unwrap();
+///         parent
+///             .write2(b"tcp".to_vec(), client, |_, result, client| {
+///                 result.unwrap();
+///
+///                 // the worker has its own copy of the socket now
+///                 drop(client);
+///             })
+///             .unwrap();
+///     })
+///     .unwrap();
+///
+/// let handles = Rc::new(RefCell::new(Vec::new()));
+/// let received = handles.clone();
+/// worker
+///     .read_start(ReusableAllocator::default(), move |worker, data| {
+///         assert_eq!(data.unwrap(), b"tcp");
+///         while let Some(handle) = worker.accept_pending().unwrap() {
+///             match handle {
+///                 ReceivedHandle::Tcp(tcp) => {
+///                     tcp.write(b"hello from the worker".to_vec(), |_, _| {}).unwrap();
+///                     received.borrow_mut().push(tcp);
+///                 }
+///                 _ => panic!("expected a Tcp handle"),
+///             }
+///         }
+///         assert_eq!(worker.pending_type(), uv_handle_type_UV_UNKNOWN_HANDLE);
+///     })
+///     .unwrap();
+///
+/// let client = Tcp::new(&r#loop).unwrap();
+/// let greeting = Rc::new(RefCell::new(Vec::new()));
+/// let log = greeting.clone();
+/// client
+///     .connect(&server.sockname().unwrap(), move |client, result| {
+///         result.unwrap();
+///         client
+///             .read_start(ReusableAllocator::default(), move |_, data| {
+///                 if let Ok(data) = data {
+///                     log.borrow_mut().extend_from_slice(data);
+///                 }
+///             })
+///             .unwrap();
+///     })
+///     .unwrap();
+///
+/// for _ in 0..100 {
+///     if greeting.borrow().len() == 21 {
+///         break;
+///     }
+///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// }
+/// assert_eq!(*greeting.borrow(), b"hello from the worker");
+///
+/// drop((client, server, worker));
+/// handles.borrow_mut().clear();
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// ```
+///
+/// The same thing works across processes. Here, the test re-runs itself as the worker, with one
+/// end of a socket pair as its stdin:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::pipe::ipc::ReceivedHandle;
+/// # use libuv_sys2::pipe::Pipe;
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::stream::Stream;
+/// # use libuv_sys2::tcp::Tcp;
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// #
+/// # #[cfg(unix)]
+/// # fn main() {
+/// use std::os::fd::OwnedFd;
+/// use std::os::unix::io::IntoRawFd;
+/// use std::os::unix::net::UnixStream;
+/// use std::process::{Command, Stdio};
+///
+/// let r#loop = Loop::new().unwrap();
+/// if std::env::var_os("LIBUV_SYS2_IPC_WORKER").is_some() {
+///     let parent = unsafe { Pipe::open(&r#loop, 0, true) }.unwrap();
+///     let connection = Rc::new(RefCell::new(None));
+///     parent
+///         .read_start(ReusableAllocator::default(), move |parent, _| {
+///             if let Some(ReceivedHandle::Tcp(tcp)) = parent.accept_pending().unwrap() {
+///                 // reply with our pid, then close the connection
+///                 let pid = std::process::id().to_string();
+///                 let slot = connection.clone();
+///                 tcp.write(pid.into_bytes(), move |_, _| drop(slot.borrow_mut().take()))
+///                     .unwrap();
+///                 *connection.borrow_mut() = Some(tcp);
+///                 parent.read_stop().unwrap();
+///             }
+///         })
+///         .unwrap();
+///     r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+///     std::process::exit(0);
+/// }
+///
+/// let (ours, theirs) = UnixStream::pair().unwrap();
+/// let mut child = Command::new(std::env::current_exe().unwrap())
+///     .env("LIBUV_SYS2_IPC_WORKER", "1")
+///     .stdin(Stdio::from(OwnedFd::from(theirs)))
+///     .spawn()
+///     .unwrap();
+/// let worker = unsafe { Pipe::open(&r#loop, ours.into_raw_fd(), true) }.unwrap();
+///
+/// let server = Tcp::new(&r#loop).unwrap();
+/// server.bind(&"127.0.0.1:0".parse().unwrap(), 0).unwrap();
+/// server
+///     .listen(16, move |_, client| {
+///         let client = client.unwrap();
+///         worker
+///             .write2(b"tcp".to_vec(), client, |_, result, client| {
+///                 result.unwrap();
+///                 drop(client);
+///             })
+///             .unwrap();
+///     })
+///     .unwrap();
+///
+/// let client = Tcp::new(&r#loop).unwrap();
+/// let reply = Rc::new(RefCell::new(Vec::new()));
+/// let log = reply.clone();
+/// client
+///     .connect(&server.sockname().unwrap(), move |client, result| {
+///         result.unwrap();
+///         client
+///             .read_start(ReusableAllocator::default(), move |_, data| {
+///                 if let Ok(data) = data {
+///                     log.borrow_mut().extend_from_slice(data);
+///                 }
+///             })
+///             .unwrap();
+///     })
+///     .unwrap();
+///
+/// for _ in 0..1000 {
+///     if !reply.borrow().is_empty() {
+///         break;
+///     }
+///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// }
+/// let status = child.wait().unwrap();
+/// assert!(status.success());
+///
+/// // the reply came from the worker process
+/// let pid = String::from_utf8(reply.borrow().clone()).unwrap();
+/// assert_eq!(pid, child.id().to_string());
+///
+/// drop((client, server));
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// # }
+/// #
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub enum ReceivedHandle {
+    Tcp(Tcp),
+    Pipe(Pipe),
+    Udp(Udp),
+}
+
+impl Pipe {
+    /// Write `data` to an IPC pipe along with `handle`, which the process at the other end can
+    /// accept with accept_pending(). `cb` is called once the write has finished, and is given
+    /// `handle` back: this process keeps its own copy of the handle, so to hand it off, simply
+    /// drop it.
+    pub fn write2<B, H, F>(&self, data: B, handle: H, cb: F) -> Result<()>
+    where
+        B: AsRef<[u8]> + 'static,
+        H: SendHandle + 'static,
+        F: FnOnce(&Pipe, Result<()>, H) + 'static,
+    {
+        if !self.is_ipc() {
+            return Err(UvError::new("uv_write2", uv_errno_t_UV_EINVAL));
+        }
+
+        // The handle is kept in the request, so that it stays open until the write has finished.
+        let send_ptr = sealed::SendHandle::send_ptr(&handle);
+        let cb = move |pipe: &Pipe, result| cb(pipe, result, handle);
+        unsafe { WriteRequest::submit(self.as_stream_ptr(), vec![data], send_ptr, cb) }
+    }
+
+    /// Returns true if the pipe was created for IPC.
+    pub fn is_ipc(&self) -> bool {
+        unsafe { (*self.as_ptr()).ipc != 0 }
+    }
+
+    /// The number of handles that have been received, but not yet accepted.
+    pub fn pending_count(&self) -> usize {
+        unsafe { uv_pipe_pending_count(self.as_ptr()) as usize }
+    }
+
+    /// The type of the next handle to be accepted, or uv_handle_type_UV_UNKNOWN_HANDLE if there
+    /// isn't one.
+    pub fn pending_type(&self) -> uv_handle_type {
+        unsafe { uv_pipe_pending_type(self.as_ptr()) }
+    }
+
+    /// Accept the next handle that was received on an IPC pipe, wrapped in the right type.
+    /// Returns None if no handles are pending. Handles arrive along with data, so this should be
+    /// called from the read callback, until it returns None.
+    pub fn accept_pending(&self) -> Result<Option<ReceivedHandle>> {
+        if self.pending_count() == 0 {
+            return Ok(None);
+        }
+
+        let r#loop = self.get_loop();
+        let (handle, ptr) = match self.pending_type() {
+            uv_handle_type_UV_TCP => {
+                let tcp = Tcp::new(&r#loop)?;
+                let ptr = tcp.as_ptr() as *mut uv_stream_t;
+                (ReceivedHandle::Tcp(tcp), ptr)
+            }
+            uv_handle_type_UV_NAMED_PIPE => {
+                let pipe = Pipe::new(&r#loop, false)?;
+                let ptr = pipe.as_ptr() as *mut uv_stream_t;
+                (ReceivedHandle::Pipe(pipe), ptr)
+            }
+            uv_handle_type_UV_UDP => {
+                let udp = Udp::new(&r#loop)?;
+                let ptr = udp.as_ptr() as *mut uv_stream_t;
+                (ReceivedHandle::Udp(udp), ptr)
+            }
+            _ => return Err(UvError::new("uv_pipe_pending_type", uv_errno_t_UV_ENOTSUP)),
+        };
+        unsafe { uvret!(uv_accept(self.as_stream_ptr(), ptr)) }?;
+        Ok(Some(handle))
+    }
+}
@@ -0,0 +1,357 @@
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::handle::{self, Callback};
+#[cfg(unix)]
+use crate::uv_poll_init;
+use crate::{
+    uv_errno_t_UV_EEXIST, uv_fileno, uv_handle_t, uv_is_active, uv_loop_t, uv_os_fd_t,
+    uv_os_sock_t, uv_poll_event, uv_poll_event_UV_DISCONNECT, uv_poll_event_UV_PRIORITIZED,
+    uv_poll_event_UV_READABLE, uv_poll_event_UV_WRITABLE, uv_poll_init_socket, uv_poll_start,
+    uv_poll_stop, uv_poll_t,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::{BitAnd, BitOr, BitOrAssign, Sub};
+use std::os::raw::c_int;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, BorrowedFd, RawFd};
+use std::ptr;
+
+/// A set of events to watch for with a Poll, or that a Poll reported.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PollEvents(uv_poll_event);
+
+impl PollEvents {
+    pub const EMPTY: PollEvents = PollEvents(0);
+    pub const READABLE: PollEvents = PollEvents(uv_poll_event_UV_READABLE);
+    pub const WRITABLE: PollEvents = PollEvents(uv_poll_event_UV_WRITABLE);
+
+    /// The other end of the socket has closed its side of the connection. This is only reported
+    /// on some platforms, and can be reported along with READABLE.
+    pub const DISCONNECT: PollEvents = PollEvents(uv_poll_event_UV_DISCONNECT);
+
+    /// Urgent (out-of-band) data is available. Not supported on Windows.
+    pub const PRIORITIZED: PollEvents = PollEvents(uv_poll_event_UV_PRIORITIZED);
+
+    const ALL: [(PollEvents, &'static str); 4] = [
+        (PollEvents::READABLE, "READABLE"),
+        (PollEvents::WRITABLE, "WRITABLE"),
+        (PollEvents::DISCONNECT, "DISCONNECT"),
+        (PollEvents::PRIORITIZED, "PRIORITIZED"),
+    ];
+
+    /// Convert a raw uv_poll_event bitmask into a PollEvents. Unknown bits are dropped.
+    pub fn from_raw(raw: uv_poll_event) -> PollEvents {
+        let known = PollEvents::ALL
+            .iter()
+            .fold(0, |bits, (event, _)| bits | event.0);
+        PollEvents(raw & known)
+    }
+
+    /// The raw uv_poll_event bitmask.
+    pub fn to_raw(self) -> uv_poll_event {
+        self.0
+    }
+
+    /// Returns true if every event in `other` is in this set.
+    pub fn contains(self, other: PollEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any event in `other` is in this set.
+    pub fn intersects(self, other: PollEvents) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn insert(&mut self, other: PollEvents) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: PollEvents) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for PollEvents {
+    type Output = PollEvents;
+
+    fn bitor(self, other: PollEvents) -> PollEvents {
+        PollEvents(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for PollEvents {
+    fn bitor_assign(&mut self, other: PollEvents) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for PollEvents {
+    type Output = PollEvents;
+
+    fn bitand(self, other: PollEvents) -> PollEvents {
+        PollEvents(self.0 & other.0)
+    }
+}
+
+impl Sub for PollEvents {
+    type Output = PollEvents;
+
+    fn sub(self, other: PollEvents) -> PollEvents {
+        PollEvents(self.0 & !other.0)
+    }
+}
+
+impl fmt::Debug for PollEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = PollEvents::ALL
+            .iter()
+            .filter(|(event, _)| self.contains(*event))
+            .map(|(_, name)| *name)
+            .collect();
+        if names.is_empty() {
+            write!(f, "PollEvents(EMPTY)")
+        } else {
+            write!(f, "PollEvents({})", names.join(" | "))
+        }
+    }
+}
+
+type PollCallback = dyn FnMut(&Poll, Result<PollEvents>);
+
+struct PollState {
+    cb: Callback<PollCallback>,
+    events: PollEvents,
+
+    /// The entry in POLLED for this handle.
+    key: (usize, usize),
+}
+
+thread_local! {
+    /// The (loop, fd) pairs that have a Poll. Handles can only be used from the thread that runs
+    /// their loop, so each thread only needs to know about its own.
+    static POLLED: RefCell<HashSet<(usize, usize)>> = RefCell::new(HashSet::new());
+}
+
+/// A safe wrapper around uv_poll_t, for watching file descriptors that belong to some other
+/// library, ie, the sockets of an HTTP client or database driver, for readability and
+/// writability. The other library does the actual reading and writing when the Poll says that it
+/// can.
+///
+/// libuv doesn't support more than one poll handle for the same file descriptor in a loop, so
+/// creating a second Poll for a file descriptor fails with UV_EEXIST until the first is dropped.
+/// The file descriptor also mustn't belong to another libuv handle, such as a Tcp, and it must
+/// stay open until the Poll is dropped; the Poll doesn't close it.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::poll::{Poll, PollEvents};
+/// # use libuv_sys2::uv_errno_t_UV_EEXIST;
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// #
+/// # #[cfg(unix)]
+/// # fn main() {
+/// use std::io::{Read, Write};
+/// use std::os::unix::io::{AsFd, AsRawFd};
+/// use std::os::unix::net::UnixStream;
+///
+/// let r#loop = Loop::new().unwrap();
+/// let (mut ours, mut theirs) = UnixStream::pair().unwrap();
+/// ours.set_nonblocking(true).unwrap();
+///
+/// let poll = Poll::from_fd(&r#loop, ours.as_fd()).unwrap();
+/// let err = Poll::new(&r#loop, ours.as_raw_fd()).err().unwrap();
+/// assert_eq!(err.code(), uv_errno_t_UV_EEXIST);
+///
+/// let received = Rc::new(RefCell::new(Vec::new()));
+/// let log = received.clone();
+/// poll.start(PollEvents::WRITABLE, move |poll, events| {
+///     let events = events.unwrap();
+///     if events.contains(PollEvents::WRITABLE) {
+///         // send a request, then switch to waiting for the reply
+///         ours.write_all(b"ping").unwrap();
+///         poll.set_events(PollEvents::READABLE | PollEvents::DISCONNECT).unwrap();
+///         return;
+///     }
+///
+///     let mut buf = [0; 64];
+///     loop {
+///         match ours.read(&mut buf) {
+///             Ok(0) => return poll.stop().unwrap(),
+///             Ok(n) => log.borrow_mut().extend_from_slice(&buf[..n]),
+///             Err(_) => return,
+///         }
+///     }
+/// })
+/// .unwrap();
+///
+/// r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// assert_eq!(poll.events(), PollEvents::READABLE | PollEvents::DISCONNECT);
+///
+/// let mut request = [0; 4];
+/// theirs.read_exact(&mut request).unwrap();
+/// assert_eq!(&request, b"ping");
+/// theirs.write_all(b"pong").unwrap();
+/// drop(theirs);
+///
+/// // the loop stops once the Poll is stopped
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// assert_eq!(*received.borrow(), b"pong");
+/// assert!(!poll.is_active());
+///
+/// drop(poll);
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// # }
+/// #
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub struct Poll {
+    handle: *mut uv_poll_t,
+}
+
+impl Poll {
+    /// Create a Poll for the file descriptor `fd`, which is put into non-blocking mode.
+    #[cfg(unix)]
+    pub fn new(r#loop: &Loop, fd: RawFd) -> Result<Poll> {
+        Poll::init(r#loop, |r#loop, handle| unsafe {
+            uvret!(uv_poll_init(r#loop, handle, fd))
+        })
+    }
+
+    /// Like new(), for a borrowed file descriptor.
+    #[cfg(unix)]
+    pub fn from_fd(r#loop: &Loop, fd: BorrowedFd<'_>) -> Result<Poll> {
+        Poll::new(r#loop, fd.as_raw_fd())
+    }
+
+    /// Create a Poll for a socket. On unix, this is the same as new(); on Windows, it's the only
+    /// way to create a Poll.
+    pub fn new_socket(r#loop: &Loop, socket: uv_os_sock_t) -> Result<Poll> {
+        Poll::init(r#loop, |r#loop, handle| unsafe {
+            uvret!(uv_poll_init_socket(r#loop, handle, socket))
+        })
+    }
+
+    fn init<F>(r#loop: &Loop, init: F) -> Result<Poll>
+    where
+        F: FnOnce(*mut uv_loop_t, *mut uv_poll_t) -> Result<c_int>,
+    {
+        let state = PollState {
+            cb: Callback::empty(),
+            events: PollEvents::EMPTY,
+            key: (0, 0),
+        };
+        let handle: *mut uv_poll_t = unsafe { handle::alloc(r#loop, state, init)? };
+
+        // On Windows, the file descriptor is translated into a socket, so the key is whatever
+        // libuv ended up with.
+        let mut fd: uv_os_fd_t = unsafe { std::mem::zeroed() };
+        unsafe { uv_fileno(handle as *const uv_handle_t, &mut fd) };
+        let key = (r#loop.as_ptr() as usize, fd as usize);
+        let registered = POLLED.with(|polled| polled.borrow_mut().insert(key));
+        if !registered {
+            unsafe { handle::close::<_, PollState>(handle) };
+            return Err(UvError::new("uv_poll_init", uv_errno_t_UV_EEXIST));
+        }
+        unsafe { (*handle::state::<_, PollState>(handle)).key = key };
+        Ok(Poll { handle })
+    }
+
+    /// Start watching for `events`. `cb` is called with the events that occurred, which may
+    /// include DISCONNECT even if it wasn't asked for, or with an error. If the Poll is already
+    /// started, the events and callback are replaced.
+    ///
+    /// libuv only reports that an event might have occurred, so the callback should be prepared
+    /// for reads and writes to fail with EAGAIN.
+    pub fn start<F>(&self, events: PollEvents, cb: F) -> Result<()>
+    where
+        F: FnMut(&Poll, Result<PollEvents>) + 'static,
+    {
+        unsafe {
+            let state = handle::state::<_, PollState>(self.handle);
+            (*state).cb.set(Box::new(cb));
+        }
+        self.set_events(events)
+    }
+
+    /// Change the events being watched for, keeping the callback. This is safe to call from
+    /// inside the callback, ie, to start watching for WRITABLE once there is something to
+    /// write.
+    pub fn set_events(&self, events: PollEvents) -> Result<()> {
+        unsafe {
+            uvret!(uv_poll_start(
+                self.handle,
+                events.to_raw() as c_int,
+                Some(poll_cb)
+            ))?;
+            (*handle::state::<_, PollState>(self.handle)).events = events;
+        }
+        Ok(())
+    }
+
+    /// The events being watched for, or EMPTY if the Poll is stopped.
+    pub fn events(&self) -> PollEvents {
+        unsafe { (*handle::state::<_, PollState>(self.handle)).events }
+    }
+
+    /// Stop watching the file descriptor.
+    pub fn stop(&self) -> Result<()> {
+        unsafe {
+            uvret!(uv_poll_stop(self.handle))?;
+            (*handle::state::<_, PollState>(self.handle)).events = PollEvents::EMPTY;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the Poll is watching for events.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the handle belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, PollState>(self.handle) }
+    }
+
+    /// The raw uv_poll_t.
+    pub fn as_ptr(&self) -> *mut uv_poll_t {
+        self.handle
+    }
+}
+
+impl Drop for Poll {
+    fn drop(&mut self) {
+        unsafe {
+            let key = (*handle::state::<_, PollState>(self.handle)).key;
+            let _ = POLLED.try_with(|polled| polled.borrow_mut().remove(&key));
+            handle::close::<_, PollState>(self.handle)
+        }
+    }
+}
+
+unsafe extern "C" fn poll_cb(handle: *mut uv_poll_t, status: c_int, events: c_int) {
+    // The Poll passed to the callback doesn't own the handle, so it must not be dropped.
+    let poll = ManuallyDrop::new(Poll { handle });
+    let state = handle::state::<_, PollState>(handle);
+    let result = if status < 0 {
+        // On unix, libuv stops the handle itself before reporting an error.
+        if !poll.is_active() {
+            (*state).events = PollEvents::EMPTY;
+        }
+        Err(UvError::new("uv_poll_start", status))
+    } else {
+        Ok(PollEvents::from_raw(events as uv_poll_event))
+    };
+    handle::invoke(ptr::addr_of_mut!((*state).cb), |cb| cb(&poll, result));
+}
@@ -0,0 +1,567 @@
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::handle;
+use crate::pipe::Pipe;
+use crate::stream::Stream;
+use crate::{
+    uv_errno_t_UV_EINVAL, uv_handle_t, uv_is_active, uv_kill, uv_process_flags,
+    uv_process_flags_UV_PROCESS_DETACHED, uv_process_flags_UV_PROCESS_SETGID,
+    uv_process_flags_UV_PROCESS_SETUID, uv_process_flags_UV_PROCESS_WINDOWS_HIDE,
+    uv_process_flags_UV_PROCESS_WINDOWS_VERBATIM_ARGUMENTS, uv_process_get_pid, uv_process_kill,
+    uv_process_options_t, uv_process_t, uv_spawn, uv_stdio_container_s__bindgen_ty_1,
+    uv_stdio_container_t, uv_stdio_flags_UV_CREATE_PIPE, uv_stdio_flags_UV_IGNORE,
+    uv_stdio_flags_UV_INHERIT_FD, uv_stdio_flags_UV_INHERIT_STREAM,
+    uv_stdio_flags_UV_READABLE_PIPE, uv_stdio_flags_UV_WRITABLE_PIPE, uv_stream_t,
+};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{CString, OsStr, OsString};
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+pub mod output;
+
+type ExitCallback = dyn FnOnce(&Process, i64, u32);
+
+struct ProcessState {
+    exit: Option<Box<ExitCallback>>,
+
+    /// The parent's ends of any piped stdio, indexed by file descriptor.
+    pipes: Vec<Option<Pipe>>,
+}
+
+/// Convert a string for libuv, which wants NUL-terminated UTF-8 on Windows.
+fn cstring(s: &OsStr) -> Result<CString> {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        s.as_bytes().to_vec()
+    };
+    #[cfg(windows)]
+    let bytes = match s.to_str() {
+        Some(s) => s.as_bytes().to_vec(),
+        None => return Err(UvError::new("uv_spawn", uv_errno_t_UV_EINVAL)),
+    };
+    CString::new(bytes).map_err(|_| UvError::new("uv_spawn", uv_errno_t_UV_EINVAL))
+}
+
+/// A NULL-terminated array of pointers into `strings`, for uv_process_options_t's args and env.
+fn pointers(strings: &[CString]) -> Vec<*mut c_char> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr() as *mut c_char)
+        .chain(Some(ptr::null_mut()))
+        .collect()
+}
+
+enum StdioKind<'a> {
+    Inherit,
+    Null,
+    Piped { ipc: bool },
+    Fd(c_int),
+    Stream(*mut uv_stream_t, PhantomData<&'a ()>),
+}
+
+/// What to connect one of a child process's file descriptors to. See Command::stdin() and
+/// friends.
+pub struct Stdio<'a>(StdioKind<'a>);
+
+impl<'a> Stdio<'a> {
+    /// The child inherits the same file descriptor from this process. This is the default for
+    /// stdin, stdout and stderr.
+    pub fn inherit() -> Stdio<'a> {
+        Stdio(StdioKind::Inherit)
+    }
+
+    /// The file descriptor is closed in the child (or, for stdin, stdout and stderr, redirected
+    /// to /dev/null).
+    pub fn null() -> Stdio<'a> {
+        Stdio(StdioKind::Null)
+    }
+
+    /// A new pipe is created between the child and this process. The parent's end is available
+    /// from the Process, ie, with Process::take_stdout().
+    pub fn piped() -> Stdio<'a> {
+        Stdio(StdioKind::Piped { ipc: false })
+    }
+
+    /// Like piped(), but the pipe is an IPC pipe, which can be used to pass handles to the child
+    /// (see Pipe::write2()).
+    pub fn piped_ipc() -> Stdio<'a> {
+        Stdio(StdioKind::Piped { ipc: true })
+    }
+
+    /// The child inherits `fd` from this process. On Windows, this is a C runtime file
+    /// descriptor.
+    pub fn fd(fd: c_int) -> Stdio<'a> {
+        Stdio(StdioKind::Fd(fd))
+    }
+
+    /// The child inherits the file descriptor that backs `stream`, such as a Tcp socket.
+    pub fn stream<S: Stream>(stream: &'a S) -> Stdio<'a> {
+        Stdio(StdioKind::Stream(stream.as_stream_ptr(), PhantomData))
+    }
+}
+
+/// A builder for child processes, modeled after std::process::Command, which spawns a Process
+/// with uv_spawn.
+///
+/// Unlike std::process::Command, spawning doesn't block and there's no wait(): the exit status
+/// is passed to a callback when the loop notices that the child has exited. By default, the
+/// child inherits this process's stdin, stdout, stderr, environment and working directory.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::process::{Command, Stdio};
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::stream::Stream;
+/// # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// #
+/// # #[cfg(unix)]
+/// # fn main() {
+/// let r#loop = Loop::new().unwrap();
+/// let exit = Rc::new(RefCell::new(None));
+/// let status = exit.clone();
+/// let process = Command::new("sh")
+///     .args(&["-c", "echo $GREETING from $0; exit 3", "sh"])
+///     .env_clear()
+///     .env("GREETING", "hello")
+///     .stdin(Stdio::null())
+///     .stdout(Stdio::piped())
+///     .spawn(&r#loop, move |_, exit_status, term_signal| {
+///         *status.borrow_mut() = Some((exit_status, term_signal));
+///     })
+///     .unwrap();
+/// assert_ne!(process.pid(), 0);
+///
+/// let output = Rc::new(RefCell::new(Vec::new()));
+/// let log = output.clone();
+/// let stdout = process.take_stdout().unwrap();
+/// assert!(process.take_stdout().is_none());
+/// stdout
+///     .read_start(ReusableAllocator::default(), move |stdout, data| match data {
+///         Ok(data) => log.borrow_mut().extend_from_slice(data),
+///         Err(err) => {
+///             assert!(err.is_eof());
+///             stdout.read_stop().unwrap();
+///         }
+///     })
+///     .unwrap();
+///
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// assert_eq!(*output.borrow(), b"hello from sh\n");
+/// assert_eq!(*exit.borrow(), Some((3, 0)));
+/// # }
+/// #
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+///
+/// Killing a child:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::process::Command;
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, SIGTERM};
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// #
+/// # #[cfg(unix)]
+/// # fn main() {
+/// let r#loop = Loop::new().unwrap();
+/// let signal = Rc::new(Cell::new(0));
+/// let term_signal = signal.clone();
+/// let process = Command::new("sleep")
+///     .arg("10")
+///     .spawn(&r#loop, move |_, _, signum| term_signal.set(signum))
+///     .unwrap();
+/// process.kill(SIGTERM).unwrap();
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// assert_eq!(signal.get(), SIGTERM);
+///
+/// // programs that don't exist fail to spawn
+/// let err = Command::new("/does/not/exist").spawn(&r#loop, |_, _, _| {});
+/// assert_eq!(err.err().unwrap().name(), "ENOENT");
+/// # }
+/// #
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub struct Command<'a> {
+    program: OsString,
+    args: Vec<OsString>,
+    env_clear: bool,
+    env: BTreeMap<OsString, Option<OsString>>,
+    cwd: Option<PathBuf>,
+    stdio: Vec<Stdio<'a>>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    flags: uv_process_flags,
+}
+
+impl<'a> Command<'a> {
+    /// Create a new Command for `program`. If `program` isn't a path, it is searched for in the
+    /// PATH.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Command<'a> {
+        Command {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            env_clear: false,
+            env: BTreeMap::new(),
+            cwd: None,
+            stdio: vec![Stdio::inherit(), Stdio::inherit(), Stdio::inherit()],
+            uid: None,
+            gid: None,
+            flags: 0,
+        }
+    }
+
+    /// Add an argument.
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command<'a> {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Add several arguments.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command<'a>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Set an environment variable in the child.
+    pub fn env<K, V>(&mut self, key: K, value: V) -> &mut Command<'a>
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.env.insert(
+            key.as_ref().to_os_string(),
+            Some(value.as_ref().to_os_string()),
+        );
+        self
+    }
+
+    /// Set several environment variables in the child.
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Command<'a>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self.env(key, value);
+        }
+        self
+    }
+
+    /// Remove an environment variable from the child's environment.
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Command<'a> {
+        self.env.insert(key.as_ref().to_os_string(), None);
+        self
+    }
+
+    /// Clear the child's environment, so that it only has the variables set with env().
+    pub fn env_clear(&mut self) -> &mut Command<'a> {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    /// Set the child's working directory.
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Command<'a> {
+        self.cwd = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the child's stdin.
+    pub fn stdin(&mut self, stdio: Stdio<'a>) -> &mut Command<'a> {
+        self.stdio(0, stdio)
+    }
+
+    /// Set the child's stdout.
+    pub fn stdout(&mut self, stdio: Stdio<'a>) -> &mut Command<'a> {
+        self.stdio(1, stdio)
+    }
+
+    /// Set the child's stderr.
+    pub fn stderr(&mut self, stdio: Stdio<'a>) -> &mut Command<'a> {
+        self.stdio(2, stdio)
+    }
+
+    /// Set the child's file descriptor `fd`. Descriptors past stderr that aren't set explicitly
+    /// are closed in the child.
+    pub fn stdio(&mut self, fd: usize, stdio: Stdio<'a>) -> &mut Command<'a> {
+        while self.stdio.len() <= fd {
+            self.stdio.push(Stdio::null());
+        }
+        self.stdio[fd] = stdio;
+        self
+    }
+
+    /// Run the child as the user `uid`. This isn't supported on Windows.
+    pub fn uid(&mut self, uid: u32) -> &mut Command<'a> {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Run the child with the group `gid`. This isn't supported on Windows.
+    pub fn gid(&mut self, gid: u32) -> &mut Command<'a> {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Run the child in its own process group (or, on Windows, without a console), so that it
+    /// can keep running after this process exits. The loop still waits for a detached child,
+    /// unless its Process is dropped or unreferenced.
+    pub fn detached(&mut self, detached: bool) -> &mut Command<'a> {
+        self.flag(uv_process_flags_UV_PROCESS_DETACHED, detached)
+    }
+
+    /// On Windows, hide the child's window. This has no effect on other platforms.
+    pub fn windows_hide(&mut self, hide: bool) -> &mut Command<'a> {
+        self.flag(uv_process_flags_UV_PROCESS_WINDOWS_HIDE, hide)
+    }
+
+    /// On Windows, pass the arguments to the child without quoting or escaping them. This has no
+    /// effect on other platforms.
+    pub fn windows_verbatim_arguments(&mut self, verbatim: bool) -> &mut Command<'a> {
+        self.flag(
+            uv_process_flags_UV_PROCESS_WINDOWS_VERBATIM_ARGUMENTS,
+            verbatim,
+        )
+    }
+
+    fn flag(&mut self, flag: uv_process_flags, enable: bool) -> &mut Command<'a> {
+        if enable {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+        self
+    }
+
+    /// The child's environment as "KEY=VALUE" strings, or None to inherit this process's.
+    fn environment(&self) -> Result<Option<Vec<CString>>> {
+        if !self.env_clear && self.env.is_empty() {
+            return Ok(None);
+        }
+
+        let mut vars: BTreeMap<OsString, OsString> = BTreeMap::new();
+        if !self.env_clear {
+            vars.extend(env::vars_os());
+        }
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => vars.insert(key.clone(), value.clone()),
+                None => vars.remove(key),
+            };
+        }
+        vars.into_iter()
+            .map(|(mut var, value)| {
+                var.push("=");
+                var.push(value);
+                cstring(&var)
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// Spawn the child. `on_exit` is called with the exit status and the signal that terminated
+    /// the child (or zero) once the child has exited.
+    ///
+    /// Any pipes requested with Stdio::piped() are created on `loop`, and can be taken from the
+    /// returned Process.
+    pub fn spawn<F>(&mut self, r#loop: &Loop, on_exit: F) -> Result<Process>
+    where
+        F: FnOnce(&Process, i64, u32) + 'static,
+    {
+        // All of the strings must outlive the call to uv_spawn.
+        let file = cstring(&self.program)?;
+        let mut args = vec![file.clone()];
+        for arg in &self.args {
+            args.push(cstring(arg)?);
+        }
+        let arg_ptrs = pointers(&args);
+        let env = self.environment()?;
+        let env_ptrs = env.as_deref().map(pointers);
+        let cwd = match &self.cwd {
+            Some(cwd) => Some(cstring(cwd.as_os_str())?),
+            None => None,
+        };
+
+        let mut pipes = Vec::with_capacity(self.stdio.len());
+        let mut containers = Vec::with_capacity(self.stdio.len());
+        for (fd, stdio) in self.stdio.iter().enumerate() {
+            let (flags, data, pipe) = match stdio.0 {
+                StdioKind::Inherit => (
+                    uv_stdio_flags_UV_INHERIT_FD,
+                    uv_stdio_container_s__bindgen_ty_1 { fd: fd as c_int },
+                    None,
+                ),
+                StdioKind::Null => (
+                    uv_stdio_flags_UV_IGNORE,
+                    uv_stdio_container_s__bindgen_ty_1 { fd: -1 },
+                    None,
+                ),
+                StdioKind::Piped { ipc } => {
+                    // The flags are from the child's point of view.
+                    let direction = match fd {
+                        0 if !ipc => uv_stdio_flags_UV_READABLE_PIPE,
+                        1 | 2 if !ipc => uv_stdio_flags_UV_WRITABLE_PIPE,
+                        _ => uv_stdio_flags_UV_READABLE_PIPE | uv_stdio_flags_UV_WRITABLE_PIPE,
+                    };
+                    let pipe = Pipe::new(r#loop, ipc)?;
+                    let stream = pipe.as_stream_ptr();
+                    (
+                        uv_stdio_flags_UV_CREATE_PIPE | direction,
+                        uv_stdio_container_s__bindgen_ty_1 { stream },
+                        Some(pipe),
+                    )
+                }
+                StdioKind::Fd(fd) => (
+                    uv_stdio_flags_UV_INHERIT_FD,
+                    uv_stdio_container_s__bindgen_ty_1 { fd },
+                    None,
+                ),
+                StdioKind::Stream(stream, _) => (
+                    uv_stdio_flags_UV_INHERIT_STREAM,
+                    uv_stdio_container_s__bindgen_ty_1 { stream },
+                    None,
+                ),
+            };
+            containers.push(uv_stdio_container_t { flags, data });
+            pipes.push(pipe);
+        }
+
+        let mut flags = self.flags;
+        if self.uid.is_some() {
+            flags |= uv_process_flags_UV_PROCESS_SETUID;
+        }
+        if self.gid.is_some() {
+            flags |= uv_process_flags_UV_PROCESS_SETGID;
+        }
+
+        let mut options: uv_process_options_t = unsafe { mem::zeroed() };
+        options.exit_cb = Some(exit_cb);
+        options.file = file.as_ptr();
+        options.args = arg_ptrs.as_ptr() as *mut *mut c_char;
+        options.env = env_ptrs
+            .as_ref()
+            .map_or(ptr::null_mut(), |env| env.as_ptr() as *mut *mut c_char);
+        options.cwd = cwd.as_ref().map_or(ptr::null(), |cwd| cwd.as_ptr());
+        options.flags = flags as _;
+        options.stdio_count = containers.len() as c_int;
+        options.stdio = containers.as_mut_ptr();
+        options.uid = self.uid.unwrap_or(0) as _;
+        options.gid = self.gid.unwrap_or(0) as _;
+
+        let state = ProcessState {
+            exit: Some(Box::new(on_exit)),
+            pipes,
+        };
+
+        // uv_spawn initializes the handle even if it fails to spawn the child, so the handle is
+        // allocated first, and must be closed either way.
+        let handle = unsafe { handle::alloc(r#loop, state, |_, _| Ok(0))? };
+        let process = Process { handle };
+        unsafe { uvret!(uv_spawn(r#loop.as_ptr(), handle, &options)) }?;
+        Ok(process)
+    }
+}
+
+/// A safe wrapper around uv_process_t: a child process, created by Command::spawn().
+///
+/// Dropping the Process doesn't kill the child; it only stops watching it, so the exit callback
+/// is never called.
+pub struct Process {
+    handle: *mut uv_process_t,
+}
+
+impl Process {
+    /// The child's process ID.
+    pub fn pid(&self) -> i32 {
+        unsafe { uv_process_get_pid(self.handle) as i32 }
+    }
+
+    /// Send the signal `signum` (ie, SIGTERM) to the child. On Windows, SIGTERM, SIGKILL and
+    /// SIGINT all terminate the child.
+    pub fn kill(&self, signum: u32) -> Result<()> {
+        unsafe { uvret!(uv_process_kill(self.handle, signum as c_int)) }?;
+        Ok(())
+    }
+
+    /// Take the parent's end of the child's stdin, if it was created with Stdio::piped().
+    pub fn take_stdin(&self) -> Option<Pipe> {
+        self.take_pipe(0)
+    }
+
+    /// Take the parent's end of the child's stdout, if it was created with Stdio::piped().
+    pub fn take_stdout(&self) -> Option<Pipe> {
+        self.take_pipe(1)
+    }
+
+    /// Take the parent's end of the child's stderr, if it was created with Stdio::piped().
+    pub fn take_stderr(&self) -> Option<Pipe> {
+        self.take_pipe(2)
+    }
+
+    /// Take the parent's end of the child's file descriptor `fd`, if it was created with
+    /// Stdio::piped(). Each pipe can only be taken once; any that aren't taken are closed when
+    /// the Process is dropped.
+    pub fn take_pipe(&self, fd: usize) -> Option<Pipe> {
+        unsafe {
+            let state = handle::state::<_, ProcessState>(self.handle);
+            let pipes = &mut (*state).pipes;
+            pipes.get_mut(fd).and_then(Option::take)
+        }
+    }
+
+    /// Returns true until the child has exited.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the handle belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, ProcessState>(self.handle) }
+    }
+
+    /// The raw uv_process_t.
+    pub fn as_ptr(&self) -> *mut uv_process_t {
+        self.handle
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        unsafe { handle::close::<_, ProcessState>(self.handle) }
+    }
+}
+
+/// Send the signal `signum` to the process `pid`.
+pub fn kill(pid: i32, signum: u32) -> Result<()> {
+    unsafe { uvret!(uv_kill(pid as c_int, signum as c_int)) }?;
+    Ok(())
+}
+
+unsafe extern "C" fn exit_cb(handle: *mut uv_process_t, exit_status: i64, term_signal: c_int) {
+    let state = handle::state::<_, ProcessState>(handle);
+    if let Some(cb) = (*state).exit.take() {
+        // The Process passed to the callback doesn't own the handle, so it must not be dropped.
+        let process = ManuallyDrop::new(Process { handle });
+        cb(&process, exit_status, term_signal as u32);
+    }
+}
@@ -0,0 +1,375 @@
+//! Spawning a child and collecting its output and exit status, without blocking the loop.
+
+use super::{Command, Process, Stdio};
+use crate::error::Result;
+use crate::event_loop::Loop;
+use crate::pipe::Pipe;
+use crate::stream::allocator::ReusableAllocator;
+use crate::stream::Stream;
+use crate::timer::Timer;
+use crate::SIGKILL;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The default limit on how much of each of stdout and stderr is collected: 1MiB.
+pub const DEFAULT_OUTPUT_LIMIT: usize = 1024 * 1024;
+
+/// How a child process exited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExitStatus {
+    code: i64,
+    signal: u32,
+    timed_out: bool,
+}
+
+impl ExitStatus {
+    /// Returns true if the child exited normally with a status of zero.
+    pub fn success(&self) -> bool {
+        self.signal == 0 && self.code == 0
+    }
+
+    /// The child's exit status, or None if it was terminated by a signal.
+    pub fn code(&self) -> Option<i64> {
+        if self.signal == 0 {
+            Some(self.code)
+        } else {
+            None
+        }
+    }
+
+    /// The signal that terminated the child, if any.
+    pub fn signal(&self) -> Option<u32> {
+        if self.signal == 0 {
+            None
+        } else {
+            Some(self.signal)
+        }
+    }
+
+    /// Returns true if the child was killed because it ran past its timeout.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+}
+
+/// The output of a finished child process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+
+    /// True if stdout or stderr was cut off at the limit. The rest of the output is read and
+    /// discarded, so the child doesn't stall on a full pipe.
+    pub truncated: bool,
+}
+
+/// Options for Command::output().
+#[derive(Clone, Debug)]
+pub struct OutputOptions {
+    /// The most that is collected from each of stdout and stderr.
+    pub limit: usize,
+
+    /// Data to write to the child's stdin, which is closed afterward. If None, stdin is
+    /// connected to /dev/null.
+    pub input: Option<Vec<u8>>,
+
+    /// How long the child may run before it is killed with SIGKILL.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        OutputOptions {
+            limit: DEFAULT_OUTPUT_LIMIT,
+            input: None,
+            timeout: None,
+        }
+    }
+}
+
+type DoneCallback = dyn FnOnce(Output);
+
+/// The state shared by the callbacks of a child whose output is being collected. The handles
+/// are kept here, and released once everything has finished.
+struct Collector {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+    exit: Option<(i64, u32)>,
+    timed_out: bool,
+
+    /// The number of output pipes that haven't reached EOF.
+    open: usize,
+
+    process: Option<Process>,
+    stdin: Option<Pipe>,
+    pipes: Vec<Pipe>,
+    timer: Option<Timer>,
+    done: Option<Box<DoneCallback>>,
+}
+
+impl Collector {
+    fn append(&mut self, fd: usize, data: &[u8]) {
+        let buffer = if fd == 1 {
+            &mut self.stdout
+        } else {
+            &mut self.stderr
+        };
+        let room = self.limit.saturating_sub(buffer.len());
+        if data.len() > room {
+            self.truncated = true;
+        }
+        buffer.extend_from_slice(&data[..data.len().min(room)]);
+    }
+}
+
+/// Deliver the output once the child has exited and both pipes have reached EOF. The result
+/// only depends on all three having happened, not the order they happened in.
+fn finish(collector: &Rc<RefCell<Collector>>) {
+    let (done, output) = {
+        let mut c = collector.borrow_mut();
+        if c.open > 0 || c.done.is_none() {
+            return;
+        }
+        let (code, signal) = match c.exit {
+            Some(exit) => exit,
+            None => return,
+        };
+
+        // Release the handles, which also breaks the reference cycles between the collector and
+        // the handles' callbacks.
+        c.process = None;
+        c.stdin = None;
+        c.pipes.clear();
+        c.timer = None;
+
+        let output = Output {
+            status: ExitStatus {
+                code,
+                signal,
+                timed_out: c.timed_out,
+            },
+            stdout: std::mem::take(&mut c.stdout),
+            stderr: std::mem::take(&mut c.stderr),
+            truncated: c.truncated,
+        };
+        (c.done.take(), output)
+    };
+    if let Some(done) = done {
+        done(output);
+    }
+}
+
+impl<'a> Command<'a> {
+    /// Spawn the child with its stdout and stderr piped, and collect its output. `cb` is called
+    /// with the output once the child has exited and both pipes have reached EOF. Returns the
+    /// child's pid.
+    ///
+    /// Whatever this Command's stdin, stdout and stderr were set to is ignored, but kept for
+    /// later calls. If the child outlives its timeout, it is killed and its output so far is
+    /// returned; if it has already exited, but something else is holding the pipes open (such
+    /// as a grandchild), collection simply stops.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use libuv_sys2::event_loop::Loop;
+    /// # use libuv_sys2::process::output::OutputOptions;
+    /// # use libuv_sys2::process::Command;
+    /// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, SIGKILL};
+    /// # use std::cell::RefCell;
+    /// # use std::rc::Rc;
+    /// # use std::time::Duration;
+    /// #
+    /// # #[cfg(unix)]
+    /// # fn main() {
+    /// let r#loop = Loop::new().unwrap();
+    /// let results = Rc::new(RefCell::new(Vec::new()));
+    ///
+    /// let options = OutputOptions {
+    ///     input: Some(b"hello".to_vec()),
+    ///     ..OutputOptions::default()
+    /// };
+    /// let log = results.clone();
+    /// Command::new("sh")
+    ///     .args(&["-c", "cat; echo oops >&2; exit 2"])
+    ///     .output(&r#loop, options, move |output| log.borrow_mut().push(output))
+    ///     .unwrap();
+    ///
+    /// // a child that runs too long is killed
+    /// let options = OutputOptions {
+    ///     limit: 4,
+    ///     timeout: Some(Duration::from_millis(100)),
+    ///     ..OutputOptions::default()
+    /// };
+    /// let log = results.clone();
+    /// Command::new("sh")
+    ///     .args(&["-c", "echo started; exec sleep 10"])
+    ///     .output(&r#loop, options, move |output| log.borrow_mut().push(output))
+    ///     .unwrap();
+    ///
+    /// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+    /// let mut results = results.borrow_mut();
+    /// results.sort_by_key(|output| output.status.timed_out());
+    ///
+    /// assert_eq!(results[0].stdout, b"hello");
+    /// assert_eq!(results[0].stderr, b"oops\n");
+    /// assert_eq!(results[0].status.code(), Some(2));
+    /// assert!(!results[0].truncated);
+    ///
+    /// assert_eq!(results[1].stdout, b"star");
+    /// assert!(results[1].truncated);
+    /// assert!(results[1].status.timed_out());
+    /// assert_eq!(results[1].status.signal(), Some(SIGKILL));
+    /// # }
+    /// #
+    /// # #[cfg(not(unix))]
+    /// # fn main() {}
+    /// ```
+    pub fn output<F>(&mut self, r#loop: &Loop, options: OutputOptions, cb: F) -> Result<i32>
+    where
+        F: FnOnce(Output) + 'static,
+    {
+        let stdin = match options.input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        };
+        let stdio = vec![stdin, Stdio::piped(), Stdio::piped()];
+        let saved: Vec<Stdio<'a>> = self.stdio.splice(0..3, stdio).collect();
+        let collector = self.collect(r#loop, options.limit, 2, options.timeout, Box::new(cb));
+        self.stdio.splice(0..3, saved);
+        let collector = collector?;
+
+        let mut c = collector.borrow_mut();
+        let process = c.process.as_ref().expect("the child was just spawned");
+        let pid = process.pid();
+        let stdin = process.take_stdin();
+        let pipes = vec![
+            process.take_stdout().expect("stdout is piped"),
+            process.take_stderr().expect("stderr is piped"),
+        ];
+
+        // Once the input has been written, stdin is closed so that the child sees EOF. Write
+        // errors are ignored: the child is free to exit without reading its input.
+        if let (Some(stdin), Some(input)) = (stdin, options.input) {
+            let weak = Rc::downgrade(&collector);
+            let ret = stdin.write(input, move |_, _| {
+                if let Some(collector) = weak.upgrade() {
+                    collector.borrow_mut().stdin = None;
+                }
+            });
+            if ret.is_ok() {
+                c.stdin = Some(stdin);
+            }
+        }
+
+        for (fd, pipe) in (1..).zip(pipes) {
+            let shared = collector.clone();
+            let ret = pipe.read_start(ReusableAllocator::default(), move |pipe, data| match data {
+                Ok(data) => shared.borrow_mut().append(fd, data),
+
+                // EOF, or an error that ends the stream just the same.
+                Err(_) => {
+                    let _ = pipe.read_stop();
+                    let open = {
+                        let mut c = shared.borrow_mut();
+                        c.open -= 1;
+                        c.open
+                    };
+                    if open == 0 {
+                        finish(&shared);
+                    }
+                }
+            });
+            match ret {
+                Ok(()) => c.pipes.push(pipe),
+                Err(_) => c.open -= 1,
+            }
+        }
+        drop(c);
+
+        // Everything may have finished already if reading failed.
+        finish(&collector);
+        Ok(pid)
+    }
+
+    /// Spawn the child and call `cb` with its exit status once it has exited, killing it with
+    /// SIGKILL if it runs longer than `timeout`. The child's stdio is whatever this Command was
+    /// set up with. Returns the child's pid.
+    pub fn status<F>(&mut self, r#loop: &Loop, timeout: Option<Duration>, cb: F) -> Result<i32>
+    where
+        F: FnOnce(ExitStatus) + 'static,
+    {
+        let done = Box::new(move |output: Output| cb(output.status));
+        let collector = self.collect(r#loop, 0, 0, timeout, done)?;
+        let pid = collector
+            .borrow()
+            .process
+            .as_ref()
+            .expect("the child was just spawned")
+            .pid();
+        Ok(pid)
+    }
+
+    /// Spawn the child, with an exit callback and timeout that report to a new Collector. `open`
+    /// is the number of output pipes that the caller is going to read.
+    fn collect(
+        &mut self,
+        r#loop: &Loop,
+        limit: usize,
+        open: usize,
+        timeout: Option<Duration>,
+        done: Box<DoneCallback>,
+    ) -> Result<Rc<RefCell<Collector>>> {
+        let timer = match timeout {
+            Some(_) => Some(Timer::new(r#loop)?),
+            None => None,
+        };
+        let collector = Rc::new(RefCell::new(Collector {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            limit,
+            truncated: false,
+            exit: None,
+            timed_out: false,
+            open,
+            process: None,
+            stdin: None,
+            pipes: Vec::new(),
+            timer: None,
+            done: Some(done),
+        }));
+
+        let shared = collector.clone();
+        let process = self.spawn(r#loop, move |_, code, signal| {
+            shared.borrow_mut().exit = Some((code, signal));
+            finish(&shared);
+        })?;
+        collector.borrow_mut().process = Some(process);
+
+        if let (Some(timer), Some(timeout)) = (timer, timeout) {
+            let shared = collector.clone();
+            timer.start(timeout, Duration::ZERO, move |_| {
+                let mut c = shared.borrow_mut();
+                if c.exit.is_none() {
+                    // The exit callback finishes up. If the kill fails, the child must have
+                    // exited already.
+                    if let Some(process) = &c.process {
+                        c.timed_out = process.kill(SIGKILL).is_ok();
+                    }
+                } else {
+                    // The child has exited, but the pipes are still open, so stop waiting.
+                    c.pipes.clear();
+                    c.open = 0;
+                    drop(c);
+                    finish(&shared);
+                }
+            })?;
+            collector.borrow_mut().timer = Some(timer);
+        }
+        Ok(collector)
+    }
+}
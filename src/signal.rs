@@ -0,0 +1,262 @@
+use crate::error::Result;
+use crate::event_loop::Loop;
+use crate::handle::{self, Callback};
+use crate::{
+    uv_handle_t, uv_is_active, uv_signal_init, uv_signal_start, uv_signal_start_oneshot,
+    uv_signal_stop, uv_signal_t, uv_unref,
+};
+use std::cell::RefCell;
+use std::mem::{self, ManuallyDrop};
+use std::os::raw::c_int;
+use std::ptr;
+use std::rc::Rc;
+
+type SignalCallback = dyn FnMut(&Signal, u32);
+
+struct SignalState {
+    cb: Callback<SignalCallback>,
+}
+
+/// A safe wrapper around uv_signal_t.
+///
+/// Signal numbers are the SIG* constants from the bindings, ie, SIGINT. The signal handler is
+/// removed when the Signal is dropped.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::signal::Signal;
+/// # #[cfg(unix)]
+/// # use libuv_sys2::{uv_kill, uv_os_getpid, uv_run_mode_UV_RUN_ONCE, SIGUSR1};
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// #
+/// # #[cfg(unix)]
+/// # fn main() {
+/// let r#loop = Loop::new().unwrap();
+/// let oneshot_count = Rc::new(Cell::new(0));
+/// let persistent_count = Rc::new(Cell::new(0));
+///
+/// let oneshot = Signal::new(&r#loop).unwrap();
+/// let count = oneshot_count.clone();
+/// oneshot
+///     .start_oneshot(SIGUSR1, move |_, signum| {
+///         assert_eq!(signum, SIGUSR1);
+///         count.set(count.get() + 1);
+///     })
+///     .unwrap();
+///
+/// // the persistent handler keeps SIGUSR1 from reverting to its default action (terminating the
+/// // process) once the one-shot handler has fired
+/// let persistent = Signal::new(&r#loop).unwrap();
+/// let count = persistent_count.clone();
+/// persistent
+///     .start(SIGUSR1, move |_, _| count.set(count.get() + 1))
+///     .unwrap();
+///
+/// for _ in 0..2 {
+///     unsafe { uv_kill(uv_os_getpid(), SIGUSR1 as _) };
+///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// }
+/// assert_eq!(oneshot_count.get(), 1);
+/// assert_eq!(persistent_count.get(), 2);
+/// assert!(!oneshot.is_active());
+/// assert!(persistent.is_active());
+/// # }
+/// #
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub struct Signal {
+    handle: *mut uv_signal_t,
+}
+
+impl Signal {
+    /// Create a new signal handle.
+    pub fn new(r#loop: &Loop) -> Result<Signal> {
+        let state = SignalState {
+            cb: Callback::empty(),
+        };
+        let handle = unsafe {
+            handle::alloc(r#loop, state, |r#loop, handle| {
+                uvret!(uv_signal_init(r#loop, handle))
+            })?
+        };
+        Ok(Signal { handle })
+    }
+
+    /// Start watching for `signum`. `cb` is called, with the signal number, every time the signal
+    /// is received until the handle is stopped. If the handle is already started, it is updated
+    /// with the new signal and callback.
+    pub fn start<F>(&self, signum: u32, cb: F) -> Result<()>
+    where
+        F: FnMut(&Signal, u32) + 'static,
+    {
+        unsafe {
+            let state = handle::state::<_, SignalState>(self.handle);
+            (*state).cb.set(Box::new(cb));
+            uvret!(uv_signal_start(
+                self.handle,
+                Some(signal_cb),
+                signum as c_int
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Like start(), except the handle is automatically stopped after the first time the signal
+    /// is received, so `cb` is called at most once.
+    pub fn start_oneshot<F>(&self, signum: u32, cb: F) -> Result<()>
+    where
+        F: FnMut(&Signal, u32) + 'static,
+    {
+        unsafe {
+            let state = handle::state::<_, SignalState>(self.handle);
+            (*state).cb.set(Box::new(cb));
+            uvret!(uv_signal_start_oneshot(
+                self.handle,
+                Some(signal_cb),
+                signum as c_int
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Stop watching for the signal.
+    pub fn stop(&self) -> Result<()> {
+        unsafe { uvret!(uv_signal_stop(self.handle)) }?;
+        Ok(())
+    }
+
+    /// Returns true if the handle is watching for a signal.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the handle belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, SignalState>(self.handle) }
+    }
+
+    /// The raw uv_signal_t.
+    pub fn as_ptr(&self) -> *mut uv_signal_t {
+        self.handle
+    }
+}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        unsafe { handle::close::<_, SignalState>(self.handle) }
+    }
+}
+
+unsafe extern "C" fn signal_cb(handle: *mut uv_signal_t, signum: c_int) {
+    // The Signal passed to the callback doesn't own the handle, so it must not be dropped.
+    let signal = ManuallyDrop::new(Signal { handle });
+    let state = handle::state::<_, SignalState>(handle);
+    handle::invoke(ptr::addr_of_mut!((*state).cb), |cb| {
+        cb(&signal, signum as u32)
+    });
+}
+
+/// The signal handlers installed by on_shutdown(). The handlers are removed when this is dropped.
+pub struct ShutdownSignals {
+    signals: Vec<Signal>,
+}
+
+impl ShutdownSignals {
+    /// The individual signal handles.
+    pub fn signals(&self) -> &[Signal] {
+        &self.signals
+    }
+}
+
+struct ShutdownState {
+    cb: Option<Box<dyn FnOnce(u32)>>,
+    handles: Vec<*mut uv_signal_t>,
+}
+
+/// Install one-shot handlers for `signals` (typically SIGINT and SIGTERM) that call `cb`, with
+/// the signal number, the first time any of them is received. At that point, all of the handlers
+/// are stopped, restoring the default action for each signal, so a second Ctrl+C will terminate
+/// the process as usual.
+///
+/// The handlers don't keep the loop alive (see uv_unref), so a program that finishes its work
+/// exits normally without waiting for a signal.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::signal::on_shutdown;
+/// # use libuv_sys2::timer::Timer;
+/// # #[cfg(unix)]
+/// # use libuv_sys2::{uv_kill, uv_os_getpid, uv_run_mode_UV_RUN_ONCE, SIGINT, SIGTERM};
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// # use std::time::Duration;
+/// #
+/// # #[cfg(unix)]
+/// # fn main() {
+/// let r#loop = Loop::new().unwrap();
+///
+/// // the shutdown handlers don't keep the loop alive, but this timer will
+/// let timer = Timer::new(&r#loop).unwrap();
+/// timer.start(Duration::from_secs(60), Duration::ZERO, |_| {}).unwrap();
+///
+/// let received = Rc::new(Cell::new(None));
+/// let shutdown = {
+///     let received = received.clone();
+///     on_shutdown(&r#loop, [SIGINT, SIGTERM], move |signum| {
+///         received.set(Some(signum));
+///     })
+///     .unwrap()
+/// };
+///
+/// unsafe { uv_kill(uv_os_getpid(), SIGTERM as _) };
+/// r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// assert_eq!(received.get(), Some(SIGTERM));
+/// assert!(shutdown.signals().iter().all(|signal| !signal.is_active()));
+/// # }
+/// #
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub fn on_shutdown<I, F>(r#loop: &Loop, signals: I, cb: F) -> Result<ShutdownSignals>
+where
+    I: IntoIterator<Item = u32>,
+    F: FnOnce(u32) + 'static,
+{
+    let shared = Rc::new(RefCell::new(ShutdownState {
+        cb: Some(Box::new(cb)),
+        handles: Vec::new(),
+    }));
+
+    let mut shutdown = ShutdownSignals {
+        signals: Vec::new(),
+    };
+    for signum in signals {
+        let signal = Signal::new(r#loop)?;
+        let state = shared.clone();
+        signal.start_oneshot(signum, move |_, signum| {
+            let (cb, handles) = {
+                let mut state = state.borrow_mut();
+                (state.cb.take(), mem::take(&mut state.handles))
+            };
+
+            // The handles are all owned by the ShutdownSignals; since this callback is running,
+            // it hasn't been dropped yet, so they're all still valid.
+            for handle in handles {
+                unsafe { uv_signal_stop(handle) };
+            }
+            if let Some(cb) = cb {
+                cb(signum);
+            }
+        })?;
+        unsafe { uv_unref(signal.handle as *mut uv_handle_t) };
+        shared.borrow_mut().handles.push(signal.handle);
+        shutdown.signals.push(signal);
+    }
+    Ok(shutdown)
+}
@@ -0,0 +1,638 @@
+//! The Stream trait, which is implemented by the safe wrappers for uv_stream_t types.
+
+use crate::error::{Result, UvError};
+use crate::handle::{self, Callback};
+use crate::{
+    uv_buf_t, uv_connect_cb, uv_connect_t, uv_errno_t_UV_EAGAIN, uv_errno_t_UV_EINVAL, uv_handle_t,
+    uv_is_closing, uv_is_readable, uv_is_writable, uv_listen, uv_read_start, uv_read_stop,
+    uv_stream_get_write_queue_size, uv_stream_t, uv_try_write, uv_write, uv_write2, uv_write_t,
+};
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::rc::{Rc, Weak};
+
+pub mod allocator;
+
+use self::allocator::{Allocator, ReadBuffer};
+
+pub(crate) mod sealed {
+    use crate::uv_stream_t;
+
+    /// Implemented by the stream wrappers in this crate. A wrapper's handle must have been created
+    /// by handle::alloc() with a StreamState.
+    pub trait StreamHandle {
+        /// The raw handle, as a uv_stream_t.
+        fn stream_ptr(&self) -> *mut uv_stream_t;
+
+        /// Wrap a raw handle. The wrapper will close the handle when it is dropped, so wrappers
+        /// that are passed to callbacks must not be dropped.
+        ///
+        /// # Safety
+        /// `ptr` must point to a handle of the wrapper's type.
+        unsafe fn from_stream_ptr(ptr: *mut uv_stream_t) -> Self;
+    }
+}
+
+/// The default high watermark for send(), in bytes.
+pub const DEFAULT_HIGH_WATERMARK: usize = 64 * 1024;
+
+/// The default low watermark for send(), in bytes.
+pub const DEFAULT_LOW_WATERMARK: usize = 16 * 1024;
+
+/// The state attached to every stream handle. A wrapper may attach a state of its own instead,
+/// as long as it's repr(C) and starts with a StreamState (see handle::HandleData).
+pub(crate) struct StreamState {
+    reader: Callback<dyn Reader>,
+    connection: Callback<dyn FnMut(c_int)>,
+    drain: Callback<dyn FnMut(*mut uv_stream_t)>,
+    high_watermark: usize,
+    low_watermark: usize,
+
+    /// Set when send() returns WouldBlock; cleared when the drain callback is called.
+    blocked: bool,
+
+    /// The first error from a write that was queued by send(), which is returned by the next call
+    /// to send().
+    write_error: Option<UvError>,
+
+    /// Dropped along with the handle, so that operations that outlive a borrow of the stream,
+    /// such as fs::copy::send_file(), can tell whether it still exists.
+    alive: Rc<()>,
+}
+
+impl StreamState {
+    pub(crate) fn new() -> StreamState {
+        StreamState {
+            reader: Callback::empty(),
+            connection: Callback::empty(),
+            drain: Callback::empty(),
+            high_watermark: DEFAULT_HIGH_WATERMARK,
+            low_watermark: DEFAULT_LOW_WATERMARK,
+            blocked: false,
+            write_error: None,
+            alive: Rc::new(()),
+        }
+    }
+}
+
+unsafe fn stream_state(stream: *const uv_stream_t) -> *mut StreamState {
+    handle::state::<_, StreamState>(stream)
+}
+
+/// A token that can no longer be upgraded once `stream` has been deallocated. The stream may
+/// still be closing while it can be upgraded.
+pub(crate) unsafe fn alive(stream: *const uv_stream_t) -> Weak<()> {
+    let state = &*stream_state(stream);
+    Rc::downgrade(&state.alive)
+}
+
+/// Start listening for connections. The wrappers accept connections themselves, so `cb` is simply
+/// called with the status of each incoming connection.
+pub(crate) unsafe fn listen(
+    stream: *mut uv_stream_t,
+    backlog: c_int,
+    cb: Box<dyn FnMut(c_int)>,
+) -> Result<()> {
+    (*stream_state(stream)).connection.set(cb);
+    uvret!(uv_listen(stream, backlog, Some(connection_cb)))?;
+    Ok(())
+}
+
+unsafe extern "C" fn connection_cb(stream: *mut uv_stream_t, status: c_int) {
+    let state = stream_state(stream);
+    handle::invoke(ptr::addr_of_mut!((*state).connection), |cb| cb(status));
+}
+
+/// A connection attempt that is in flight. Like a WriteRequest, the request lives on the heap
+/// until its callback is called.
+#[repr(C)]
+pub(crate) struct ConnectRequest<S, F> {
+    /// This must be the first field so that the uv_connect_t pointer that libuv passes to the
+    /// callback can be cast back to the ConnectRequest.
+    req: uv_connect_t,
+    stream: *mut uv_stream_t,
+    cb: F,
+    _stream: PhantomData<fn(&S)>,
+}
+
+impl<S, F> ConnectRequest<S, F>
+where
+    S: Stream,
+    F: FnOnce(&S, Result<()>) + 'static,
+{
+    /// Allocate a request and pass it, along with the callback that libuv should call, to
+    /// `connect`, which should call the appropriate uv_*_connect function.
+    pub(crate) unsafe fn submit<C>(stream: *mut uv_stream_t, cb: F, connect: C) -> Result<()>
+    where
+        C: FnOnce(*mut uv_connect_t, uv_connect_cb) -> Result<c_int>,
+    {
+        let req = Box::into_raw(Box::new(ConnectRequest {
+            req: mem::zeroed(),
+            stream,
+            cb,
+            _stream: PhantomData::<fn(&S)>,
+        }));
+        if let Err(err) = connect(req as *mut uv_connect_t, Some(connect_cb::<S, F>)) {
+            mem::drop(Box::from_raw(req));
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn connect_cb<S, F>(req: *mut uv_connect_t, status: c_int)
+where
+    S: Stream,
+    F: FnOnce(&S, Result<()>) + 'static,
+{
+    let ConnectRequest { stream, cb, .. } = *Box::from_raw(req as *mut ConnectRequest<S, F>);
+    let result = match status {
+        0 => Ok(()),
+        code => Err(UvError::new("uv_connect", code)),
+    };
+
+    // The wrapper passed to the callback doesn't own the handle, so it must not be dropped.
+    cb(&ManuallyDrop::new(S::from_stream_ptr(stream)), result);
+}
+
+/// A type-erased read callback, along with the allocator that provides its buffers.
+trait Reader {
+    fn alloc(&mut self, suggested_size: usize) -> uv_buf_t;
+
+    unsafe fn read(&mut self, stream: *mut uv_stream_t, nread: isize);
+}
+
+struct TypedReader<S, A: Allocator, F> {
+    allocator: A,
+
+    /// The buffer from the last call to alloc(), which libuv is reading into.
+    pending: Option<A::Buffer>,
+
+    cb: F,
+    _stream: PhantomData<fn(&S)>,
+}
+
+impl<S, A, F> Reader for TypedReader<S, A, F>
+where
+    S: Stream,
+    A: Allocator,
+    F: FnMut(&S, Result<A::Buffer>),
+{
+    fn alloc(&mut self, suggested_size: usize) -> uv_buf_t {
+        let buffer = self.pending.insert(self.allocator.alloc(suggested_size));
+        let memory = buffer.capacity_mut();
+        uv_buf_t {
+            base: memory.as_mut_ptr() as *mut c_char,
+            len: memory.len() as _,
+        }
+    }
+
+    unsafe fn read(&mut self, stream: *mut uv_stream_t, nread: isize) {
+        // nread may be zero, which is the equivalent of EAGAIN: there's nothing to report, but
+        // libuv is giving back the buffer.
+        let buffer = self.pending.take();
+        if nread == 0 {
+            return;
+        }
+
+        // The wrapper passed to the callback doesn't own the handle, so it must not be dropped.
+        let stream = ManuallyDrop::new(S::from_stream_ptr(stream));
+        match buffer {
+            Some(mut buffer) if nread > 0 => {
+                buffer.set_len(nread as usize);
+                (self.cb)(&stream, Ok(buffer));
+            }
+            _ => (self.cb)(&stream, Err(UvError::new("uv_read_start", nread as c_int))),
+        }
+    }
+}
+
+/// Build a uv_buf_t that points at `data`. libuv never writes to the memory of a buffer that is
+/// being written, so the const-to-mut cast is fine.
+pub(crate) fn write_buf(data: &[u8]) -> uv_buf_t {
+    uv_buf_t {
+        base: data.as_ptr() as *mut c_char,
+        len: data.len() as _,
+    }
+}
+
+/// A write that is in flight. libuv requires both the request and the memory being written to stay
+/// put until the write callback is called, so both live on the heap until then.
+#[repr(C)]
+pub(crate) struct WriteRequest<S, B, F> {
+    /// This must be the first field so that the uv_write_t pointer that libuv passes to the
+    /// callback can be cast back to the WriteRequest.
+    req: uv_write_t,
+    stream: *mut uv_stream_t,
+    bufs: Vec<B>,
+    cb: F,
+    _stream: PhantomData<fn(&S)>,
+}
+
+impl<S, B, F> WriteRequest<S, B, F>
+where
+    S: Stream,
+    B: AsRef<[u8]> + 'static,
+    F: FnOnce(&S, Result<()>) + 'static,
+{
+    /// Queue a write. If `send_handle` isn't null, it's sent along with the data, with
+    /// uv_write2; `stream` must then be an IPC pipe.
+    pub(crate) unsafe fn submit(
+        stream: *mut uv_stream_t,
+        bufs: Vec<B>,
+        send_handle: *mut uv_stream_t,
+        cb: F,
+    ) -> Result<()> {
+        // libuv asserts that there's at least one buffer
+        if bufs.is_empty() {
+            return Err(UvError::new("uv_write", uv_errno_t_UV_EINVAL));
+        }
+
+        let raw_bufs: Vec<uv_buf_t> = bufs.iter().map(|buf| write_buf(buf.as_ref())).collect();
+        let req = Box::into_raw(Box::new(WriteRequest {
+            req: mem::zeroed(),
+            stream,
+            bufs,
+            cb,
+            _stream: PhantomData::<fn(&S)>,
+        }));
+
+        // libuv copies the uv_buf_t array itself, so only the memory it points at needs to live
+        // as long as the request.
+        let ret = if send_handle.is_null() {
+            uvret!(uv_write(
+                req as *mut uv_write_t,
+                stream,
+                raw_bufs.as_ptr(),
+                raw_bufs.len() as _,
+                Some(write_cb::<S, B, F>)
+            ))
+        } else {
+            uvret!(uv_write2(
+                req as *mut uv_write_t,
+                stream,
+                raw_bufs.as_ptr(),
+                raw_bufs.len() as _,
+                send_handle,
+                Some(write_cb::<S, B, F>)
+            ))
+        };
+        if let Err(err) = ret {
+            mem::drop(Box::from_raw(req));
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn write_cb<S, B, F>(req: *mut uv_write_t, status: c_int)
+where
+    S: Stream,
+    B: AsRef<[u8]> + 'static,
+    F: FnOnce(&S, Result<()>) + 'static,
+{
+    let WriteRequest {
+        stream, bufs, cb, ..
+    } = *Box::from_raw(req as *mut WriteRequest<S, B, F>);
+    let result = match status {
+        0 => Ok(()),
+        code => Err(UvError::new("uv_write", code)),
+    };
+
+    // The wrapper passed to the callback doesn't own the handle, so it must not be dropped.
+    cb(&ManuallyDrop::new(S::from_stream_ptr(stream)), result);
+    mem::drop(bufs);
+    check_drain(stream);
+}
+
+/// Call the drain callback if send() has returned WouldBlock and the write queue has since fallen
+/// to the low watermark.
+unsafe fn check_drain(stream: *mut uv_stream_t) {
+    if uv_is_closing(stream as *const uv_handle_t) != 0 {
+        return;
+    }
+    let state = stream_state(stream);
+    if !(*state).blocked || uv_stream_get_write_queue_size(stream) > (*state).low_watermark {
+        return;
+    }
+    (*state).blocked = false;
+    handle::invoke(ptr::addr_of_mut!((*state).drain), |cb| cb(stream));
+}
+
+/// Whether a stream can accept more data from send().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStatus {
+    /// The write queue is at or below the high watermark.
+    Ready,
+
+    /// The write queue is above the high watermark. The data was still queued, but the caller
+    /// should stop sending until the drain callback is called.
+    WouldBlock,
+}
+
+/// The part of a buffer that uv_try_write didn't write.
+struct Unwritten<B> {
+    buf: B,
+    offset: usize,
+}
+
+impl<B: AsRef<[u8]>> AsRef<[u8]> for Unwritten<B> {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf.as_ref()[self.offset..]
+    }
+}
+
+/// A safe wrapper around a uv_stream_t: a Tcp, Pipe or Tty handle.
+///
+/// Reads are delivered to a callback along with a buffer from an Allocator (see the allocator
+/// module). The end of the stream is reported as an error for which UvError::is_eof() returns
+/// true.
+pub trait Stream: sealed::StreamHandle + Sized + 'static {
+    /// The raw handle, as a uv_stream_t.
+    fn as_stream_ptr(&self) -> *mut uv_stream_t {
+        self.stream_ptr()
+    }
+
+    /// Start reading from the stream. `cb` is called with the data from each read; the data is
+    /// only borrowed, and its buffer is given back to `allocator` as soon as `cb` returns. If the
+    /// stream is already reading, the allocator and callback are replaced.
+    fn read_start<A, F>(&self, allocator: A, mut cb: F) -> Result<()>
+    where
+        A: Allocator,
+        F: FnMut(&Self, Result<&[u8]>) + 'static,
+    {
+        self.read_start_owned(
+            allocator,
+            move |stream, result: Result<A::Buffer>| match result {
+                Ok(buffer) => cb(stream, Ok(&buffer)),
+                Err(err) => cb(stream, Err(err)),
+            },
+        )
+    }
+
+    /// Like read_start(), except `cb` takes ownership of each buffer. The buffer is given back to
+    /// `allocator` whenever it is dropped.
+    fn read_start_owned<A, F>(&self, allocator: A, cb: F) -> Result<()>
+    where
+        A: Allocator,
+        F: FnMut(&Self, Result<A::Buffer>) + 'static,
+    {
+        let reader = TypedReader {
+            allocator,
+            pending: None,
+            cb,
+            _stream: PhantomData::<fn(&Self)>,
+        };
+        unsafe {
+            let stream = self.stream_ptr();
+            (*stream_state(stream)).reader.set(Box::new(reader));
+
+            // The new reader is already in place, but uv_read_start fails with UV_EALREADY if the
+            // stream is already reading, so reading is stopped first.
+            uv_read_stop(stream);
+            uvret!(uv_read_start(stream, Some(alloc_cb), Some(read_cb)))?;
+        }
+        Ok(())
+    }
+
+    /// Stop reading from the stream.
+    fn read_stop(&self) -> Result<()> {
+        unsafe { uvret!(uv_read_stop(self.stream_ptr())) }?;
+        Ok(())
+    }
+
+    /// Write `data` to the stream. `data` may be anything that owns its bytes, such as a Vec<u8>,
+    /// a Box<[u8]>, a String or a bytes::Bytes; it is dropped once the write has finished, right
+    /// after `cb` is called with the result. Any number of writes may be in flight at once; they
+    /// are written in the order that they were queued.
+    ///
+    /// If the write can't be queued, an error is returned and `cb` is never called. Writes that
+    /// are still queued when the stream is closed fail with UV_ECANCELED.
+    fn write<B, F>(&self, data: B, cb: F) -> Result<()>
+    where
+        B: AsRef<[u8]> + 'static,
+        F: FnOnce(&Self, Result<()>) + 'static,
+    {
+        self.write_vectored(vec![data], cb)
+    }
+
+    /// Like write(), except the buffers in `bufs` are written, in order, as a single write.
+    /// Returns an error (UV_EINVAL) if `bufs` is empty.
+    fn write_vectored<B, F>(&self, bufs: Vec<B>, cb: F) -> Result<()>
+    where
+        B: AsRef<[u8]> + 'static,
+        F: FnOnce(&Self, Result<()>) + 'static,
+    {
+        unsafe { WriteRequest::<Self, B, F>::submit(self.stream_ptr(), bufs, ptr::null_mut(), cb) }
+    }
+
+    /// Write `data` to the stream with backpressure. The data is written immediately, with
+    /// uv_try_write, as far as possible; only the leftover bytes are queued. If the write queue
+    /// is then above the high watermark, WouldBlock is returned: the data was accepted, but the
+    /// caller should stop sending until the drain callback (see on_drain()) is called.
+    ///
+    /// There is no callback for individual sends. Instead, if a queued write fails, the error is
+    /// returned by the next call to send().
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use libuv_sys2::event_loop::Loop;
+    /// # use libuv_sys2::pipe::Pipe;
+    /// # use libuv_sys2::stream::allocator::ReusableAllocator;
+    /// # use libuv_sys2::stream::{Stream, WriteStatus};
+    /// # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+    /// # use std::cell::{Cell, RefCell};
+    /// # use std::rc::Rc;
+    /// #
+    /// # #[cfg(unix)]
+    /// # fn main() {
+    /// let r#loop = Loop::new().unwrap();
+    /// let (writer, reader) = Pipe::socketpair(&r#loop, false).unwrap();
+    /// writer.set_watermarks(1024, 0);
+    /// assert_eq!(writer.watermarks(), (1024, 0));
+    ///
+    /// let drains = Rc::new(Cell::new(0));
+    /// let drained = drains.clone();
+    /// writer.on_drain(move |_| drained.set(drained.get() + 1));
+    ///
+    /// // nothing is reading yet, so everything fits in the socket's buffer until it doesn't
+    /// let mut expected = b"hello".to_vec();
+    /// let status = writer.send_vectored(vec![b"he".to_vec(), b"llo".to_vec()]).unwrap();
+    /// assert_eq!(status, WriteStatus::Ready);
+    /// let mut status = WriteStatus::Ready;
+    /// for chunk in 0..1000 {
+    ///     let data = vec![chunk as u8; 64 * 1024];
+    ///     expected.extend_from_slice(&data);
+    ///     status = writer.send(data).unwrap();
+    ///     if status == WriteStatus::WouldBlock {
+    ///         break;
+    ///     }
+    /// }
+    /// assert_eq!(status, WriteStatus::WouldBlock);
+    /// assert!(writer.write_queue_size() > 1024);
+    /// assert_eq!(drains.get(), 0);
+    ///
+    /// // once the peer starts reading, the queue drains and the callback is called exactly once
+    /// let total = expected.len();
+    /// let received = Rc::new(RefCell::new(Vec::new()));
+    /// let log = received.clone();
+    /// reader
+    ///     .read_start(ReusableAllocator::default(), move |reader, data| {
+    ///         log.borrow_mut().extend_from_slice(data.unwrap());
+    ///         if log.borrow().len() == total {
+    ///             reader.read_stop().unwrap();
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+    ///
+    /// assert_eq!(drains.get(), 1);
+    /// assert_eq!(writer.write_queue_size(), 0);
+    /// assert!(*received.borrow() == expected);
+    /// # }
+    /// #
+    /// # #[cfg(not(unix))]
+    /// # fn main() {}
+    /// ```
+    fn send<B>(&self, data: B) -> Result<WriteStatus>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        self.send_vectored(vec![data])
+    }
+
+    /// Like send(), except the buffers in `bufs` are sent, in order.
+    fn send_vectored<B>(&self, bufs: Vec<B>) -> Result<WriteStatus>
+    where
+        B: AsRef<[u8]> + 'static,
+    {
+        unsafe {
+            let stream = self.stream_ptr();
+            let state = stream_state(stream);
+            if let Some(err) = (*state).write_error.take() {
+                return Err(err);
+            }
+
+            // uv_try_write fails with UV_EAGAIN if nothing could be written, including when there
+            // are already writes queued, which keeps the data in order.
+            let raw_bufs: Vec<uv_buf_t> = bufs.iter().map(|buf| write_buf(buf.as_ref())).collect();
+            let mut written = if raw_bufs.is_empty() {
+                0
+            } else {
+                match uv_try_write(stream, raw_bufs.as_ptr(), raw_bufs.len() as _) {
+                    code if code == uv_errno_t_UV_EAGAIN => 0,
+                    code if code < 0 => return Err(UvError::new("uv_try_write", code)),
+                    written => written as usize,
+                }
+            };
+
+            let mut unwritten = Vec::new();
+            for buf in bufs {
+                let len = buf.as_ref().len();
+                if written >= len {
+                    written -= len;
+                } else {
+                    unwritten.push(Unwritten {
+                        buf,
+                        offset: written,
+                    });
+                    written = 0;
+                }
+            }
+            if !unwritten.is_empty() {
+                WriteRequest::submit(
+                    stream,
+                    unwritten,
+                    ptr::null_mut(),
+                    |stream: &Self, result| {
+                        if let Err(err) = result {
+                            let state = stream_state(stream.stream_ptr());
+                            (*state).write_error.get_or_insert(err);
+                        }
+                    },
+                )?;
+            }
+
+            if uv_stream_get_write_queue_size(stream) > (*state).high_watermark {
+                (*state).blocked = true;
+                return Ok(WriteStatus::WouldBlock);
+            }
+        }
+        Ok(WriteStatus::Ready)
+    }
+
+    /// Set the high and low watermarks for send(), in bytes. The defaults are
+    /// DEFAULT_HIGH_WATERMARK and DEFAULT_LOW_WATERMARK.
+    ///
+    /// # Panics
+    /// Panics if `low` is greater than `high`.
+    fn set_watermarks(&self, high: usize, low: usize) {
+        assert!(low <= high, "low watermark is above the high watermark");
+        unsafe {
+            let state = stream_state(self.stream_ptr());
+            (*state).high_watermark = high;
+            (*state).low_watermark = low;
+        }
+    }
+
+    /// The high and low watermarks for send(), in bytes.
+    fn watermarks(&self) -> (usize, usize) {
+        unsafe {
+            let state = stream_state(self.stream_ptr());
+            ((*state).high_watermark, (*state).low_watermark)
+        }
+    }
+
+    /// Set the callback that is called when, after send() has returned WouldBlock, the write queue
+    /// falls to the low watermark or below.
+    fn on_drain<F>(&self, mut cb: F)
+    where
+        F: FnMut(&Self) + 'static,
+    {
+        unsafe {
+            let state = stream_state(self.stream_ptr());
+            (*state).drain.set(Box::new(move |stream| {
+                // The wrapper passed to the callback doesn't own the handle, so it must not be
+                // dropped.
+                let stream = ManuallyDrop::new(Self::from_stream_ptr(stream));
+                cb(&stream)
+            }));
+        }
+    }
+
+    /// The number of bytes that are queued for writing.
+    fn write_queue_size(&self) -> usize {
+        unsafe { uv_stream_get_write_queue_size(self.stream_ptr()) }
+    }
+
+    /// Returns true if the stream is readable.
+    fn is_readable(&self) -> bool {
+        unsafe { uv_is_readable(self.stream_ptr()) != 0 }
+    }
+
+    /// Returns true if the stream is writable.
+    fn is_writable(&self) -> bool {
+        unsafe { uv_is_writable(self.stream_ptr()) != 0 }
+    }
+}
+
+unsafe extern "C" fn alloc_cb(handle: *mut uv_handle_t, suggested_size: usize, buf: *mut uv_buf_t) {
+    let state = stream_state(handle as *const uv_stream_t);
+    *buf = handle::invoke(ptr::addr_of_mut!((*state).reader), |reader| {
+        reader.alloc(suggested_size)
+    })
+    .unwrap_or(uv_buf_t {
+        base: ptr::null_mut(),
+        len: 0,
+    });
+}
+
+unsafe extern "C" fn read_cb(stream: *mut uv_stream_t, nread: isize, _buf: *const uv_buf_t) {
+    let state = stream_state(stream);
+    handle::invoke(ptr::addr_of_mut!((*state).reader), |reader| {
+        reader.read(stream, nread)
+    });
+}
@@ -0,0 +1,237 @@
+//! Read-buffer allocators for Stream::read_start().
+//!
+//! Before every read, libuv asks for a buffer to read into. An Allocator decides where that memory
+//! comes from. Buffers are handed to the read callback as an owned ReadBuffer; when the callback
+//! is done with a buffer (or as soon as the callback returns, for the borrowed variant of
+//! read_start()), dropping it returns the memory to the allocator's pool so that it can be reused
+//! for a later read.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// The size of the buffers libuv suggests for stream reads.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A source of buffers for stream reads.
+pub trait Allocator: 'static {
+    /// The buffer type passed to the read callback.
+    type Buffer: ReadBuffer + 'static;
+
+    /// Allocate a buffer for the next read. `suggested_size` is libuv's hint for how big the
+    /// buffer should be; it's fine to ignore it. A buffer with no capacity will cause the read to
+    /// fail with UV_ENOBUFS.
+    fn alloc(&mut self, suggested_size: usize) -> Self::Buffer;
+}
+
+/// A buffer that libuv can read into.
+///
+/// The buffer derefs to the bytes that were read, ie, after set_len() has been called.
+pub trait ReadBuffer: Deref<Target = [u8]> {
+    /// The memory that libuv may read into. The buffer isn't moved between the time that this is
+    /// called and the time that set_len() is called.
+    fn capacity_mut(&mut self) -> &mut [u8];
+
+    /// Called after a read with the number of bytes that were read into the buffer.
+    fn set_len(&mut self, len: usize);
+}
+
+/// The free list shared by an allocator and the buffers it has handed out.
+struct Pool {
+    free: RefCell<Vec<Box<[u8]>>>,
+    buffer_size: usize,
+    max_free: usize,
+}
+
+impl Pool {
+    fn new(buffer_size: usize, max_free: usize) -> Rc<Pool> {
+        Rc::new(Pool {
+            free: RefCell::new(Vec::new()),
+            buffer_size,
+            max_free,
+        })
+    }
+
+    fn take(self: &Rc<Pool>) -> PooledBuffer {
+        let data = self
+            .free
+            .borrow_mut()
+            .pop()
+            .unwrap_or_else(|| vec![0; self.buffer_size].into_boxed_slice());
+        PooledBuffer {
+            data: Some(data),
+            len: 0,
+            pool: self.clone(),
+        }
+    }
+
+    fn release(&self, data: Box<[u8]>) {
+        let mut free = self.free.borrow_mut();
+        if free.len() < self.max_free {
+            free.push(data);
+        }
+    }
+}
+
+/// A buffer handed out by a SlabAllocator or a ReusableAllocator. The buffer's memory goes back
+/// to the allocator's pool when it is dropped.
+pub struct PooledBuffer {
+    data: Option<Box<[u8]>>,
+    len: usize,
+    pool: Rc<Pool>,
+}
+
+impl PooledBuffer {
+    /// Copy the contents of the buffer into a Vec.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.deref().to_vec()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data.as_ref().expect("buffer released")[..self.len]
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data.as_mut().expect("buffer released")[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl ReadBuffer for PooledBuffer {
+    fn capacity_mut(&mut self) -> &mut [u8] {
+        self.data.as_mut().expect("buffer released")
+    }
+
+    fn set_len(&mut self, len: usize) {
+        assert!(len <= self.data.as_ref().map_or(0, |data| data.len()));
+        self.len = len;
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(data) = self.data.take() {
+            self.pool.release(data);
+        }
+    }
+}
+
+/// An allocator that hands out fixed-size buffers from a pool. Buffers that are dropped go back
+/// into the pool, which holds on to at most `max_free` of them; any more are deallocated.
+///
+/// This is a good fit for callbacks that hold on to buffers (passing them to a write, for example)
+/// since the pool grows to however many buffers are in use at once.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::stream::allocator::{Allocator, ReadBuffer, SlabAllocator};
+/// #
+/// let mut allocator = SlabAllocator::new(1024, 4);
+/// let mut first = allocator.alloc(65536);
+/// assert_eq!(first.capacity_mut().len(), 1024);
+/// let ptr = first.capacity_mut().as_ptr();
+///
+/// // while the first buffer is in use, the allocator hands out a different one
+/// let mut second = allocator.alloc(65536);
+/// assert_ne!(second.capacity_mut().as_ptr(), ptr);
+///
+/// // once it's dropped, its memory is reused
+/// drop(first);
+/// let mut third = allocator.alloc(65536);
+/// assert_eq!(third.capacity_mut().as_ptr(), ptr);
+/// ```
+pub struct SlabAllocator {
+    pool: Rc<Pool>,
+}
+
+impl SlabAllocator {
+    /// Create an allocator that hands out buffers of `buffer_size` bytes, keeping up to
+    /// `max_free` unused buffers around for reuse.
+    pub fn new(buffer_size: usize, max_free: usize) -> SlabAllocator {
+        SlabAllocator {
+            pool: Pool::new(buffer_size, max_free),
+        }
+    }
+}
+
+impl Default for SlabAllocator {
+    /// 64KiB buffers, keeping up to 16 of them for reuse.
+    fn default() -> SlabAllocator {
+        SlabAllocator::new(DEFAULT_BUFFER_SIZE, 16)
+    }
+}
+
+impl Allocator for SlabAllocator {
+    type Buffer = PooledBuffer;
+
+    fn alloc(&mut self, _suggested_size: usize) -> PooledBuffer {
+        self.pool.take()
+    }
+}
+
+/// An allocator that reuses a single buffer for every read. This is the cheapest option for
+/// callbacks that are done with the data by the time they return, such as the borrowed variant of
+/// Stream::read_start().
+///
+/// If the buffer is still in use when the next read happens, another buffer is allocated for that
+/// read. Only one buffer is ever kept for reuse, though: whichever is dropped first.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::stream::allocator::{Allocator, ReadBuffer, ReusableAllocator};
+/// #
+/// let mut allocator = ReusableAllocator::new(1024);
+/// let mut first = allocator.alloc(65536);
+/// let ptr = first.capacity_mut().as_ptr();
+/// drop(first);
+///
+/// let mut second = allocator.alloc(65536);
+/// assert_eq!(second.capacity_mut().as_ptr(), ptr);
+/// let mut temporary = allocator.alloc(65536);
+/// assert_ne!(temporary.capacity_mut().as_ptr(), ptr);
+///
+/// // the buffer that is dropped first is the one that is kept
+/// drop(second);
+/// drop(temporary);
+/// assert_eq!(allocator.alloc(65536).capacity_mut().as_ptr(), ptr);
+/// ```
+pub struct ReusableAllocator {
+    pool: Rc<Pool>,
+}
+
+impl ReusableAllocator {
+    /// Create an allocator that reuses a single buffer of `buffer_size` bytes.
+    pub fn new(buffer_size: usize) -> ReusableAllocator {
+        let pool = Pool::new(buffer_size, 1);
+        pool.release(vec![0; buffer_size].into_boxed_slice());
+        ReusableAllocator { pool }
+    }
+}
+
+impl Default for ReusableAllocator {
+    /// A single 64KiB buffer.
+    fn default() -> ReusableAllocator {
+        ReusableAllocator::new(DEFAULT_BUFFER_SIZE)
+    }
+}
+
+impl Allocator for ReusableAllocator {
+    type Buffer = PooledBuffer;
+
+    fn alloc(&mut self, _suggested_size: usize) -> PooledBuffer {
+        self.pool.take()
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::handle;
+use crate::net::SockAddr;
+use crate::stream::{self, sealed, ConnectRequest, Stream, StreamState};
+use crate::{
+    uv_accept, uv_handle_t, uv_is_active, uv_stream_t, uv_tcp_bind, uv_tcp_close_reset,
+    uv_tcp_connect, uv_tcp_flags, uv_tcp_getpeername, uv_tcp_getsockname, uv_tcp_init,
+    uv_tcp_init_ex, uv_tcp_keepalive, uv_tcp_keepalive_ex, uv_tcp_nodelay,
+    uv_tcp_simultaneous_accepts, uv_tcp_t,
+};
+use std::mem::{self, ManuallyDrop};
+use std::net::SocketAddr;
+use std::os::raw::{c_int, c_uint};
+use std::time::Duration;
+
+/// A safe wrapper around uv_tcp_t.
+///
+/// Reading and writing are provided by the Stream trait. The socket is closed when the Tcp is
+/// dropped.
+///
+/// # Example
+///
+/// An echo server and a client on the same loop:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::stream::Stream;
+/// # use libuv_sys2::tcp::Tcp;
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// #
+/// let r#loop = Loop::new().unwrap();
+///
+/// let server = Tcp::new(&r#loop).unwrap();
+/// server.bind(&"127.0.0.1:0".parse().unwrap(), 0).unwrap();
+/// let clients = Rc::new(RefCell::new(Vec::new()));
+/// let accepted = clients.clone();
+/// server
+///     .listen(128, move |_, client| {
+///         let client = client.unwrap();
+///         client
+///             .read_start(ReusableAllocator::default(), |client, data| {
+///                 if let Ok(data) = data {
+///                     client.write(data.to_vec(), |_, _| {}).unwrap();
+///                 }
+///             })
+///             .unwrap();
+///         accepted.borrow_mut().push(client);
+///     })
+///     .unwrap();
+/// let addr = server.sockname().unwrap();
+/// assert_eq!(addr.ip().to_string(), "127.0.0.1");
+/// assert_ne!(addr.port(), 0);
+///
+/// let client = Tcp::new(&r#loop).unwrap();
+/// let received = Rc::new(RefCell::new(Vec::new()));
+/// let echoed = received.clone();
+/// client
+///     .connect(&addr, move |client, result| {
+///         result.unwrap();
+///         assert_eq!(client.peername().unwrap(), addr);
+///         client.nodelay(true).unwrap();
+///         client.write(b"hello".to_vec(), |_, result| result.unwrap()).unwrap();
+///         client
+///             .read_start(ReusableAllocator::default(), move |_, data| {
+///                 echoed.borrow_mut().extend_from_slice(data.unwrap());
+///             })
+///             .unwrap();
+///     })
+///     .unwrap();
+///
+/// for _ in 0..100 {
+///     if received.borrow().len() == 5 {
+///         break;
+///     }
+///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// }
+/// assert_eq!(*received.borrow(), b"hello");
+/// assert_eq!(clients.borrow().len(), 1);
+///
+/// drop((client, server));
+/// clients.borrow_mut().clear();
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// ```
+pub struct Tcp {
+    handle: *mut uv_tcp_t,
+}
+
+impl Tcp {
+    /// Create a new TCP handle. The socket itself isn't created until the handle is bound or
+    /// connected.
+    pub fn new(r#loop: &Loop) -> Result<Tcp> {
+        let handle = unsafe {
+            handle::alloc(r#loop, StreamState::new(), |r#loop, handle| {
+                uvret!(uv_tcp_init(r#loop, handle))
+            })?
+        };
+        Ok(Tcp { handle })
+    }
+
+    /// Create a new TCP handle, immediately creating a socket for the address `family`: AF_INET,
+    /// AF_INET6, or AF_UNSPEC (which, like new(), doesn't create a socket).
+    pub fn with_family(r#loop: &Loop, family: u32) -> Result<Tcp> {
+        let handle = unsafe {
+            handle::alloc(r#loop, StreamState::new(), |r#loop, handle| {
+                uvret!(uv_tcp_init_ex(r#loop, handle, family as c_uint))
+            })?
+        };
+        Ok(Tcp { handle })
+    }
+
+    /// Bind the handle to an address. `flags` may include uv_tcp_flags_UV_TCP_IPV6ONLY, to
+    /// disable dual-stack support on an IPv6 address, and uv_tcp_flags_UV_TCP_REUSEPORT, to allow
+    /// several sockets to bind to the same address and port (where the platform supports it).
+    ///
+    /// Some errors, such as UV_EADDRINUSE, are deferred until listen() or connect() is called.
+    pub fn bind(&self, addr: &SocketAddr, flags: uv_tcp_flags) -> Result<()> {
+        let addr = SockAddr::new(addr)?;
+        unsafe { uvret!(uv_tcp_bind(self.handle, addr.as_ptr(), flags as c_uint)) }?;
+        Ok(())
+    }
+
+    /// Start listening for connections. Each incoming connection is accepted, and `cb` is called
+    /// with the new Tcp handle. Dropping the new handle closes the connection.
+    pub fn listen<F>(&self, backlog: i32, mut cb: F) -> Result<()>
+    where
+        F: FnMut(&Tcp, Result<Tcp>) + 'static,
+    {
+        let handle = self.handle;
+        unsafe {
+            stream::listen(
+                self.as_stream_ptr(),
+                backlog as c_int,
+                Box::new(move |status| {
+                    // The Tcp passed to the callback doesn't own the handle, so it must not be
+                    // dropped.
+                    let server = ManuallyDrop::new(Tcp { handle });
+                    let client = match status {
+                        0 => server.accept(),
+                        code => Err(UvError::new("uv_listen", code)),
+                    };
+                    cb(&server, client)
+                }),
+            )
+        }
+    }
+
+    /// Accept an incoming connection on a listening handle.
+    fn accept(&self) -> Result<Tcp> {
+        let client = Tcp::new(&self.get_loop())?;
+        unsafe { uvret!(uv_accept(self.as_stream_ptr(), client.as_stream_ptr())) }?;
+        Ok(client)
+    }
+
+    /// Connect to `addr`. `cb` is called once the connection has been established or has failed.
+    pub fn connect<F>(&self, addr: &SocketAddr, cb: F) -> Result<()>
+    where
+        F: FnOnce(&Tcp, Result<()>) + 'static,
+    {
+        let addr = SockAddr::new(addr)?;
+        let handle = self.handle;
+        unsafe {
+            ConnectRequest::submit(self.as_stream_ptr(), cb, |req, connect_cb| {
+                uvret!(uv_tcp_connect(req, handle, addr.as_ptr(), connect_cb))
+            })
+        }
+    }
+
+    /// Enable or disable Nagle's algorithm.
+    pub fn nodelay(&self, enable: bool) -> Result<()> {
+        unsafe { uvret!(uv_tcp_nodelay(self.handle, enable as c_int)) }?;
+        Ok(())
+    }
+
+    /// Enable or disable TCP keep-alive. `delay` is the initial delay before the first probe is
+    /// sent, with a resolution of one second; it is ignored when disabling keep-alive.
+    pub fn keepalive(&self, enable: bool, delay: Duration) -> Result<()> {
+        unsafe { uvret!(uv_tcp_keepalive(self.handle, enable as c_int, secs(delay))) }?;
+        Ok(())
+    }
+
+    /// Like keepalive(), but also sets the interval between probes and the number of unanswered
+    /// probes before the connection is dropped. Each value is rounded down to a whole number of
+    /// seconds.
+    pub fn keepalive_ex(
+        &self,
+        enable: bool,
+        idle: Duration,
+        interval: Duration,
+        count: u32,
+    ) -> Result<()> {
+        unsafe {
+            uvret!(uv_tcp_keepalive_ex(
+                self.handle,
+                enable as c_int,
+                secs(idle),
+                secs(interval),
+                count as c_uint
+            ))
+        }?;
+        Ok(())
+    }
+
+    /// Enable or disable simultaneous asynchronous accept requests when listening. This only has
+    /// an effect on Windows.
+    pub fn simultaneous_accepts(&self, enable: bool) -> Result<()> {
+        unsafe { uvret!(uv_tcp_simultaneous_accepts(self.handle, enable as c_int)) }?;
+        Ok(())
+    }
+
+    /// The address that the handle is bound to.
+    pub fn sockname(&self) -> Result<SocketAddr> {
+        unsafe {
+            let mut addr = SockAddr::unspecified();
+            let mut len = addr.capacity();
+            uvret!(uv_tcp_getsockname(self.handle, addr.as_mut_ptr(), &mut len))?;
+            addr.to_socket_addr()
+        }
+    }
+
+    /// The address of the peer that the handle is connected to.
+    pub fn peername(&self) -> Result<SocketAddr> {
+        unsafe {
+            let mut addr = SockAddr::unspecified();
+            let mut len = addr.capacity();
+            uvret!(uv_tcp_getpeername(self.handle, addr.as_mut_ptr(), &mut len))?;
+            addr.to_socket_addr()
+        }
+    }
+
+    /// Close the connection by sending a RST packet, rather than the usual graceful shutdown.
+    /// If this fails, the handle is closed normally.
+    pub fn close_reset(self) -> Result<()> {
+        let handle = ManuallyDrop::new(self);
+        let ret = unsafe {
+            uvret!(uv_tcp_close_reset(
+                handle.handle,
+                Some(handle::close_cb::<uv_tcp_t, StreamState>)
+            ))
+        };
+        if let Err(err) = ret {
+            mem::drop(ManuallyDrop::into_inner(handle));
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the handle is listening, reading, or connecting.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the handle belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, StreamState>(self.handle) }
+    }
+
+    /// The raw uv_tcp_t.
+    pub fn as_ptr(&self) -> *mut uv_tcp_t {
+        self.handle
+    }
+}
+
+/// Convert a Duration to the whole seconds that libuv uses for keep-alive settings, saturating if
+/// it doesn't fit.
+fn secs(duration: Duration) -> c_uint {
+    duration.as_secs().min(c_uint::MAX as u64) as c_uint
+}
+
+impl sealed::StreamHandle for Tcp {
+    fn stream_ptr(&self) -> *mut uv_stream_t {
+        self.handle as *mut uv_stream_t
+    }
+
+    unsafe fn from_stream_ptr(ptr: *mut uv_stream_t) -> Tcp {
+        Tcp {
+            handle: ptr as *mut uv_tcp_t,
+        }
+    }
+}
+
+impl Stream for Tcp {}
+
+impl Drop for Tcp {
+    fn drop(&mut self) {
+        unsafe { handle::close::<_, StreamState>(self.handle) }
+    }
+}
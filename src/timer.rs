@@ -0,0 +1,206 @@
+use crate::error::Result;
+use crate::event_loop::Loop;
+use crate::handle::{self, Callback};
+use crate::{
+    uv_handle_t, uv_is_active, uv_timer_again, uv_timer_get_due_in, uv_timer_get_repeat,
+    uv_timer_init, uv_timer_set_repeat, uv_timer_start, uv_timer_stop, uv_timer_t,
+};
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::time::Duration;
+
+/// Convert a Duration to the millisecond resolution that libuv uses for timeouts, saturating if it
+/// doesn't fit.
+pub(crate) fn millis(duration: Duration) -> u64 {
+    duration.as_millis().min(u64::MAX as u128) as u64
+}
+
+struct TimerState {
+    cb: Callback<dyn FnMut(&Timer)>,
+}
+
+/// A safe wrapper around uv_timer_t.
+///
+/// The timer is closed when it is dropped. Timeouts have a resolution of one millisecond.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::timer::Timer;
+/// # use libuv_sys2::uv_run_mode_UV_RUN_NOWAIT;
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// # use std::time::Duration;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let timer = Timer::new(&r#loop).unwrap();
+/// let fired = Rc::new(Cell::new(0));
+///
+/// // a timeout of zero fires on the next iteration of the loop; a repeat of zero makes it a
+/// // one-shot timer
+/// let counter = fired.clone();
+/// timer
+///     .start(Duration::ZERO, Duration::ZERO, move |_| counter.set(counter.get() + 1))
+///     .unwrap();
+/// assert!(timer.is_active());
+///
+/// r#loop.run(uv_run_mode_UV_RUN_NOWAIT);
+/// assert_eq!(fired.get(), 1);
+/// assert!(!timer.is_active());
+///
+/// r#loop.run(uv_run_mode_UV_RUN_NOWAIT);
+/// assert_eq!(fired.get(), 1);
+///
+/// // the due time is measured from the loop's cached "now", so it is exact until the loop runs
+/// timer.start(Duration::from_secs(10), Duration::ZERO, |_| {}).unwrap();
+/// assert_eq!(timer.due_in(), Duration::from_secs(10));
+///
+/// // again() restarts the timer using the repeat value as the timeout
+/// timer.set_repeat(Duration::from_millis(500));
+/// assert_eq!(timer.repeat(), Duration::from_millis(500));
+/// timer.again().unwrap();
+/// assert_eq!(timer.due_in(), Duration::from_millis(500));
+///
+/// timer.stop().unwrap();
+/// assert!(!timer.is_active());
+/// assert_eq!(timer.due_in(), Duration::ZERO);
+/// ```
+///
+/// Timers may also be re-armed from inside their own callback, either by calling start() with a
+/// new callback, or with again() and set_repeat():
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::timer::Timer;
+/// # use libuv_sys2::uv_run_mode_UV_RUN_NOWAIT;
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// # use std::time::Duration;
+/// #
+/// fn arm(timer: &Timer, fired: Rc<Cell<u32>>) {
+///     timer
+///         .start(Duration::ZERO, Duration::ZERO, move |timer| {
+///             fired.set(fired.get() + 1);
+///             if fired.get() < 3 {
+///                 arm(timer, fired.clone());
+///             }
+///         })
+///         .unwrap();
+/// }
+///
+/// let r#loop = Loop::new().unwrap();
+/// let timer = Timer::new(&r#loop).unwrap();
+/// let fired = Rc::new(Cell::new(0));
+/// arm(&timer, fired.clone());
+///
+/// for _ in 0..10 {
+///     if !timer.is_active() {
+///         break;
+///     }
+///     r#loop.run(uv_run_mode_UV_RUN_NOWAIT);
+/// }
+/// assert_eq!(fired.get(), 3);
+/// assert!(!timer.is_active());
+/// ```
+pub struct Timer {
+    handle: *mut uv_timer_t,
+}
+
+impl Timer {
+    /// Create a new timer.
+    pub fn new(r#loop: &Loop) -> Result<Timer> {
+        let state = TimerState {
+            cb: Callback::empty(),
+        };
+        let handle = unsafe {
+            handle::alloc(r#loop, state, |r#loop, handle| {
+                uvret!(uv_timer_init(r#loop, handle))
+            })?
+        };
+        Ok(Timer { handle })
+    }
+
+    /// Start the timer. `cb` will be called after `timeout`, and then every `repeat` after that.
+    /// If `repeat` is zero, the timer is one-shot: it stops after the callback is called once.
+    ///
+    /// If the timer is already active, it is simply updated, replacing the previous callback.
+    pub fn start<F>(&self, timeout: Duration, repeat: Duration, cb: F) -> Result<()>
+    where
+        F: FnMut(&Timer) + 'static,
+    {
+        unsafe {
+            let state = handle::state::<_, TimerState>(self.handle);
+            (*state).cb.set(Box::new(cb));
+            uvret!(uv_timer_start(
+                self.handle,
+                Some(timer_cb),
+                millis(timeout),
+                millis(repeat)
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Stop the timer. The callback will not be called until the timer is started again.
+    pub fn stop(&self) -> Result<()> {
+        unsafe { uvret!(uv_timer_stop(self.handle)) }?;
+        Ok(())
+    }
+
+    /// Stop the timer and, if it is repeating, restart it using the repeat value as the timeout.
+    /// Returns an error (UV_EINVAL) if the timer has never been started.
+    pub fn again(&self) -> Result<()> {
+        unsafe { uvret!(uv_timer_again(self.handle)) }?;
+        Ok(())
+    }
+
+    /// Set the repeat interval. If the timer is active, the new interval takes effect the next
+    /// time the timer fires; it does not change the current timeout.
+    pub fn set_repeat(&self, repeat: Duration) {
+        unsafe { uv_timer_set_repeat(self.handle, millis(repeat)) }
+    }
+
+    /// Get the repeat interval.
+    pub fn repeat(&self) -> Duration {
+        Duration::from_millis(unsafe { uv_timer_get_repeat(self.handle) })
+    }
+
+    /// Get the time until the timer fires, relative to the loop's cached "now". Returns zero if
+    /// the timer has expired or isn't active.
+    pub fn due_in(&self) -> Duration {
+        // uv_timer_stop() leaves the timeout alone, so libuv would report a stopped timer as due.
+        if !self.is_active() {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(unsafe { uv_timer_get_due_in(self.handle) })
+    }
+
+    /// Returns true if the timer is running.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the timer belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, TimerState>(self.handle) }
+    }
+
+    /// The raw uv_timer_t.
+    pub fn as_ptr(&self) -> *mut uv_timer_t {
+        self.handle
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        unsafe { handle::close::<_, TimerState>(self.handle) }
+    }
+}
+
+unsafe extern "C" fn timer_cb(handle: *mut uv_timer_t) {
+    // The Timer passed to the callback doesn't own the handle, so it must not be dropped.
+    let timer = ManuallyDrop::new(Timer { handle });
+    let state = handle::state::<_, TimerState>(handle);
+    handle::invoke(ptr::addr_of_mut!((*state).cb), |cb| cb(&timer));
+}
@@ -0,0 +1,369 @@
+use crate::error::Result;
+use crate::event_loop::Loop;
+use crate::handle;
+use crate::signal::{on_shutdown, ShutdownSignals};
+use crate::stream::{sealed, Stream, StreamState};
+#[cfg(unix)]
+use crate::SIGTERM;
+use crate::{
+    uv_file, uv_handle_t, uv_is_active, uv_kill, uv_os_getpid, uv_stream_t, uv_tty_get_vterm_state,
+    uv_tty_get_winsize, uv_tty_init, uv_tty_mode_t, uv_tty_mode_t_UV_TTY_MODE_IO,
+    uv_tty_mode_t_UV_TTY_MODE_NORMAL, uv_tty_mode_t_UV_TTY_MODE_RAW,
+    uv_tty_mode_t_UV_TTY_MODE_RAW_VT, uv_tty_reset_mode, uv_tty_set_mode, uv_tty_set_vterm_state,
+    uv_tty_t, uv_tty_vtermstate_t, uv_tty_vtermstate_t_UV_TTY_SUPPORTED,
+    uv_tty_vtermstate_t_UV_TTY_UNSUPPORTED, SIGINT,
+};
+use std::os::raw::c_int;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+pub mod editor;
+pub mod input;
+
+/// The signals that reset the terminal while a ModeGuard exists. Windows doesn't have SIGTERM.
+#[cfg(unix)]
+const RESET_SIGNALS: [u32; 2] = [SIGINT, SIGTERM];
+#[cfg(windows)]
+const RESET_SIGNALS: [u32; 1] = [SIGINT];
+
+/// The modes that a terminal can be put in with Tty::set_mode().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TtyMode {
+    /// The initial, line-buffered mode.
+    Normal,
+
+    /// Raw input mode: input is passed through byte by byte, without echoing or line editing.
+    /// On Windows, ENABLE_WINDOW_INPUT is also enabled.
+    Raw,
+
+    /// Binary-safe I/O mode, for inter-process communication. Unix only.
+    Io,
+
+    /// Raw input mode with virtual terminal input sequences (on Windows, ENABLE_WINDOW_INPUT is
+    /// replaced with ENABLE_VIRTUAL_TERMINAL_INPUT). Elsewhere, this is the same as Raw.
+    RawVt,
+}
+
+impl TtyMode {
+    /// Convert the mode into a raw uv_tty_mode_t.
+    pub fn to_raw(self) -> uv_tty_mode_t {
+        match self {
+            TtyMode::Normal => uv_tty_mode_t_UV_TTY_MODE_NORMAL,
+            TtyMode::Raw => uv_tty_mode_t_UV_TTY_MODE_RAW,
+            TtyMode::Io => uv_tty_mode_t_UV_TTY_MODE_IO,
+            TtyMode::RawVt => uv_tty_mode_t_UV_TTY_MODE_RAW_VT,
+        }
+    }
+}
+
+/// Whether the console supports ANSI escape sequences itself, or libuv emulates them. This only
+/// matters on Windows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VtermState {
+    Supported,
+    Unsupported,
+}
+
+/// The state attached to a Tty's handle. The stream functions only know about the StreamState,
+/// which is why it has to come first.
+#[repr(C)]
+struct TtyState {
+    stream: StreamState,
+
+    /// The mode that Tty::set_mode() last put the terminal in.
+    mode: TtyMode,
+}
+
+/// A safe wrapper around uv_tty_t: a stream for a terminal.
+///
+/// Reading and writing are provided by the Stream trait. Changing the terminal's mode with
+/// set_mode_guarded() returns a guard that puts the terminal back the way it was, so that an
+/// error, a panic, or Ctrl+C doesn't leave the user's terminal in raw mode.
+///
+/// # Example
+///
+/// On Linux, a Tty can be tested against a pseudo-terminal:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::tty::{Tty, TtyMode};
+/// # use libuv_sys2::uv_run_mode_UV_RUN_DEFAULT;
+/// # use std::os::raw::{c_int, c_void};
+/// # use std::ptr;
+/// #
+/// # #[cfg(target_os = "linux")]
+/// # fn main() {
+/// #[repr(C)]
+/// struct Winsize {
+///     rows: u16,
+///     cols: u16,
+///     xpixel: u16,
+///     ypixel: u16,
+/// }
+///
+/// #[repr(C)]
+/// struct Termios {
+///     iflag: u32,
+///     oflag: u32,
+///     cflag: u32,
+///     lflag: u32,
+///     rest: [u8; 64],
+/// }
+///
+/// #[link(name = "util")]
+/// extern "C" {
+///     fn openpty(
+///         master: *mut c_int,
+///         slave: *mut c_int,
+///         name: *mut c_void,
+///         termp: *const c_void,
+///         winp: *const Winsize,
+///     ) -> c_int;
+///     fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+/// }
+/// const ICANON: u32 = 0o2;
+///
+/// let lflag = |fd| unsafe {
+///     let mut termios: Termios = std::mem::zeroed();
+///     assert_eq!(tcgetattr(fd, &mut termios), 0);
+///     termios.lflag
+/// };
+///
+/// let (mut master, mut slave) = (0, 0);
+/// let size = Winsize { rows: 24, cols: 80, xpixel: 0, ypixel: 0 };
+/// let ret = unsafe { openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size) };
+/// assert_eq!(ret, 0);
+///
+/// let r#loop = Loop::new().unwrap();
+/// let tty = Tty::new(&r#loop, slave).unwrap();
+/// assert_eq!(tty.get_winsize().unwrap(), (80, 24));
+/// assert_ne!(lflag(slave) & ICANON, 0);
+///
+/// {
+///     let _guard = tty.set_mode_guarded(TtyMode::Raw).unwrap();
+///     assert_eq!(lflag(slave) & ICANON, 0);
+/// }
+/// assert_ne!(lflag(slave) & ICANON, 0);
+///
+/// // guards put back whatever mode they replaced, so they can be nested
+/// {
+///     let _raw = tty.set_mode_guarded(TtyMode::Raw).unwrap();
+///     {
+///         let _io = tty.set_mode_guarded(TtyMode::Io).unwrap();
+///         assert_eq!(tty.mode(), TtyMode::Io);
+///     }
+///     assert_eq!(tty.mode(), TtyMode::Raw);
+///     assert_eq!(lflag(slave) & ICANON, 0);
+/// }
+/// assert_eq!(tty.mode(), TtyMode::Normal);
+/// assert_ne!(lflag(slave) & ICANON, 0);
+///
+/// // the mode is restored when a panic unwinds past the guard, too
+/// let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+///     let _guard = tty.set_mode_guarded(TtyMode::Raw).unwrap();
+///     panic!("oops");
+/// }));
+/// assert!(result.is_err());
+/// assert_ne!(lflag(slave) & ICANON, 0);
+///
+/// drop(tty);
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// # }
+/// #
+/// # #[cfg(not(target_os = "linux"))]
+/// # fn main() {}
+/// ```
+pub struct Tty {
+    handle: *mut uv_tty_t,
+}
+
+impl Tty {
+    /// Create a new Tty for the file descriptor `fd`, ie, 0 for stdin or 1 for stdout. On unix,
+    /// libuv reopens the terminal, so changing the Tty's mode doesn't affect other processes that
+    /// share the file descriptor.
+    pub fn new(r#loop: &Loop, fd: uv_file) -> Result<Tty> {
+        let handle = unsafe {
+            let state = TtyState {
+                stream: StreamState::new(),
+                mode: TtyMode::Normal,
+            };
+            handle::alloc(r#loop, state, |r#loop, handle| {
+                uvret!(uv_tty_init(r#loop, handle, fd, 0))
+            })?
+        };
+        Ok(Tty { handle })
+    }
+
+    /// Set the terminal's mode. Prefer set_mode_guarded(), unless the mode will be restored some
+    /// other way.
+    pub fn set_mode(&self, mode: TtyMode) -> Result<()> {
+        unsafe {
+            uvret!(uv_tty_set_mode(self.handle, mode.to_raw()))?;
+            (*handle::state::<_, TtyState>(self.handle)).mode = mode;
+        }
+        Ok(())
+    }
+
+    /// The mode that the terminal was last put in with set_mode(), or Normal if it hasn't been
+    /// changed. This doesn't know about resets with reset_mode().
+    pub fn mode(&self) -> TtyMode {
+        unsafe { (*handle::state::<_, TtyState>(self.handle)).mode }
+    }
+
+    /// Set the terminal's mode, returning a guard that puts it back in its previous mode when it's
+    /// dropped. Guards may be nested, as long as they're dropped in the reverse order.
+    ///
+    /// The terminal is also reset, with reset_mode(), if the process panics while the guard
+    /// exists (even if the panic can't unwind, ie, because it happened in a libuv callback), and
+    /// if the process receives SIGINT or SIGTERM. In the latter case, the signal is then raised
+    /// again, so the process is terminated as usual.
+    ///
+    /// While any guard exists, SIGINT and SIGTERM are handled by libuv rather than terminating the
+    /// process immediately, and libuv delivers signals through the loop. The reset only happens
+    /// when the guard's loop next runs, so a process that's blocked outside the loop won't respond
+    /// to Ctrl+C until it returns to the loop.
+    pub fn set_mode_guarded(&self, mode: TtyMode) -> Result<ModeGuard<'_>> {
+        let previous = self.mode();
+        let reset = ResetOnExit::new(&self.get_loop())?;
+        self.set_mode(mode)?;
+        Ok(ModeGuard {
+            tty: self,
+            previous,
+            _reset: reset,
+        })
+    }
+
+    /// Restore the terminal settings that libuv saved when the first Tty was put in a mode other
+    /// than Normal. libuv only saves the settings of that one terminal, so any other terminal
+    /// keeps its mode, and nothing happens if no Tty has left Normal mode. This is safe to call
+    /// from a signal handler. On Windows, it does nothing.
+    pub fn reset_mode() -> Result<()> {
+        unsafe { uvret!(uv_tty_reset_mode()) }?;
+        Ok(())
+    }
+
+    /// The terminal's size, as (width, height), in characters.
+    pub fn get_winsize(&self) -> Result<(i32, i32)> {
+        let (mut width, mut height) = (0, 0);
+        unsafe { uvret!(uv_tty_get_winsize(self.handle, &mut width, &mut height)) }?;
+        Ok((width, height))
+    }
+
+    /// Whether libuv emulates ANSI escape sequences on the console. This is only supported on
+    /// Windows; elsewhere, it returns UV_ENOTSUP.
+    pub fn get_vterm_state() -> Result<VtermState> {
+        let mut state: uv_tty_vtermstate_t = uv_tty_vtermstate_t_UV_TTY_UNSUPPORTED;
+        unsafe { uvret!(uv_tty_get_vterm_state(&mut state)) }?;
+        if state == uv_tty_vtermstate_t_UV_TTY_SUPPORTED {
+            Ok(VtermState::Supported)
+        } else {
+            Ok(VtermState::Unsupported)
+        }
+    }
+
+    /// Override libuv's detection of whether the console supports ANSI escape sequences. This
+    /// must be called before any Tty is created, and only has an effect on Windows.
+    pub fn set_vterm_state(state: VtermState) {
+        let state = match state {
+            VtermState::Supported => uv_tty_vtermstate_t_UV_TTY_SUPPORTED,
+            VtermState::Unsupported => uv_tty_vtermstate_t_UV_TTY_UNSUPPORTED,
+        };
+        unsafe { uv_tty_set_vterm_state(state) };
+    }
+
+    /// Returns true if the tty is reading.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the handle belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, TtyState>(self.handle) }
+    }
+
+    /// The raw uv_tty_t.
+    pub fn as_ptr(&self) -> *mut uv_tty_t {
+        self.handle
+    }
+}
+
+impl sealed::StreamHandle for Tty {
+    fn stream_ptr(&self) -> *mut uv_stream_t {
+        self.handle as *mut uv_stream_t
+    }
+
+    unsafe fn from_stream_ptr(ptr: *mut uv_stream_t) -> Tty {
+        Tty {
+            handle: ptr as *mut uv_tty_t,
+        }
+    }
+}
+
+impl Stream for Tty {}
+
+impl Drop for Tty {
+    fn drop(&mut self) {
+        unsafe { handle::close::<_, TtyState>(self.handle) }
+    }
+}
+
+/// The number of ResetOnExits that exist, so the panic hook knows whether to reset the terminal.
+static GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Install a panic hook, in front of whatever hook was already installed, that resets the
+/// terminal while any ResetOnExit exists. Unlike the guard's Drop, the hook runs even when the
+/// panic can't unwind.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if GUARDS.load(Ordering::SeqCst) > 0 {
+                unsafe { uv_tty_reset_mode() };
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// Resets the terminal if the process panics or receives SIGINT or SIGTERM while this exists.
+/// Putting the terminal back into the right mode when this is dropped is up to the owner.
+struct ResetOnExit {
+    _signals: ShutdownSignals,
+}
+
+impl ResetOnExit {
+    fn new(r#loop: &Loop) -> Result<ResetOnExit> {
+        let signals = on_shutdown(r#loop, RESET_SIGNALS, |signum| unsafe {
+            uv_tty_reset_mode();
+            uv_kill(uv_os_getpid(), signum as c_int);
+        })?;
+        install_panic_hook();
+        GUARDS.fetch_add(1, Ordering::SeqCst);
+        Ok(ResetOnExit { _signals: signals })
+    }
+}
+
+impl Drop for ResetOnExit {
+    fn drop(&mut self) {
+        GUARDS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// When dropped, puts a Tty back in the mode it was in before the guard was created. See
+/// Tty::set_mode_guarded().
+pub struct ModeGuard<'a> {
+    tty: &'a Tty,
+    previous: TtyMode,
+    _reset: ResetOnExit,
+}
+
+impl Drop for ModeGuard<'_> {
+    fn drop(&mut self) {
+        // Going through the handle, rather than uv_tty_reset_mode, keeps libuv's idea of the
+        // handle's mode up to date.
+        let _ = self.tty.set_mode(self.previous);
+    }
+}
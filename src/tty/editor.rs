@@ -0,0 +1,715 @@
+//! An asynchronous line editor, for prompts and REPLs that run on a libuv loop.
+//!
+//! The editor puts the terminal in raw mode while a line is being read, decodes keys with the
+//! input module, and draws the line itself. It supports the usual emacs-style bindings:
+//!
+//! | Keys                       | Action                                            |
+//! |----------------------------|---------------------------------------------------|
+//! | Left, Right, Ctrl+B/F      | Move the cursor a character                       |
+//! | Ctrl+Left/Right, Alt+B/F   | Move the cursor a word                            |
+//! | Home, End, Ctrl+A/E        | Move the cursor to the start or end of the line   |
+//! | Up, Down, Ctrl+P/N         | Recall the previous or next line from the history |
+//! | Backspace, Delete, Ctrl+D  | Delete a character                                |
+//! | Ctrl+W, Alt+Backspace      | Kill the word before the cursor                   |
+//! | Alt+D                      | Kill the word after the cursor                    |
+//! | Ctrl+U, Ctrl+K             | Kill to the start or end of the line              |
+//! | Ctrl+Y                     | Yank (paste) the last thing that was killed       |
+//! | Tab                        | Complete, using the completion callback           |
+//! | Ctrl+L                     | Clear the screen                                  |
+//! | Enter                      | Finish the line                                   |
+//! | Ctrl+C                     | Abandon the line                                  |
+//! | Ctrl+D on an empty line    | End of input                                      |
+//!
+//! Every character is assumed to take up one column, and the prompt shouldn't contain escape
+//! sequences, or the cursor will end up in the wrong place.
+
+use super::input::{Event, Key, KeyEvent, DEFAULT_ESCAPE_TIMEOUT};
+use super::{ResetOnExit, Tty, TtyMode};
+use crate::error::{Result, UvError};
+use crate::signal::Signal;
+use crate::stream::Stream;
+use crate::{uv_errno_t_UV_EALREADY, SIGWINCH};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+/// The default number of lines that are kept in the history.
+pub const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+/// The number of columns to assume if the terminal doesn't know its size.
+const DEFAULT_COLUMNS: usize = 80;
+
+/// How a call to read_line() finished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReadLine {
+    /// Enter was pressed.
+    Line(String),
+
+    /// Ctrl+C was pressed.
+    Interrupted,
+
+    /// Ctrl+D was pressed on an empty line.
+    Eof,
+}
+
+/// The result of a completion callback.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Completion {
+    /// The byte offset in the line where the text being completed starts. The text from here to
+    /// the cursor is replaced with the completion.
+    pub start: usize,
+
+    /// The possible completions. If there's more than one, the line is completed as far as
+    /// their common prefix, or, if that adds nothing, they are listed below the line.
+    pub candidates: Vec<String>,
+}
+
+type Completer = dyn FnMut(&str, usize) -> Completion;
+type LineCallback = dyn FnOnce(&LineEditor, Result<ReadLine>);
+
+/// The line being read.
+struct Line {
+    prompt: String,
+    buffer: Vec<char>,
+    cursor: usize,
+
+    /// The history entry that's being shown, or history.len() for the new line.
+    history_index: usize,
+
+    /// The new line, while an older one is being shown.
+    draft: Vec<char>,
+
+    /// The row that the terminal's cursor is on, relative to the prompt, as of the last redraw.
+    cursor_row: usize,
+
+    /// The mode that the input terminal was in before the line was started, which it's put
+    /// back in once the line is finished.
+    previous_mode: TtyMode,
+
+    cb: Box<LineCallback>,
+    _reset: ResetOnExit,
+}
+
+impl Line {
+    fn text(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    fn insert(&mut self, chars: &[char]) {
+        let at = self.cursor;
+        self.buffer.splice(at..at, chars.iter().copied());
+        self.cursor += chars.len();
+    }
+
+    /// Remove the characters in `start..end`, moving the cursor to `start`.
+    fn remove(&mut self, start: usize, end: usize) -> Vec<char> {
+        self.cursor = start;
+        self.buffer.drain(start..end).collect()
+    }
+
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.buffer[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.buffer[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.buffer.len() && !self.buffer[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.buffer.len() && self.buffer[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    /// The number of columns taken up by the prompt and line, and by the prompt and the text
+    /// before the cursor.
+    fn widths(&self) -> (usize, usize) {
+        let prompt = self.prompt.chars().count();
+        (prompt + self.buffer.len(), prompt + self.cursor)
+    }
+}
+
+/// Append a control sequence with a numeric parameter to `out`.
+fn csi(out: &mut Vec<u8>, n: usize, last: char) {
+    out.extend_from_slice(format!("\x1b[{}{}", n, last).as_bytes());
+}
+
+/// The length, in bytes, of the prefix that `candidates` have in common.
+fn common_prefix(candidates: &[String]) -> usize {
+    let first = &candidates[0];
+    let mut len = first.len();
+    for other in &candidates[1..] {
+        len = first[..len]
+            .char_indices()
+            .zip(other.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or_else(|| len.min(other.len()));
+    }
+    len
+}
+
+struct EditorState {
+    input: Tty,
+    output: Tty,
+    winch: Signal,
+    columns: Cell<usize>,
+    history: RefCell<VecDeque<String>>,
+    history_limit: Cell<usize>,
+    killed: RefCell<Vec<char>>,
+    completer: RefCell<Option<Box<Completer>>>,
+    line: RefCell<Option<Line>>,
+}
+
+impl EditorState {
+    fn update_columns(&self) {
+        let columns = match self.output.get_winsize() {
+            Ok((width, _)) if width > 0 => width as usize,
+            _ => DEFAULT_COLUMNS,
+        };
+        self.columns.set(columns);
+    }
+
+    fn write(&self, out: Vec<u8>) {
+        if !out.is_empty() {
+            let _ = self.output.write(out, |_, _| {});
+        }
+    }
+
+    /// Redraw the prompt and line, and put the cursor in the right place. Lines that are wider
+    /// than the terminal wrap onto the rows below.
+    fn render(&self, line: &mut Line, out: &mut Vec<u8>) {
+        let columns = self.columns.get();
+        let (total, cursor) = line.widths();
+        if line.cursor_row > 0 {
+            csi(out, line.cursor_row, 'A');
+        }
+        out.extend_from_slice(b"\r\x1b[J");
+        out.extend_from_slice(line.prompt.as_bytes());
+        out.extend_from_slice(line.text().as_bytes());
+
+        // After writing to the last column, terminals don't move the cursor to the next row
+        // until the next character arrives.
+        if total > 0 && total % columns == 0 {
+            out.extend_from_slice(b"\r\n");
+        }
+
+        let (end_row, row, column) = (total / columns, cursor / columns, cursor % columns);
+        if end_row > row {
+            csi(out, end_row - row, 'A');
+        }
+        out.push(b'\r');
+        if column > 0 {
+            csi(out, column, 'C');
+        }
+        line.cursor_row = row;
+    }
+
+    fn recall(&self, line: &mut Line, index: usize) {
+        let history = self.history.borrow();
+        if index > history.len() || index == line.history_index {
+            return;
+        }
+        if line.history_index == history.len() {
+            line.draft = mem::take(&mut line.buffer);
+        }
+        line.buffer = match history.get(index) {
+            Some(entry) => entry.chars().collect(),
+            None => mem::take(&mut line.draft),
+        };
+        line.history_index = index;
+        line.cursor = line.buffer.len();
+    }
+
+    fn kill(&self, line: &mut Line, start: usize, end: usize) {
+        if start < end {
+            *self.killed.borrow_mut() = line.remove(start, end);
+        }
+    }
+
+    fn complete(&self, line: &mut Line, out: &mut Vec<u8>) {
+        let mut completer = self.completer.borrow_mut();
+        let completer = match completer.as_mut() {
+            Some(completer) => completer,
+            None => return,
+        };
+        let text = line.text();
+        let cursor: usize = line.buffer[..line.cursor]
+            .iter()
+            .map(|c| c.len_utf8())
+            .sum();
+        let Completion { start, candidates } = completer(&text, cursor);
+        if candidates.is_empty() || start > cursor || !text.is_char_boundary(start) {
+            out.push(0x07);
+            return;
+        }
+
+        let typed = text[start..cursor].chars().count();
+        let prefix = &candidates[0][..common_prefix(&candidates)];
+        let completion = if candidates.len() == 1 {
+            &candidates[0]
+        } else if prefix.chars().count() > typed {
+            prefix
+        } else {
+            // List the candidates below the line; the line is redrawn after them.
+            let (total, cursor) = line.widths();
+            let columns = self.columns.get();
+            if total / columns > cursor / columns {
+                csi(out, total / columns - cursor / columns, 'B');
+            }
+            out.extend_from_slice(b"\r\n");
+            out.extend_from_slice(candidates.join("  ").as_bytes());
+            out.extend_from_slice(b"\r\n");
+            line.cursor_row = 0;
+            return;
+        };
+        let start = text[..start].chars().count();
+        let completion: Vec<char> = completion.chars().collect();
+        line.remove(start, line.cursor);
+        line.insert(&completion);
+    }
+
+    /// Handle a key, returning the result if it finishes the line.
+    fn on_key(&self, line: &mut Line, event: KeyEvent, out: &mut Vec<u8>) -> Option<ReadLine> {
+        let KeyEvent { key, modifiers } = event;
+        match (key, modifiers.ctrl, modifiers.alt) {
+            (Key::Enter, ..) | (Key::Char('j'), true, false) => {
+                return Some(ReadLine::Line(line.text()));
+            }
+            (Key::Char('c'), true, false) => return Some(ReadLine::Interrupted),
+            (Key::Char('d'), true, false) if line.buffer.is_empty() => return Some(ReadLine::Eof),
+            (Key::Char('d'), true, false) | (Key::Delete, ..)
+                if line.cursor < line.buffer.len() =>
+            {
+                line.remove(line.cursor, line.cursor + 1);
+            }
+            (Key::Backspace, false, true) | (Key::Char('w'), true, false) => {
+                self.kill(line, line.word_start(), line.cursor);
+            }
+            (Key::Backspace, ..) | (Key::Char('h'), true, false) if line.cursor > 0 => {
+                line.remove(line.cursor - 1, line.cursor);
+            }
+            (Key::Char('d'), false, true) => self.kill(line, line.cursor, line.word_end()),
+            (Key::Char('u'), true, false) => self.kill(line, 0, line.cursor),
+            (Key::Char('k'), true, false) => self.kill(line, line.cursor, line.buffer.len()),
+            (Key::Char('y'), true, false) => {
+                let killed = self.killed.borrow().clone();
+                line.insert(&killed);
+            }
+            (Key::Left, false, false) | (Key::Char('b'), true, false) => {
+                line.cursor = line.cursor.saturating_sub(1);
+            }
+            (Key::Right, false, false) | (Key::Char('f'), true, false) => {
+                line.cursor = (line.cursor + 1).min(line.buffer.len());
+            }
+            (Key::Left, ..) | (Key::Char('b'), false, true) => line.cursor = line.word_start(),
+            (Key::Right, ..) | (Key::Char('f'), false, true) => line.cursor = line.word_end(),
+            (Key::Home, ..) | (Key::Char('a'), true, false) => line.cursor = 0,
+            (Key::End, ..) | (Key::Char('e'), true, false) => line.cursor = line.buffer.len(),
+            (Key::Up, ..) | (Key::Char('p'), true, false) if line.history_index > 0 => {
+                self.recall(line, line.history_index - 1);
+            }
+            (Key::Down, ..) | (Key::Char('n'), true, false) => {
+                self.recall(line, line.history_index + 1);
+            }
+            (Key::Char('l'), true, false) => {
+                out.extend_from_slice(b"\x1b[H\x1b[2J");
+                line.cursor_row = 0;
+            }
+            (Key::Tab, false, false) => self.complete(line, out),
+            (Key::Char(c), false, false) => line.insert(&[c]),
+            _ => {}
+        }
+        None
+    }
+
+    fn add_history(&self, entry: &str) {
+        let mut history = self.history.borrow_mut();
+        if entry.is_empty() || history.back().map(String::as_str) == Some(entry) {
+            return;
+        }
+        history.push_back(entry.to_string());
+        while history.len() > self.history_limit.get() {
+            history.pop_front();
+        }
+    }
+}
+
+impl Drop for EditorState {
+    fn drop(&mut self) {
+        if let Some(line) = self.line.get_mut() {
+            let _ = self.input.set_mode(line.previous_mode);
+        }
+    }
+}
+
+fn on_event(state: &Rc<EditorState>, event: Result<Event>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(err) => return finish(state, Err(err)),
+    };
+
+    let mut out = Vec::new();
+    let result = {
+        let mut line = state.line.borrow_mut();
+        let line = match line.as_mut() {
+            Some(line) => line,
+            None => return,
+        };
+        let result = match event {
+            Event::Key(event) => state.on_key(line, event, &mut out),
+            Event::Paste(text) => {
+                // The line can't hold line breaks, so they become spaces.
+                let text: Vec<char> = text
+                    .chars()
+                    .map(|c| if c == '\r' || c == '\n' { ' ' } else { c })
+                    .filter(|c| !c.is_control())
+                    .collect();
+                line.insert(&text);
+                None
+            }
+            _ => return,
+        };
+        if result.is_none() {
+            state.render(line, &mut out);
+        }
+        result
+    };
+    state.write(out);
+    if let Some(result) = result {
+        finish(state, Ok(result));
+    }
+}
+
+fn on_resize(state: &EditorState) {
+    let mut line = state.line.borrow_mut();
+    let line = match line.as_mut() {
+        Some(line) => line,
+        None => return,
+    };
+    state.update_columns();
+
+    // Most terminals reflow wrapped lines when they're resized, so the cursor is now on the row
+    // that it would be on at the new width.
+    let (_, cursor) = line.widths();
+    line.cursor_row = cursor / state.columns.get();
+    let mut out = Vec::new();
+    state.render(line, &mut out);
+    state.write(out);
+}
+
+/// Move past the end of the line, hand the terminal back, and call the line's callback.
+fn finish(state: &Rc<EditorState>, result: Result<ReadLine>) {
+    let line = match state.line.borrow_mut().take() {
+        Some(line) => line,
+        None => return,
+    };
+
+    let mut out = Vec::new();
+    let (total, _) = line.widths();
+    let end_row = total / state.columns.get();
+    if end_row > line.cursor_row {
+        csi(&mut out, end_row - line.cursor_row, 'B');
+    }
+    if let Ok(ReadLine::Interrupted) = result {
+        out.extend_from_slice(b"^C");
+    }
+    out.extend_from_slice(b"\r\n");
+    state.write(out);
+
+    let _ = state.input.read_stop();
+    let _ = state.winch.stop();
+    let _ = state.input.set_mode(line.previous_mode);
+    if let Ok(ReadLine::Line(text)) = &result {
+        state.add_history(text);
+    }
+
+    let cb = line.cb;
+    drop(line._reset);
+    cb(
+        &LineEditor {
+            state: state.clone(),
+        },
+        result,
+    );
+}
+
+/// A line editor, reading from one Tty and drawing on another.
+///
+/// # Example
+///
+/// On Linux, the editor can be driven through a pseudo-terminal:
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::stream::Stream;
+/// # use libuv_sys2::tty::editor::{Completion, LineEditor, ReadLine};
+/// # use libuv_sys2::tty::{Tty, TtyMode};
+/// # use libuv_sys2::{uv_kill, uv_os_getpid, SIGWINCH};
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+/// # use std::cell::RefCell;
+/// # use std::os::raw::{c_int, c_ulong, c_void};
+/// # use std::ptr;
+/// # use std::rc::Rc;
+/// #
+/// # #[cfg(target_os = "linux")]
+/// # fn main() {
+/// # #[repr(C)]
+/// # struct Winsize {
+/// #     rows: u16,
+/// #     cols: u16,
+/// #     xpixel: u16,
+/// #     ypixel: u16,
+/// # }
+/// # #[link(name = "util")]
+/// # extern "C" {
+/// #     fn openpty(
+/// #         master: *mut c_int,
+/// #         slave: *mut c_int,
+/// #         name: *mut c_void,
+/// #         termp: *const c_void,
+/// #         winp: *const Winsize,
+/// #     ) -> c_int;
+/// #     fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+/// #     fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+/// # }
+/// # const TIOCSWINSZ: c_ulong = 0x5414;
+/// # let (mut master, mut slave) = (0, 0);
+/// # let size = Winsize { rows: 24, cols: 80, xpixel: 0, ypixel: 0 };
+/// # let ret = unsafe { openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size) };
+/// # assert_eq!(ret, 0);
+/// # let type_keys = |keys: &[u8]| unsafe {
+/// #     let written = write(master, keys.as_ptr() as *const c_void, keys.len());
+/// #     assert_eq!(written, keys.len() as isize);
+/// # };
+/// #
+/// // `slave` is one end of a pseudo-terminal; type_keys() writes to the other end, `master`
+/// let r#loop = Loop::new().unwrap();
+/// let editor = LineEditor::new(
+///     Tty::new(&r#loop, slave).unwrap(),
+///     Tty::new(&r#loop, slave).unwrap(),
+/// )
+/// .unwrap();
+/// editor.set_completer(|line, cursor| {
+///     let start = line[..cursor].rfind(' ').map_or(0, |i| i + 1);
+///     let word = &line[start..cursor];
+///     let candidates = ["hello", "help", "history"]
+///         .iter()
+///         .filter(|candidate| candidate.starts_with(word))
+///         .map(|candidate| candidate.to_string())
+///         .collect();
+///     Completion { start, candidates }
+/// });
+///
+/// // everything the editor draws ends up on the master side
+/// let screen = Rc::new(RefCell::new(Vec::new()));
+/// let log = screen.clone();
+/// let terminal = Tty::new(&r#loop, master).unwrap();
+/// terminal
+///     .read_start(ReusableAllocator::default(), move |_, data| {
+///         if let Ok(data) = data {
+///             log.borrow_mut().extend_from_slice(data);
+///         }
+///     })
+///     .unwrap();
+///
+/// // start reading a line, type some keys, and wait for the result
+/// let read = |keys: &[u8]| {
+///     let result = Rc::new(RefCell::new(None));
+///     let slot = result.clone();
+///     editor
+///         .read_line("> ", move |_, line| *slot.borrow_mut() = Some(line.unwrap()))
+///         .unwrap();
+///     type_keys(keys);
+///     for _ in 0..1000 {
+///         if result.borrow().is_some() {
+///             break;
+///         }
+///         r#loop.run(uv_run_mode_UV_RUN_ONCE);
+///     }
+///     let line = result.borrow_mut().take().unwrap();
+///     line
+/// };
+/// let entered = |text: &str| ReadLine::Line(text.to_string());
+///
+/// // cursor movement and history
+/// assert_eq!(read(b"hello\x1b[D\x1b[DXY\r"), entered("helXYlo"));
+/// assert_eq!(read(b"\x1b[A!\r"), entered("helXYlo!"));
+/// assert_eq!(editor.history(), vec!["helXYlo", "helXYlo!"]);
+///
+/// // Ctrl+W kills a word, Ctrl+A moves to the start, and Ctrl+Y yanks the word back
+/// assert_eq!(read(b"abc def\x17\x01\x19\r"), entered("defabc "));
+///
+/// // Tab completes the common prefix "hel", then "hello"
+/// assert_eq!(read(b"say he\tl\t\r"), entered("say hello"));
+///
+/// assert_eq!(read(b"oops\x03"), ReadLine::Interrupted);
+/// assert_eq!(read(b"\x04"), ReadLine::Eof);
+/// assert!(String::from_utf8_lossy(&screen.borrow()).contains("> helXYlo!"));
+///
+/// // the line is redrawn when the terminal is resized
+/// let size = Winsize { rows: 24, cols: 10, xpixel: 0, ypixel: 0 };
+/// assert_eq!(unsafe { ioctl(master, TIOCSWINSZ, &size as *const Winsize) }, 0);
+/// unsafe { uv_kill(uv_os_getpid(), SIGWINCH as c_int) };
+/// assert_eq!(read(b"wider than ten columns\r"), entered("wider than ten columns"));
+///
+/// // the input terminal goes back to the mode it was in before the line was started
+/// assert_eq!(editor.input().mode(), TtyMode::Normal);
+/// editor.input().set_mode(TtyMode::Io).unwrap();
+/// assert_eq!(read(b"io\r"), entered("io"));
+/// assert_eq!(editor.input().mode(), TtyMode::Io);
+/// editor.input().set_mode(TtyMode::Normal).unwrap();
+///
+/// drop((editor, terminal));
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// # }
+/// #
+/// # #[cfg(not(target_os = "linux"))]
+/// # fn main() {}
+/// ```
+pub struct LineEditor {
+    state: Rc<EditorState>,
+}
+
+impl LineEditor {
+    /// Create a line editor that reads keys from `input` and draws on `output`, which are
+    /// usually Ttys for stdin and stdout.
+    pub fn new(input: Tty, output: Tty) -> Result<LineEditor> {
+        let winch = Signal::new(&input.get_loop())?;
+        let state = Rc::new(EditorState {
+            input,
+            output,
+            winch,
+            columns: Cell::new(DEFAULT_COLUMNS),
+            history: RefCell::new(VecDeque::new()),
+            history_limit: Cell::new(DEFAULT_HISTORY_LIMIT),
+            killed: RefCell::new(Vec::new()),
+            completer: RefCell::new(None),
+            line: RefCell::new(None),
+        });
+        Ok(LineEditor { state })
+    }
+
+    /// Show `prompt` and read a line, without blocking the loop. `cb` is called once the line is
+    /// finished, and may call read_line() again for the next one. Non-empty lines are added to
+    /// the history.
+    ///
+    /// The terminal is in raw mode until the line is finished, and then goes back to the mode it
+    /// was in before. As with Tty::set_mode_guarded(), it is reset if the process panics or is
+    /// killed with SIGINT or SIGTERM in the meantime. Returns an error (UV_EALREADY) if a line is
+    /// already being read.
+    pub fn read_line<F>(&self, prompt: &str, cb: F) -> Result<()>
+    where
+        F: FnOnce(&LineEditor, Result<ReadLine>) + 'static,
+    {
+        let state = &self.state;
+        if state.line.borrow().is_some() {
+            return Err(UvError::new("uv_read_start", uv_errno_t_UV_EALREADY));
+        }
+
+        let previous_mode = state.input.mode();
+        let reset = ResetOnExit::new(&state.input.get_loop())?;
+        state.input.set_mode(TtyMode::Raw)?;
+        let started = self.start_reading();
+        if let Err(err) = started {
+            let _ = state.input.read_stop();
+            let _ = state.input.set_mode(previous_mode);
+            return Err(err);
+        }
+
+        state.update_columns();
+        let mut line = Line {
+            prompt: prompt.to_string(),
+            buffer: Vec::new(),
+            cursor: 0,
+            history_index: state.history.borrow().len(),
+            draft: Vec::new(),
+            cursor_row: 0,
+            previous_mode,
+            cb: Box::new(cb),
+            _reset: reset,
+        };
+        let mut out = Vec::new();
+        state.render(&mut line, &mut out);
+        state.write(out);
+        *state.line.borrow_mut() = Some(line);
+        Ok(())
+    }
+
+    /// Start reading keys and watching for SIGWINCH. The callbacks only hold weak references,
+    /// since the handles belong to the state.
+    fn start_reading(&self) -> Result<()> {
+        let weak = Rc::downgrade(&self.state);
+        self.state
+            .input
+            .read_events(DEFAULT_ESCAPE_TIMEOUT, move |_, event| {
+                if let Some(state) = weak.upgrade() {
+                    on_event(&state, event);
+                }
+            })?;
+
+        let weak = Rc::downgrade(&self.state);
+        self.state.winch.start(SIGWINCH, move |_, _| {
+            if let Some(state) = weak.upgrade() {
+                on_resize(&state);
+            }
+        })
+    }
+
+    /// Returns true if a line is being read.
+    pub fn is_reading(&self) -> bool {
+        self.state.line.borrow().is_some()
+    }
+
+    /// Set the completion callback, which is called when Tab is pressed. It's passed the line
+    /// and the cursor's byte offset, and returns what the text before the cursor could be
+    /// completed with.
+    pub fn set_completer<F>(&self, completer: F)
+    where
+        F: FnMut(&str, usize) -> Completion + 'static,
+    {
+        *self.state.completer.borrow_mut() = Some(Box::new(completer));
+    }
+
+    /// Add a line to the end of the history, ie, one that was saved from an earlier session.
+    /// Empty lines, and lines that are the same as the last one, are skipped.
+    pub fn add_history(&self, entry: &str) {
+        self.state.add_history(entry);
+    }
+
+    /// The history, oldest first.
+    pub fn history(&self) -> Vec<String> {
+        self.state.history.borrow().iter().cloned().collect()
+    }
+
+    /// Set the number of lines that are kept in the history. The oldest lines are dropped
+    /// first.
+    pub fn set_history_limit(&self, limit: usize) {
+        self.state.history_limit.set(limit);
+        let mut history = self.state.history.borrow_mut();
+        while history.len() > limit {
+            history.pop_front();
+        }
+    }
+
+    /// The Tty that keys are read from.
+    pub fn input(&self) -> &Tty {
+        &self.state.input
+    }
+
+    /// The Tty that the line is drawn on. Anything written to it while a line is being read
+    /// will be overwritten when the line is redrawn.
+    pub fn output(&self) -> &Tty {
+        &self.state.output
+    }
+}
@@ -0,0 +1,796 @@
+//! Decoding the bytes read from a raw-mode terminal into key, paste, focus and mouse events.
+//!
+//! In raw mode, a terminal sends most keys as the bytes they produce, control keys as control
+//! characters, and everything else (arrows, function keys, modified keys, mouse reports, ...) as
+//! escape sequences. The trouble is that the Escape key sends a lone ESC, which is also how every
+//! escape sequence starts. The Decoder holds on to an ESC until it can tell which it is; when
+//! reading from a Tty with read_events(), a timer decides that an ESC with nothing after it was
+//! the Escape key.
+
+use super::Tty;
+use crate::error::Result;
+use crate::stream::allocator::ReusableAllocator;
+use crate::stream::Stream;
+use crate::timer::Timer;
+use std::cell::RefCell;
+use std::mem::ManuallyDrop;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+/// A reasonable escape timeout for read_events(). Escape sequences arrive in one write from the
+/// terminal, so they are rarely split across reads, except over slow connections.
+pub const DEFAULT_ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+const ESC: u8 = 0x1b;
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Escape sequences longer than this are assumed to be garbage, rather than buffered forever.
+const MAX_SEQUENCE_LEN: usize = 64;
+
+/// A bracketed paste that grows past this many bytes without ending is delivered as it is, and
+/// the rest of the input is decoded normally.
+pub const MAX_PASTE_LEN: usize = 1024 * 1024;
+
+/// A key on the keyboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A character. Control characters are reported as the letter (or symbol) with the Ctrl
+    /// modifier, ie, 0x01 is Ctrl+'a'. Shifted characters are reported as they were typed, ie,
+    /// 'A', without the Shift modifier.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+
+    /// A function key, from F(1) to F(12).
+    F(u8),
+}
+
+/// The modifier keys that were held down with a key or mouse button. Terminals can't report
+/// every combination, ie, Ctrl+Shift+'a' is indistinguishable from Ctrl+'a'.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: false,
+    };
+    pub const SHIFT: Modifiers = Modifiers {
+        shift: true,
+        ..Modifiers::NONE
+    };
+    pub const ALT: Modifiers = Modifiers {
+        alt: true,
+        ..Modifiers::NONE
+    };
+    pub const CTRL: Modifiers = Modifiers {
+        ctrl: true,
+        ..Modifiers::NONE
+    };
+
+    /// Decode xterm's modifier parameter, which is 1 plus a bit for each modifier. Meta is
+    /// treated as Alt.
+    fn from_param(param: u32) -> Modifiers {
+        let bits = param.saturating_sub(1);
+        Modifiers {
+            shift: bits & 1 != 0,
+            alt: bits & (2 | 8) != 0,
+            ctrl: bits & 4 != 0,
+        }
+    }
+}
+
+/// A key press.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    pub fn new(key: Key, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent { key, modifiers }
+    }
+}
+
+/// A mouse button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+
+    /// Any other button, numbered as the terminal reports it, ie, 8 for "back".
+    Other(u8),
+}
+
+/// What happened with the mouse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseKind {
+    Press(MouseButton),
+    Release(MouseButton),
+
+    /// The mouse moved while the button was held down.
+    Drag(MouseButton),
+
+    /// The mouse moved without a button held down. Only reported if the terminal was asked to
+    /// report all motion.
+    Move,
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+}
+
+/// A mouse report, in SGR format. Terminals only send these once they've been asked to, ie,
+/// with "\x1b[?1000h\x1b[?1006h".
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MouseEvent {
+    pub kind: MouseKind,
+
+    /// The zero-based column and row of the cell under the mouse.
+    pub column: u16,
+    pub row: u16,
+    pub modifiers: Modifiers,
+}
+
+/// Something that happened at the terminal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    Key(KeyEvent),
+
+    /// Text that was pasted, if bracketed paste was turned on with "\x1b[?2004h". The text is
+    /// passed through as the terminal sent it, so line breaks are usually "\r". Invalid UTF-8 is
+    /// replaced with U+FFFD.
+    Paste(String),
+
+    /// The terminal gained or lost focus, if focus reporting was turned on with "\x1b[?1004h".
+    FocusGained,
+    FocusLost,
+    Mouse(MouseEvent),
+
+    /// An escape sequence that wasn't recognized, or bytes that aren't valid UTF-8.
+    Unknown(Vec<u8>),
+}
+
+fn key(key: Key, modifiers: Modifiers) -> Event {
+    Event::Key(KeyEvent::new(key, modifiers))
+}
+
+/// The result of parsing the start of the input: an event and the number of bytes it took up,
+/// or Incomplete if the input ends partway through an event.
+enum Parse {
+    Event(Event, usize),
+    Incomplete,
+}
+
+/// The result of looking for the end of a control sequence.
+enum Scan {
+    /// The sequence is complete, and this many bytes long, including the final byte.
+    Done(usize),
+    Partial,
+    Invalid,
+}
+
+/// Find the end of a control sequence: any number of parameter and intermediate bytes, followed
+/// by a final byte.
+fn scan(input: &[u8]) -> Scan {
+    for (i, &byte) in input.iter().enumerate() {
+        match byte {
+            0x20..=0x3f => {}
+            0x40..=0x7e => return Scan::Done(i + 1),
+            _ => return Scan::Invalid,
+        }
+    }
+    if input.len() > MAX_SEQUENCE_LEN {
+        Scan::Invalid
+    } else {
+        Scan::Partial
+    }
+}
+
+/// Parse the first event out of `input`, which must not be empty. If `flush` is true, no more
+/// input is coming, so a partial sequence is decoded as the keys that it's made of.
+fn parse(input: &[u8], flush: bool) -> Parse {
+    if input[0] == ESC {
+        parse_escape(input, flush)
+    } else {
+        parse_char(input, flush)
+    }
+}
+
+fn parse_escape(input: &[u8], flush: bool) -> Parse {
+    // In a run of ESCs, all but the last one are the Escape key, since only the last one can
+    // start a sequence or modify a key. They're decoded one at a time, rather than by recursing
+    // through the run, which could be arbitrarily long.
+    if input.len() >= 3 && input[1] == ESC && input[2] == ESC {
+        return Parse::Event(key(Key::Escape, Modifiers::NONE), 1);
+    }
+
+    let introducer = match input.get(1) {
+        Some(&byte) => byte,
+        None if flush => return Parse::Event(key(Key::Escape, Modifiers::NONE), 1),
+        None => return Parse::Incomplete,
+    };
+    if introducer == b'[' || introducer == b'O' {
+        let body = &input[2..];
+        match scan(body) {
+            Scan::Done(len) => {
+                let event = if introducer == b'[' {
+                    csi(&body[..len])
+                } else {
+                    ss3(&body[..len])
+                };
+                let event = event.unwrap_or_else(|| Event::Unknown(input[..len + 2].to_vec()));
+                return Parse::Event(event, len + 2);
+            }
+            Scan::Partial if !flush => return Parse::Incomplete,
+
+            // Otherwise, it was Alt+'[' or Alt+'O', below.
+            _ => {}
+        }
+    }
+
+    // ESC followed by a key is that key with Alt. Anything else, ie, a mouse report, means the
+    // ESC was the Escape key.
+    match parse(&input[1..], flush) {
+        Parse::Incomplete => Parse::Incomplete,
+        Parse::Event(Event::Key(mut event), len) if !event.modifiers.alt => {
+            event.modifiers.alt = true;
+            Parse::Event(Event::Key(event), len + 1)
+        }
+        Parse::Event(..) => Parse::Event(key(Key::Escape, Modifiers::NONE), 1),
+    }
+}
+
+fn parse_char(input: &[u8], flush: bool) -> Parse {
+    let byte = input[0];
+    let (k, modifiers) = match byte {
+        b'\r' => (Key::Enter, Modifiers::NONE),
+        b'\t' => (Key::Tab, Modifiers::NONE),
+        0x7f => (Key::Backspace, Modifiers::NONE),
+        0x00 => (Key::Char(' '), Modifiers::CTRL),
+        0x01..=0x1a => (Key::Char((byte - 1 + b'a') as char), Modifiers::CTRL),
+        0x1c..=0x1f => (Key::Char((byte + 0x40) as char), Modifiers::CTRL),
+        0x20..=0x7e => (Key::Char(byte as char), Modifiers::NONE),
+        _ => return parse_utf8(input, flush),
+    };
+    Parse::Event(key(k, modifiers), 1)
+}
+
+fn parse_utf8(input: &[u8], flush: bool) -> Parse {
+    let invalid = Parse::Event(Event::Unknown(vec![input[0]]), 1);
+    let len = match input[0] {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return invalid,
+    };
+    if input.len() < len {
+        let continuation = input[1..].iter().all(|&byte| byte & 0xc0 == 0x80);
+        return if continuation && !flush {
+            Parse::Incomplete
+        } else {
+            invalid
+        };
+    }
+    match std::str::from_utf8(&input[..len]) {
+        Ok(s) => match s.chars().next() {
+            Some(c) => Parse::Event(key(Key::Char(c), Modifiers::NONE), len),
+            None => invalid,
+        },
+        Err(_) => invalid,
+    }
+}
+
+/// Parse semicolon-separated numeric parameters. Missing parameters are None, and sub-parameters
+/// (after a colon) are ignored. Returns None if there's anything else in there.
+fn params(bytes: &[u8]) -> Option<Vec<Option<u32>>> {
+    if bytes.is_empty() {
+        return Some(Vec::new());
+    }
+    bytes
+        .split(|&byte| byte == b';')
+        .map(|param| {
+            let param = param.split(|&byte| byte == b':').next().unwrap_or_default();
+            if param.is_empty() {
+                return Some(None);
+            }
+            let mut value: u32 = 0;
+            for &byte in param {
+                if !byte.is_ascii_digit() {
+                    return None;
+                }
+                value = value.checked_mul(10)?.checked_add((byte - b'0') as u32)?;
+            }
+            Some(Some(value))
+        })
+        .collect()
+}
+
+fn param(params: &[Option<u32>], index: usize, default: u32) -> u32 {
+    params.get(index).copied().flatten().unwrap_or(default)
+}
+
+/// Decode a CSI sequence, which starts with "\x1b[". `body` is everything after that, up to and
+/// including the final byte.
+fn csi(body: &[u8]) -> Option<Event> {
+    let (&last, bytes) = body.split_last()?;
+    if let Some(bytes) = bytes.strip_prefix(b"<") {
+        return mouse(bytes, last);
+    }
+    let params = params(bytes)?;
+    let modifiers = Modifiers::from_param(param(&params, 1, 1));
+    let k = match last {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'P'..=b'S' => Key::F(last - b'P' + 1),
+        b'Z' => return Some(key(Key::Tab, Modifiers::SHIFT)),
+        b'I' if params.is_empty() => return Some(Event::FocusGained),
+        b'O' if params.is_empty() => return Some(Event::FocusLost),
+
+        // "CSI code ; modifiers u", from terminals that can report modified keys unambiguously.
+        b'u' => match param(&params, 0, 0) {
+            9 => Key::Tab,
+            13 => Key::Enter,
+            27 => Key::Escape,
+            127 => Key::Backspace,
+            code => Key::Char(std::char::from_u32(code)?),
+        },
+        b'~' => match param(&params, 0, 0) {
+            1 | 7 => Key::Home,
+            2 => Key::Insert,
+            3 => Key::Delete,
+            4 | 8 => Key::End,
+            5 => Key::PageUp,
+            6 => Key::PageDown,
+            n @ 11..=15 => Key::F((n - 10) as u8),
+            n @ 17..=21 => Key::F((n - 11) as u8),
+            n @ 23..=24 => Key::F((n - 12) as u8),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(key(k, modifiers))
+}
+
+/// Decode an SS3 sequence, which starts with "\x1bO". Some terminals put a modifier parameter
+/// before the final byte.
+fn ss3(body: &[u8]) -> Option<Event> {
+    let (&last, bytes) = body.split_last()?;
+    let params = params(bytes)?;
+    let modifiers = Modifiers::from_param(params.last().copied().flatten().unwrap_or(1));
+    let k = match last {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'M' => Key::Enter,
+        b'P'..=b'S' => Key::F(last - b'P' + 1),
+        _ => return None,
+    };
+    Some(key(k, modifiers))
+}
+
+/// Decode an SGR mouse report: "\x1b[<button;column;row" followed by M for a press or motion, or
+/// m for a release.
+fn mouse(bytes: &[u8], last: u8) -> Option<Event> {
+    let params = params(bytes)?;
+    if params.len() != 3 || (last != b'M' && last != b'm') {
+        return None;
+    }
+    let code = param(&params, 0, 0);
+    let column = param(&params, 1, 1).saturating_sub(1).min(u16::MAX as u32) as u16;
+    let row = param(&params, 2, 1).saturating_sub(1).min(u16::MAX as u32) as u16;
+    let modifiers = Modifiers {
+        shift: code & 4 != 0,
+        alt: code & 8 != 0,
+        ctrl: code & 16 != 0,
+    };
+
+    let number = (code & 3) as u8;
+    let kind = if code & 64 != 0 {
+        match number {
+            0 => MouseKind::ScrollUp,
+            1 => MouseKind::ScrollDown,
+            2 => MouseKind::ScrollLeft,
+            _ => MouseKind::ScrollRight,
+        }
+    } else {
+        let button = match (code & 128 != 0, number) {
+            (true, number) => Some(MouseButton::Other(number + 8)),
+            (false, 0) => Some(MouseButton::Left),
+            (false, 1) => Some(MouseButton::Middle),
+            (false, 2) => Some(MouseButton::Right),
+            (false, _) => None,
+        };
+        match (button, code & 32 != 0, last) {
+            (None, true, _) => MouseKind::Move,
+            (None, false, _) => return None,
+            (Some(button), true, _) => MouseKind::Drag(button),
+            (Some(button), false, b'M') => MouseKind::Press(button),
+            (Some(button), false, _) => MouseKind::Release(button),
+        }
+    };
+    Some(Event::Mouse(MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers,
+    }))
+}
+
+/// Turns the bytes read from a raw-mode terminal into Events.
+///
+/// Bytes that might be the start of an escape sequence are held until more input arrives, or
+/// until flush() is called. Tty::read_events() calls flush() once the input has been quiet for
+/// the escape timeout. The same goes for a bracketed paste, which ends when the terminal sends
+/// the end marker, when flush() is called, or once it reaches MAX_PASTE_LEN, so that a stray
+/// start marker can't swallow all further input.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::tty::input::{Decoder, Event, Key, KeyEvent, Modifiers, MAX_PASTE_LEN};
+/// # use libuv_sys2::tty::input::{MouseButton, MouseEvent, MouseKind};
+/// #
+/// let key = |key, modifiers| Event::Key(KeyEvent::new(key, modifiers));
+/// let mut decoder = Decoder::new();
+///
+/// // sequences and characters may be split across reads
+/// let events = decoder.decode(b"a\x03\x1b[1;5A\x1bOP\x1bx\xc3");
+/// assert_eq!(
+///     events,
+///     vec![
+///         key(Key::Char('a'), Modifiers::NONE),
+///         key(Key::Char('c'), Modifiers::CTRL),
+///         key(Key::Up, Modifiers::CTRL),
+///         key(Key::F(1), Modifiers::NONE),
+///         key(Key::Char('x'), Modifiers::ALT),
+///     ]
+/// );
+/// assert_eq!(decoder.decode(b"\xa9\x1b[3~"), vec![
+///     key(Key::Char('é'), Modifiers::NONE),
+///     key(Key::Delete, Modifiers::NONE),
+/// ]);
+///
+/// // a lone ESC might be the start of a sequence, until it's flushed
+/// assert!(decoder.decode(b"\x1b").is_empty());
+/// assert!(decoder.has_pending());
+/// assert_eq!(decoder.flush(), vec![key(Key::Escape, Modifiers::NONE)]);
+/// assert!(!decoder.has_pending());
+///
+/// // in a run of ESCs, only the last one can start a sequence or modify a key
+/// let escape = key(Key::Escape, Modifiers::NONE);
+/// assert_eq!(decoder.decode(&[0x1b; 1000]), vec![escape.clone(); 998]);
+/// assert_eq!(decoder.decode(b"x"), vec![escape, key(Key::Char('x'), Modifiers::ALT)]);
+/// assert!(!decoder.has_pending());
+///
+/// // bracketed paste, focus events and mouse reports
+/// assert!(decoder.decode(b"\x1b[200~ls \x1b[A\r").is_empty());
+/// assert!(decoder.has_pending());
+/// assert_eq!(decoder.decode(b"\x1b[201~\x1b[I\x1b[<0;10;5M\x1b[<65;1;1M"), vec![
+///     Event::Paste("ls \x1b[A\r".to_string()),
+///     Event::FocusGained,
+///     Event::Mouse(MouseEvent {
+///         kind: MouseKind::Press(MouseButton::Left),
+///         column: 9,
+///         row: 4,
+///         modifiers: Modifiers::NONE,
+///     }),
+///     Event::Mouse(MouseEvent {
+///         kind: MouseKind::ScrollDown,
+///         column: 0,
+///         row: 0,
+///         modifiers: Modifiers::NONE,
+///     }),
+/// ]);
+///
+/// assert_eq!(decoder.decode(b"\x1b[99x\xff"), vec![
+///     Event::Unknown(b"\x1b[99x".to_vec()),
+///     Event::Unknown(b"\xff".to_vec()),
+/// ]);
+///
+/// // a paste that never ends is delivered when it's flushed, or once it gets too long
+/// assert!(decoder.decode(b"\x1b[200~cat /dev/urandom").is_empty());
+/// assert_eq!(decoder.flush(), vec![Event::Paste("cat /dev/urandom".to_string())]);
+/// assert!(!decoder.has_pending());
+/// assert_eq!(decoder.decode(b"\r"), vec![key(Key::Enter, Modifiers::NONE)]);
+///
+/// assert!(decoder.decode(b"\x1b[200~").is_empty());
+/// assert_eq!(decoder.decode(&vec![b'x'; MAX_PASTE_LEN]), vec![
+///     Event::Paste("x".repeat(MAX_PASTE_LEN)),
+/// ]);
+/// assert_eq!(decoder.decode(b"\x03"), vec![key(Key::Char('c'), Modifiers::CTRL)]);
+/// ```
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+
+    /// The text of a bracketed paste that hasn't ended yet.
+    paste: Option<Vec<u8>>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Decode as much of the input so far as possible.
+    pub fn decode(&mut self, data: &[u8]) -> Vec<Event> {
+        self.buffer.extend_from_slice(data);
+        self.drain(false)
+    }
+
+    /// Decode any bytes that are being held, on the assumption that no more input is coming to
+    /// complete them: a lone ESC is the Escape key, "\x1b[" is Alt+'[', and so on. A bracketed
+    /// paste that hasn't ended is delivered with what has arrived so far.
+    ///
+    /// Pastes are seldom split across reads, but when reading with Tty::read_events() over a
+    /// slow connection, a longer escape timeout keeps a large paste in one piece.
+    pub fn flush(&mut self) -> Vec<Event> {
+        self.drain(true)
+    }
+
+    /// Returns true if there are bytes that flush() would decode.
+    pub fn has_pending(&self) -> bool {
+        self.paste.is_some() || !self.buffer.is_empty()
+    }
+
+    fn drain(&mut self, flush: bool) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut input = std::mem::take(&mut self.buffer);
+        let mut pos = 0;
+        while pos < input.len() {
+            if let Some(mut paste) = self.paste.take() {
+                // The end marker may have been split across reads.
+                let from = paste.len().saturating_sub(PASTE_END.len() - 1);
+                paste.extend_from_slice(&input[pos..]);
+                match paste[from..]
+                    .windows(PASTE_END.len())
+                    .position(|window| window == PASTE_END)
+                {
+                    Some(i) => {
+                        let end = from + i;
+                        input = paste.split_off(end + PASTE_END.len());
+                        pos = 0;
+                        paste.truncate(end);
+                        events.push(Event::Paste(String::from_utf8_lossy(&paste).into_owned()));
+                    }
+                    None if paste.len() >= MAX_PASTE_LEN => {
+                        pos = input.len();
+                        events.push(Event::Paste(String::from_utf8_lossy(&paste).into_owned()));
+                    }
+                    None => {
+                        pos = input.len();
+                        self.paste = Some(paste);
+                    }
+                }
+                continue;
+            }
+
+            // Nothing that's still incomplete after this many bytes is going to be completed, so
+            // it's decoded as it is, rather than held indefinitely.
+            let stale = input.len() - pos > MAX_SEQUENCE_LEN;
+            match parse(&input[pos..], flush || stale) {
+                Parse::Incomplete => break,
+                Parse::Event(event, len) => {
+                    if &input[pos..pos + len] == PASTE_START {
+                        self.paste = Some(Vec::new());
+                    } else {
+                        events.push(event);
+                    }
+                    pos += len;
+                }
+            }
+        }
+        input.drain(..pos);
+        self.buffer = input;
+        if flush {
+            if let Some(paste) = self.paste.take() {
+                events.push(Event::Paste(String::from_utf8_lossy(&paste).into_owned()));
+            }
+        }
+        events
+    }
+}
+
+type EventCallback = dyn FnMut(&Tty, Result<Event>);
+
+/// The state shared by read_events()'s read callback and escape timer.
+struct EventReader {
+    decoder: RefCell<Decoder>,
+    timer: Timer,
+    timeout: Duration,
+    cb: RefCell<Box<EventCallback>>,
+}
+
+impl EventReader {
+    /// Pass `events` to the callback, stopping early if it stops reading. Events that are flushed
+    /// after libuv has stopped reading by itself, ie, at the end of the input, are all delivered.
+    fn deliver(&self, tty: &Tty, events: Vec<Event>) {
+        let mut cb = self.cb.borrow_mut();
+        let reading = tty.is_active();
+        for event in events {
+            if reading && !tty.is_active() {
+                break;
+            }
+            cb(tty, Ok(event));
+        }
+    }
+
+    /// Start the escape timer, if the decoder is holding on to anything and the callback hasn't
+    /// stopped reading.
+    fn arm(self: &Rc<EventReader>, tty: &Tty) {
+        if !tty.is_active() || !self.decoder.borrow().has_pending() {
+            return;
+        }
+
+        // The timer is owned by the reader, so its callback only holds a weak reference.
+        let reader: Weak<EventReader> = Rc::downgrade(self);
+        let handle = tty.handle;
+        let ret = self.timer.start(self.timeout, Duration::ZERO, move |_| {
+            // read_stop() leaves the reader in place, so the timer may outlive reading.
+            let tty = ManuallyDrop::new(Tty { handle });
+            if !tty.is_active() {
+                return;
+            }
+            if let Some(reader) = reader.upgrade() {
+                let events = reader.decoder.borrow_mut().flush();
+                reader.deliver(&tty, events);
+            }
+        });
+        if ret.is_err() {
+            let events = self.decoder.borrow_mut().flush();
+            self.deliver(tty, events);
+        }
+    }
+}
+
+impl Tty {
+    /// Start reading from the terminal, calling `cb` with each Event as it's decoded. The
+    /// terminal should be in raw mode. Bytes that might be the start of an escape sequence are
+    /// decoded as keys once no more input has arrived for `escape_timeout`, which
+    /// DEFAULT_ESCAPE_TIMEOUT is a good choice for.
+    ///
+    /// Errors are passed to `cb`, as with Stream::read_start(), including UV_EOF. Call
+    /// read_stop() to stop reading, even from inside `cb`; `cb` isn't called again after that,
+    /// and anything that hasn't been delivered yet is discarded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use libuv_sys2::event_loop::Loop;
+    /// # use libuv_sys2::tty::input::{Event, Key, KeyEvent, Modifiers};
+    /// # use libuv_sys2::tty::{Tty, TtyMode};
+    /// # use libuv_sys2::stream::Stream;
+    /// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+    /// # use std::cell::RefCell;
+    /// # use std::os::raw::{c_int, c_void};
+    /// # use std::ptr;
+    /// # use std::rc::Rc;
+    /// # use std::time::Duration;
+    /// #
+    /// # #[cfg(target_os = "linux")]
+    /// # fn main() {
+    /// # #[link(name = "util")]
+    /// # extern "C" {
+    /// #     fn openpty(
+    /// #         master: *mut c_int,
+    /// #         slave: *mut c_int,
+    /// #         name: *mut c_void,
+    /// #         termp: *const c_void,
+    /// #         winp: *const c_void,
+    /// #     ) -> c_int;
+    /// #     fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+    /// # }
+    /// # let (mut master, mut slave) = (0, 0);
+    /// # let (name, size) = (ptr::null_mut(), ptr::null());
+    /// # assert_eq!(unsafe { openpty(&mut master, &mut slave, name, ptr::null(), size) }, 0);
+    /// # let type_keys = |keys: &[u8]| unsafe {
+    /// #     let written = write(master, keys.as_ptr() as *const c_void, keys.len());
+    /// #     assert_eq!(written, keys.len() as isize);
+    /// # };
+    /// #
+    /// // `slave` is one end of a pseudo-terminal, and type_keys() writes to the other end
+    /// let r#loop = Loop::new().unwrap();
+    /// let tty = Tty::new(&r#loop, slave).unwrap();
+    /// tty.set_mode(TtyMode::Raw).unwrap();
+    ///
+    /// let events = Rc::new(RefCell::new(Vec::new()));
+    /// let log = events.clone();
+    /// tty.read_events(Duration::from_millis(10), move |tty, event| {
+    ///     log.borrow_mut().push(event.unwrap());
+    ///     if log.borrow().len() == 3 {
+    ///         tty.read_stop().unwrap();
+    ///     }
+    /// })
+    /// .unwrap();
+    ///
+    /// // the trailing ESC is decoded once the timeout expires
+    /// type_keys(b"x\x1b[A\x1b");
+    /// for _ in 0..100 {
+    ///     if events.borrow().len() == 3 {
+    ///         break;
+    ///     }
+    ///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+    /// }
+    /// assert_eq!(*events.borrow(), vec![
+    ///     Event::Key(KeyEvent::new(Key::Char('x'), Modifiers::NONE)),
+    ///     Event::Key(KeyEvent::new(Key::Up, Modifiers::NONE)),
+    ///     Event::Key(KeyEvent::new(Key::Escape, Modifiers::NONE)),
+    /// ]);
+    ///
+    /// // nothing is delivered once reading has stopped, including a pending ESC
+    /// events.borrow_mut().clear();
+    /// let log = events.clone();
+    /// tty.read_events(Duration::from_millis(10), move |tty, event| {
+    ///     log.borrow_mut().push(event.unwrap());
+    ///     if log.borrow().len() == 2 {
+    ///         tty.read_stop().unwrap();
+    ///     }
+    /// })
+    /// .unwrap();
+    /// type_keys(b"ab\x1b");
+    /// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+    /// assert_eq!(events.borrow().len(), 2);
+    ///
+    /// tty.set_mode(TtyMode::Normal).unwrap();
+    /// drop(tty);
+    /// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+    /// # }
+    /// #
+    /// # #[cfg(not(target_os = "linux"))]
+    /// # fn main() {}
+    /// ```
+    pub fn read_events<F>(&self, escape_timeout: Duration, cb: F) -> Result<()>
+    where
+        F: FnMut(&Tty, Result<Event>) + 'static,
+    {
+        let reader = Rc::new(EventReader {
+            decoder: RefCell::new(Decoder::new()),
+            timer: Timer::new(&self.get_loop())?,
+            timeout: escape_timeout,
+            cb: RefCell::new(Box::new(cb)),
+        });
+        self.read_start(ReusableAllocator::default(), move |tty, data| {
+            let _ = reader.timer.stop();
+            match data {
+                Ok(data) => {
+                    let events = reader.decoder.borrow_mut().decode(data);
+                    reader.deliver(tty, events);
+                    reader.arm(tty);
+                }
+                Err(err) => {
+                    let events = reader.decoder.borrow_mut().flush();
+                    reader.deliver(tty, events);
+                    (reader.cb.borrow_mut())(tty, Err(err));
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,531 @@
+use crate::error::{Result, UvError};
+use crate::event_loop::Loop;
+use crate::handle::{self, Callback};
+use crate::net::SockAddr;
+use crate::stream::allocator::{Allocator, ReadBuffer};
+use crate::stream::write_buf;
+use crate::{
+    sockaddr, uv_buf_t, uv_errno_t_UV_EINVAL, uv_handle_t, uv_is_active, uv_loop_t, uv_membership,
+    uv_udp_bind, uv_udp_connect, uv_udp_flags, uv_udp_flags_UV_UDP_MMSG_CHUNK,
+    uv_udp_flags_UV_UDP_MMSG_FREE, uv_udp_flags_UV_UDP_PARTIAL, uv_udp_get_send_queue_count,
+    uv_udp_get_send_queue_size, uv_udp_getpeername, uv_udp_getsockname, uv_udp_init,
+    uv_udp_init_ex, uv_udp_recv_start, uv_udp_recv_stop, uv_udp_send, uv_udp_send_t,
+    uv_udp_set_broadcast, uv_udp_set_membership, uv_udp_set_multicast_interface,
+    uv_udp_set_multicast_loop, uv_udp_set_multicast_ttl, uv_udp_set_source_membership,
+    uv_udp_set_ttl, uv_udp_t, uv_udp_try_send, uv_udp_try_send2, uv_udp_using_recvmmsg,
+};
+use std::any::Any;
+use std::ffi::CString;
+use std::mem::{self, ManuallyDrop};
+use std::net::{IpAddr, SocketAddr};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ptr;
+use std::slice;
+
+/// A datagram received by a Udp handle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Datagram<'a> {
+    /// The contents of the datagram.
+    pub data: &'a [u8],
+
+    /// The address of the sender.
+    pub addr: Option<SocketAddr>,
+
+    /// True if the datagram was too big for the receive buffer and was truncated.
+    pub partial: bool,
+}
+
+type AllocCallback = dyn FnMut(usize) -> (uv_buf_t, Box<dyn Any>);
+type RecvCallback = dyn FnMut(&Udp, Result<Datagram<'_>>);
+
+struct UdpState {
+    alloc: Callback<AllocCallback>,
+    recv: Callback<RecvCallback>,
+
+    /// The buffer that libuv is receiving into. With recvmmsg, several datagrams are received into
+    /// one buffer, so it is only released once libuv says that it's done with the whole thing.
+    pending: Option<Box<dyn Any>>,
+}
+
+fn ip_cstring(ip: &IpAddr) -> CString {
+    CString::new(ip.to_string()).expect("IPs don't contain NULs")
+}
+
+/// A safe wrapper around uv_udp_t.
+///
+/// Received datagrams are passed to the callback as a borrowed Datagram. When recvmmsg is enabled
+/// (see with_flags()), libuv receives several datagrams into one buffer from the Allocator; the
+/// buffer is released once libuv has delivered all of them, so there's never a need to handle
+/// the UV_UDP_MMSG_CHUNK and UV_UDP_MMSG_FREE flags.
+///
+/// # Example
+///
+/// ```
+/// # use libuv_sys2::event_loop::Loop;
+/// # use libuv_sys2::stream::allocator::ReusableAllocator;
+/// # use libuv_sys2::udp::Udp;
+/// # use libuv_sys2::{uv_run_mode_UV_RUN_DEFAULT, uv_run_mode_UV_RUN_ONCE};
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// #
+/// let r#loop = Loop::new().unwrap();
+/// let receiver = Udp::new(&r#loop).unwrap();
+/// receiver.bind(&"127.0.0.1:0".parse().unwrap(), 0).unwrap();
+/// let receiver_addr = receiver.sockname().unwrap();
+///
+/// let received = Rc::new(RefCell::new(Vec::new()));
+/// let log = received.clone();
+/// receiver
+///     .recv_start(ReusableAllocator::default(), move |_, datagram| {
+///         let datagram = datagram.unwrap();
+///         assert!(!datagram.partial);
+///         log.borrow_mut().push((datagram.data.to_vec(), datagram.addr.unwrap()));
+///     })
+///     .unwrap();
+///
+/// let sender = Udp::new(&r#loop).unwrap();
+/// sender.bind(&"127.0.0.1:0".parse().unwrap(), 0).unwrap();
+/// let sender_addr = sender.sockname().unwrap();
+/// sender
+///     .send(b"queued".to_vec(), Some(&receiver_addr), |_, result| result.unwrap())
+///     .unwrap();
+///
+/// // in connected mode, datagrams go to the connected address
+/// sender.connect(Some(&receiver_addr)).unwrap();
+/// assert_eq!(sender.peername().unwrap(), receiver_addr);
+/// assert_eq!(sender.try_send(b"connected", None).unwrap(), 9);
+/// sender.connect(None).unwrap();
+/// assert!(sender.peername().is_err());
+///
+/// for _ in 0..100 {
+///     if received.borrow().len() == 2 {
+///         break;
+///     }
+///     r#loop.run(uv_run_mode_UV_RUN_ONCE);
+/// }
+/// assert_eq!(
+///     *received.borrow(),
+///     [
+///         (b"queued".to_vec(), sender_addr),
+///         (b"connected".to_vec(), sender_addr)
+///     ]
+/// );
+///
+/// drop((receiver, sender));
+/// r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+/// ```
+pub struct Udp {
+    handle: *mut uv_udp_t,
+}
+
+impl Udp {
+    /// Create a new UDP handle. The socket itself isn't created until the handle is bound or
+    /// used to send.
+    pub fn new(r#loop: &Loop) -> Result<Udp> {
+        Udp::init(r#loop, |r#loop, handle| unsafe {
+            uvret!(uv_udp_init(r#loop, handle))
+        })
+    }
+
+    /// Create a new UDP handle with `flags`. The lower 8 bits are an address family (AF_INET,
+    /// AF_INET6 or AF_UNSPEC); unless it's AF_UNSPEC, the socket is created immediately.
+    /// uv_udp_flags_UV_UDP_RECVMMSG enables receiving several datagrams per system call, where
+    /// the platform supports it; in that case, the allocator passed to recv_start() should hand
+    /// out buffers that are a multiple of 64KiB.
+    pub fn with_flags(r#loop: &Loop, flags: u32) -> Result<Udp> {
+        Udp::init(r#loop, |r#loop, handle| unsafe {
+            uvret!(uv_udp_init_ex(r#loop, handle, flags as c_uint))
+        })
+    }
+
+    fn init<F>(r#loop: &Loop, init: F) -> Result<Udp>
+    where
+        F: FnOnce(*mut uv_loop_t, *mut uv_udp_t) -> Result<c_int>,
+    {
+        let state = UdpState {
+            alloc: Callback::empty(),
+            recv: Callback::empty(),
+            pending: None,
+        };
+        let handle = unsafe { handle::alloc(r#loop, state, init)? };
+        Ok(Udp { handle })
+    }
+
+    /// Bind the handle to an address. `flags` may include uv_udp_flags_UV_UDP_IPV6ONLY,
+    /// uv_udp_flags_UV_UDP_REUSEADDR and uv_udp_flags_UV_UDP_REUSEPORT.
+    pub fn bind(&self, addr: &SocketAddr, flags: uv_udp_flags) -> Result<()> {
+        let addr = SockAddr::new(addr)?;
+        unsafe { uvret!(uv_udp_bind(self.handle, addr.as_ptr(), flags as c_uint)) }?;
+        Ok(())
+    }
+
+    /// Associate the handle with a remote address, so that every datagram is sent to it and
+    /// datagrams from any other address are discarded. Passing None disconnects the handle.
+    pub fn connect(&self, addr: Option<&SocketAddr>) -> Result<()> {
+        let addr = addr.map(SockAddr::new).transpose()?;
+        let addr = addr.as_ref().map_or(ptr::null(), SockAddr::as_ptr);
+        unsafe { uvret!(uv_udp_connect(self.handle, addr)) }?;
+        Ok(())
+    }
+
+    /// The address that the handle is bound to.
+    pub fn sockname(&self) -> Result<SocketAddr> {
+        let mut addr = SockAddr::unspecified();
+        let mut len = addr.capacity();
+        unsafe { uvret!(uv_udp_getsockname(self.handle, addr.as_mut_ptr(), &mut len)) }?;
+        addr.to_socket_addr()
+    }
+
+    /// The address that the handle is connected to.
+    pub fn peername(&self) -> Result<SocketAddr> {
+        let mut addr = SockAddr::unspecified();
+        let mut len = addr.capacity();
+        unsafe { uvret!(uv_udp_getpeername(self.handle, addr.as_mut_ptr(), &mut len)) }?;
+        addr.to_socket_addr()
+    }
+
+    /// Send `data` as a single datagram to `addr`, which must be None if, and only if, the handle
+    /// is connected. Like Stream::write(), `data` may be anything that owns its bytes; it's
+    /// dropped once the send has finished, right after `cb` is called with the result.
+    ///
+    /// If the send can't be queued, an error is returned and `cb` is never called.
+    pub fn send<B, F>(&self, data: B, addr: Option<&SocketAddr>, cb: F) -> Result<()>
+    where
+        B: AsRef<[u8]> + 'static,
+        F: FnOnce(&Udp, Result<()>) + 'static,
+    {
+        self.send_vectored(vec![data], addr, cb)
+    }
+
+    /// Like send(), except the buffers in `bufs` are sent, in order, as a single datagram.
+    /// Returns an error (UV_EINVAL) if `bufs` is empty.
+    pub fn send_vectored<B, F>(&self, bufs: Vec<B>, addr: Option<&SocketAddr>, cb: F) -> Result<()>
+    where
+        B: AsRef<[u8]> + 'static,
+        F: FnOnce(&Udp, Result<()>) + 'static,
+    {
+        if bufs.is_empty() {
+            return Err(UvError::new("uv_udp_send", uv_errno_t_UV_EINVAL));
+        }
+        let addr = addr.map(SockAddr::new).transpose()?;
+        let raw_bufs: Vec<uv_buf_t> = bufs.iter().map(|buf| write_buf(buf.as_ref())).collect();
+        unsafe {
+            let req = Box::into_raw(Box::new(SendRequest {
+                req: mem::zeroed(),
+                handle: self.handle,
+                bufs,
+                cb,
+            }));
+            let ret = uvret!(uv_udp_send(
+                req as *mut uv_udp_send_t,
+                self.handle,
+                raw_bufs.as_ptr(),
+                raw_bufs.len() as c_uint,
+                addr.as_ref().map_or(ptr::null(), SockAddr::as_ptr),
+                Some(send_cb::<B, F>)
+            ));
+            if let Err(err) = ret {
+                mem::drop(Box::from_raw(req));
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send `data` as a single datagram immediately, without queueing it. Returns the number of
+    /// bytes sent, or an error (UV_EAGAIN) if the datagram can't be sent right away.
+    pub fn try_send(&self, data: &[u8], addr: Option<&SocketAddr>) -> Result<usize> {
+        let addr = addr.map(SockAddr::new).transpose()?;
+        let buf = write_buf(data);
+        let sent = unsafe {
+            uvret!(uv_udp_try_send(
+                self.handle,
+                &buf,
+                1,
+                addr.as_ref().map_or(ptr::null(), SockAddr::as_ptr)
+            ))
+        }?;
+        Ok(sent as usize)
+    }
+
+    /// Send several datagrams immediately, with a single system call where the platform supports
+    /// it. Returns the number of datagrams that were sent, which may be fewer than were passed
+    /// in, or an error (UV_EAGAIN) if none could be sent right away.
+    pub fn try_send_many(&self, datagrams: &[(&[u8], SocketAddr)]) -> Result<usize> {
+        let addrs = datagrams
+            .iter()
+            .map(|(_, addr)| SockAddr::new(addr))
+            .collect::<Result<Vec<_>>>()?;
+        let mut bufs: Vec<uv_buf_t> = datagrams.iter().map(|(data, _)| write_buf(data)).collect();
+
+        // uv_udp_try_send2 takes an array of buffers per datagram; each of these is one buffer.
+        let mut buf_ptrs: Vec<*mut uv_buf_t> = bufs.iter_mut().map(|buf| buf as *mut _).collect();
+        let mut nbufs: Vec<c_uint> = vec![1; datagrams.len()];
+        let mut addr_ptrs: Vec<*mut sockaddr> = addrs
+            .iter()
+            .map(|addr| addr.as_ptr() as *mut sockaddr)
+            .collect();
+        let sent = unsafe {
+            uvret!(uv_udp_try_send2(
+                self.handle,
+                datagrams.len() as c_uint,
+                buf_ptrs.as_mut_ptr(),
+                nbufs.as_mut_ptr(),
+                addr_ptrs.as_mut_ptr(),
+                0
+            ))
+        }?;
+        Ok(sent as usize)
+    }
+
+    /// Start receiving datagrams, into buffers from `allocator`. `cb` is called with each
+    /// datagram. If the handle is already receiving, the allocator and callback are replaced.
+    pub fn recv_start<A, F>(&self, mut allocator: A, cb: F) -> Result<()>
+    where
+        A: Allocator,
+        F: FnMut(&Udp, Result<Datagram<'_>>) + 'static,
+    {
+        unsafe {
+            let state = handle::state::<_, UdpState>(self.handle);
+            (*state).alloc.set(Box::new(move |suggested_size| {
+                // The buffer is boxed before libuv is given its memory, so that its address is
+                // stable while it's stored in the state.
+                let mut buffer = Box::new(allocator.alloc(suggested_size));
+                let memory = buffer.capacity_mut();
+                let buf = uv_buf_t {
+                    base: memory.as_mut_ptr() as *mut c_char,
+                    len: memory.len() as _,
+                };
+                (buf, buffer as Box<dyn Any>)
+            }));
+            (*state).recv.set(Box::new(cb));
+
+            // uv_udp_recv_start returns UV_EALREADY if the handle is already receiving, but the
+            // new callbacks are already in place.
+            uv_udp_recv_stop(self.handle);
+            uvret!(uv_udp_recv_start(
+                self.handle,
+                Some(alloc_cb),
+                Some(recv_cb)
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Stop receiving datagrams.
+    pub fn recv_stop(&self) -> Result<()> {
+        unsafe { uvret!(uv_udp_recv_stop(self.handle)) }?;
+        Ok(())
+    }
+
+    /// Returns true if the handle receives several datagrams per system call.
+    pub fn using_recvmmsg(&self) -> bool {
+        unsafe { uv_udp_using_recvmmsg(self.handle) != 0 }
+    }
+
+    /// Join or leave (uv_membership_UV_JOIN_GROUP or uv_membership_UV_LEAVE_GROUP) a multicast
+    /// group, on `interface` or on the default interface.
+    pub fn set_membership(
+        &self,
+        multicast: &IpAddr,
+        interface: Option<&IpAddr>,
+        membership: uv_membership,
+    ) -> Result<()> {
+        let multicast = ip_cstring(multicast);
+        let interface = interface.map(ip_cstring);
+        unsafe {
+            uvret!(uv_udp_set_membership(
+                self.handle,
+                multicast.as_ptr(),
+                interface.as_ref().map_or(ptr::null(), |ip| ip.as_ptr()),
+                membership
+            ))
+        }?;
+        Ok(())
+    }
+
+    /// Join or leave a source-specific multicast group: only datagrams from `source` are
+    /// received.
+    pub fn set_source_membership(
+        &self,
+        multicast: &IpAddr,
+        interface: Option<&IpAddr>,
+        source: &IpAddr,
+        membership: uv_membership,
+    ) -> Result<()> {
+        let multicast = ip_cstring(multicast);
+        let interface = interface.map(ip_cstring);
+        let source = ip_cstring(source);
+        unsafe {
+            uvret!(uv_udp_set_source_membership(
+                self.handle,
+                multicast.as_ptr(),
+                interface.as_ref().map_or(ptr::null(), |ip| ip.as_ptr()),
+                source.as_ptr(),
+                membership
+            ))
+        }?;
+        Ok(())
+    }
+
+    /// Set whether multicast datagrams are looped back to the local sockets.
+    pub fn set_multicast_loop(&self, on: bool) -> Result<()> {
+        unsafe { uvret!(uv_udp_set_multicast_loop(self.handle, on as c_int)) }?;
+        Ok(())
+    }
+
+    /// Set the time-to-live of multicast datagrams, from 1 to 255.
+    pub fn set_multicast_ttl(&self, ttl: i32) -> Result<()> {
+        unsafe { uvret!(uv_udp_set_multicast_ttl(self.handle, ttl as c_int)) }?;
+        Ok(())
+    }
+
+    /// Set the interface that multicast datagrams are sent from, or reset it to the default.
+    pub fn set_multicast_interface(&self, interface: Option<&IpAddr>) -> Result<()> {
+        let interface = interface.map(ip_cstring);
+        unsafe {
+            uvret!(uv_udp_set_multicast_interface(
+                self.handle,
+                interface.as_ref().map_or(ptr::null(), |ip| ip.as_ptr())
+            ))
+        }?;
+        Ok(())
+    }
+
+    /// Set whether datagrams may be sent to broadcast addresses.
+    pub fn set_broadcast(&self, on: bool) -> Result<()> {
+        unsafe { uvret!(uv_udp_set_broadcast(self.handle, on as c_int)) }?;
+        Ok(())
+    }
+
+    /// Set the time-to-live of datagrams, from 1 to 255.
+    pub fn set_ttl(&self, ttl: i32) -> Result<()> {
+        unsafe { uvret!(uv_udp_set_ttl(self.handle, ttl as c_int)) }?;
+        Ok(())
+    }
+
+    /// The number of bytes that are queued for sending.
+    pub fn send_queue_size(&self) -> usize {
+        unsafe { uv_udp_get_send_queue_size(self.handle) }
+    }
+
+    /// The number of sends that are queued.
+    pub fn send_queue_count(&self) -> usize {
+        unsafe { uv_udp_get_send_queue_count(self.handle) }
+    }
+
+    /// Returns true if the handle is receiving.
+    pub fn is_active(&self) -> bool {
+        unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+    }
+
+    /// The loop that the handle belongs to.
+    pub fn get_loop(&self) -> Loop {
+        unsafe { handle::get_loop::<_, UdpState>(self.handle) }
+    }
+
+    /// The raw uv_udp_t.
+    pub fn as_ptr(&self) -> *mut uv_udp_t {
+        self.handle
+    }
+}
+
+impl Drop for Udp {
+    fn drop(&mut self) {
+        unsafe { handle::close::<_, UdpState>(self.handle) }
+    }
+}
+
+/// A send that is in flight. libuv requires both the request and the memory being sent to stay
+/// put until the send callback is called, so both live on the heap until then.
+#[repr(C)]
+struct SendRequest<B, F> {
+    /// This must be the first field so that the uv_udp_send_t pointer that libuv passes to the
+    /// callback can be cast back to the SendRequest.
+    req: uv_udp_send_t,
+    handle: *mut uv_udp_t,
+    bufs: Vec<B>,
+    cb: F,
+}
+
+unsafe extern "C" fn send_cb<B, F>(req: *mut uv_udp_send_t, status: c_int)
+where
+    F: FnOnce(&Udp, Result<()>),
+{
+    let SendRequest {
+        handle, bufs, cb, ..
+    } = *Box::from_raw(req as *mut SendRequest<B, F>);
+    let result = match status {
+        0 => Ok(()),
+        code => Err(UvError::new("uv_udp_send", code)),
+    };
+
+    // The Udp passed to the callback doesn't own the handle, so it must not be dropped.
+    cb(&ManuallyDrop::new(Udp { handle }), result);
+    mem::drop(bufs);
+}
+
+unsafe extern "C" fn alloc_cb(handle: *mut uv_handle_t, suggested_size: usize, buf: *mut uv_buf_t) {
+    let state = handle::state::<_, UdpState>(handle);
+    match handle::invoke(ptr::addr_of_mut!((*state).alloc), |alloc| {
+        alloc(suggested_size)
+    }) {
+        Some((raw, buffer)) => {
+            *buf = raw;
+            (*state).pending = Some(buffer);
+        }
+        None => {
+            *buf = uv_buf_t {
+                base: ptr::null_mut(),
+                len: 0,
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn recv_cb(
+    handle: *mut uv_udp_t,
+    nread: isize,
+    buf: *const uv_buf_t,
+    addr: *const sockaddr,
+    flags: c_uint,
+) {
+    let state = handle::state::<_, UdpState>(handle);
+    let flags = flags as uv_udp_flags;
+
+    // With recvmmsg, libuv calls back once per datagram with UV_UDP_MMSG_CHUNK, each pointing into
+    // the buffer, and then once more with UV_UDP_MMSG_FREE. Otherwise, it calls back once per
+    // buffer. Either way, a callback without UV_UDP_MMSG_CHUNK means libuv is done with the
+    // buffer.
+    //
+    // A callback with no data and no address means that there was nothing to read; there's
+    // nothing to report in that case, either.
+    let empty = nread == 0 && addr.is_null();
+    if flags & uv_udp_flags_UV_UDP_MMSG_FREE == 0 && !empty {
+        let result = if nread < 0 {
+            Err(UvError::new("uv_udp_recv_start", nread as c_int))
+        } else {
+            let data = match nread {
+                0 => &[][..],
+                _ => slice::from_raw_parts((*buf).base as *const u8, nread as usize),
+            };
+            let addr = if addr.is_null() {
+                None
+            } else {
+                SockAddr::from_raw(addr).to_socket_addr().ok()
+            };
+            Ok(Datagram {
+                data,
+                addr,
+                partial: flags & uv_udp_flags_UV_UDP_PARTIAL != 0,
+            })
+        };
+
+        // The Udp passed to the callback doesn't own the handle, so it must not be dropped.
+        let udp = ManuallyDrop::new(Udp { handle });
+        handle::invoke(ptr::addr_of_mut!((*state).recv), |cb| cb(&udp, result));
+    }
+    if flags & uv_udp_flags_UV_UDP_MMSG_CHUNK == 0 {
+        (*state).pending = None;
+    }
+}
@@ -0,0 +1,188 @@
+//! Safe wrappers for the loop watchers: uv_idle_t, uv_prepare_t, and uv_check_t.
+//!
+//! Each iteration of the loop runs through its phases in a fixed order: due timers, pending
+//! callbacks, idle handles, prepare handles, polling for i/o (which runs i/o callbacks), check
+//! handles, close callbacks and, finally, due timers again. So, prepare callbacks always run
+//! right before the loop blocks for i/o, and check callbacks always run right after.
+//!
+//! # Example
+//!
+//! ```
+//! # use libuv_sys2::event_loop::Loop;
+//! # use libuv_sys2::watcher::{Check, Idle, Prepare};
+//! # use libuv_sys2::{
+//! #     uv_async_init, uv_async_send, uv_async_t, uv_close, uv_handle_t, uv_run_mode_UV_RUN_DEFAULT,
+//! #     uv_run_mode_UV_RUN_NOWAIT,
+//! # };
+//! # use std::cell::RefCell;
+//! # use std::mem;
+//! #
+//! thread_local!(static LOG: RefCell<Vec<&'static str>> = RefCell::new(Vec::new()));
+//!
+//! fn log(phase: &'static str) {
+//!     LOG.with(|log| log.borrow_mut().push(phase));
+//! }
+//!
+//! // a raw async handle gives us an i/o callback that runs while the loop polls
+//! unsafe extern "C" fn async_cb(_handle: *mut uv_async_t) {
+//!     log("poll");
+//! }
+//!
+//! let r#loop = Loop::new().unwrap();
+//! let idle = Idle::new(&r#loop).unwrap();
+//! let prepare = Prepare::new(&r#loop).unwrap();
+//! let check = Check::new(&r#loop).unwrap();
+//! check.start(|_| log("check")).unwrap();
+//! prepare.start(|_| log("prepare")).unwrap();
+//! idle.start(|_| log("idle")).unwrap();
+//!
+//! let mut r#async: Box<uv_async_t> = Box::new(unsafe { mem::zeroed() });
+//! unsafe {
+//!     uv_async_init(r#loop.as_ptr(), &mut *r#async, Some(async_cb));
+//!     uv_async_send(&mut *r#async);
+//! }
+//!
+//! r#loop.run(uv_run_mode_UV_RUN_NOWAIT);
+//! r#loop.run(uv_run_mode_UV_RUN_NOWAIT);
+//! LOG.with(|log| {
+//!     assert_eq!(
+//!         *log.borrow(),
+//!         ["idle", "prepare", "poll", "check", "idle", "prepare", "check"]
+//!     )
+//! });
+//!
+//! unsafe { uv_close(&mut *r#async as *mut uv_async_t as *mut uv_handle_t, None) };
+//! mem::drop((idle, prepare, check));
+//! r#loop.run(uv_run_mode_UV_RUN_DEFAULT);
+//! ```
+
+use crate::error::Result;
+use crate::event_loop::Loop;
+use crate::handle::{self, Callback};
+use crate::{
+    uv_check_init, uv_check_start, uv_check_stop, uv_check_t, uv_handle_t, uv_idle_init,
+    uv_idle_start, uv_idle_stop, uv_idle_t, uv_is_active, uv_prepare_init, uv_prepare_start,
+    uv_prepare_stop, uv_prepare_t,
+};
+use std::mem::ManuallyDrop;
+use std::ptr;
+
+/// The three watchers only differ in which libuv functions they call, so they're all generated by
+/// this macro.
+macro_rules! watcher {
+    (
+        $(#[$attr:meta])*
+        $name:ident, $state:ident, $raw:ident, $init:ident, $start:ident, $stop:ident, $cb:ident
+    ) => {
+        struct $state {
+            cb: Callback<dyn FnMut(&$name)>,
+        }
+
+        $(#[$attr])*
+        pub struct $name {
+            handle: *mut $raw,
+        }
+
+        impl $name {
+            #[doc = concat!("Create a new ", stringify!($name), " handle.")]
+            pub fn new(r#loop: &Loop) -> Result<$name> {
+                let state = $state {
+                    cb: Callback::empty(),
+                };
+                let handle = unsafe {
+                    handle::alloc(r#loop, state, |r#loop, handle| uvret!($init(r#loop, handle)))?
+                };
+                Ok($name { handle })
+            }
+
+            /// Start the handle; `cb` will be called once per loop iteration until the handle is
+            /// stopped. If the handle is already started, the callback is replaced.
+            pub fn start<F>(&self, cb: F) -> Result<()>
+            where
+                F: FnMut(&$name) + 'static,
+            {
+                unsafe {
+                    let state = handle::state::<_, $state>(self.handle);
+                    (*state).cb.set(Box::new(cb));
+                    uvret!($start(self.handle, Some($cb)))?;
+                }
+                Ok(())
+            }
+
+            /// Stop the handle; the callback will no longer be called.
+            pub fn stop(&self) -> Result<()> {
+                unsafe { uvret!($stop(self.handle)) }?;
+                Ok(())
+            }
+
+            /// Returns true if the handle is started.
+            pub fn is_active(&self) -> bool {
+                unsafe { uv_is_active(self.handle as *const uv_handle_t) != 0 }
+            }
+
+            /// The loop that the handle belongs to.
+            pub fn get_loop(&self) -> Loop {
+                unsafe { handle::get_loop::<_, $state>(self.handle) }
+            }
+
+            #[doc = concat!("The raw ", stringify!($raw), ".")]
+            pub fn as_ptr(&self) -> *mut $raw {
+                self.handle
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                unsafe { handle::close::<_, $state>(self.handle) }
+            }
+        }
+
+        unsafe extern "C" fn $cb(handle: *mut $raw) {
+            // The wrapper passed to the callback doesn't own the handle, so it must not be
+            // dropped.
+            let watcher = ManuallyDrop::new($name { handle });
+            let state = handle::state::<_, $state>(handle);
+            handle::invoke(ptr::addr_of_mut!((*state).cb), |cb| cb(&watcher));
+        }
+    };
+}
+
+watcher!(
+    /// A safe wrapper around uv_idle_t. Idle handles run once per loop iteration, before prepare
+    /// handles.
+    ///
+    /// Despite the name, idle handles run on every iteration, not only when the loop is idle.
+    /// While an idle handle is active, the loop will not block for i/o: it polls with a zero
+    /// timeout instead.
+    Idle,
+    IdleState,
+    uv_idle_t,
+    uv_idle_init,
+    uv_idle_start,
+    uv_idle_stop,
+    idle_cb
+);
+
+watcher!(
+    /// A safe wrapper around uv_prepare_t. Prepare handles run once per loop iteration, right
+    /// before the loop polls for i/o.
+    Prepare,
+    PrepareState,
+    uv_prepare_t,
+    uv_prepare_init,
+    uv_prepare_start,
+    uv_prepare_stop,
+    prepare_cb
+);
+
+watcher!(
+    /// A safe wrapper around uv_check_t. Check handles run once per loop iteration, right after
+    /// the loop polls for i/o.
+    Check,
+    CheckState,
+    uv_check_t,
+    uv_check_init,
+    uv_check_start,
+    uv_check_stop,
+    check_cb
+);